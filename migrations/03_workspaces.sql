@@ -0,0 +1,31 @@
+CREATE TABLE IF NOT EXISTS workspaces
+(
+    id         VARCHAR(64)  NOT NULL PRIMARY KEY,
+    name       VARCHAR(255) NOT NULL,
+    created_by VARCHAR(64)  NOT NULL,
+    created_at INTEGER      NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS workspace_members
+(
+    workspace_id VARCHAR(64) NOT NULL,
+    user_id      VARCHAR(64) NOT NULL,
+    role         VARCHAR(16) NOT NULL,
+    created_at   INTEGER     NOT NULL,
+    CONSTRAINT workspace_members_pk PRIMARY KEY (workspace_id, user_id)
+);
+
+CREATE INDEX workspace_members_user_id_index ON workspace_members (user_id);
+
+-- every existing user gets a personal workspace holding the materials they created
+INSERT INTO workspaces (id, name, created_by, created_at)
+SELECT id, name, id, created_at FROM users;
+
+INSERT INTO workspace_members (workspace_id, user_id, role, created_at)
+SELECT id, id, 'owner', created_at FROM users;
+
+ALTER TABLE materials ADD COLUMN workspace_id VARCHAR(64) NOT NULL DEFAULT '';
+
+UPDATE materials SET workspace_id = creator;
+
+CREATE INDEX materials_workspace_id_index ON materials (workspace_id, created_at);
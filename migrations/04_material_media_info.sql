@@ -0,0 +1,5 @@
+ALTER TABLE materials ADD COLUMN size INTEGER;
+
+ALTER TABLE materials ADD COLUMN duration REAL;
+
+CREATE INDEX materials_workspace_id_name_index ON materials (workspace_id, name);
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS saved_searches
+(
+    id             VARCHAR(36)  NOT NULL PRIMARY KEY,
+    workspace_id   VARCHAR(64)  NOT NULL,
+    owner          VARCHAR(64)  NOT NULL,
+    name           VARCHAR(255) NOT NULL,
+    condition      TEXT         NOT NULL,
+    smart          INTEGER      NOT NULL DEFAULT 0,
+    last_viewed_at INTEGER,
+    created_at     INTEGER      NOT NULL
+);
+
+CREATE INDEX saved_searches_owner_index ON saved_searches (workspace_id, owner);
@@ -0,0 +1,18 @@
+ALTER TABLE users ADD COLUMN username VARCHAR(255);
+ALTER TABLE users ADD COLUMN password_hash VARCHAR(255);
+ALTER TABLE users ADD COLUMN failed_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN locked_until INTEGER;
+
+CREATE UNIQUE INDEX users_username_uindex ON users (username);
+
+-- single use invitation and password reset tokens, only the sha256 of a token is stored
+CREATE TABLE IF NOT EXISTS account_tokens
+(
+    token_hash VARCHAR(64) NOT NULL PRIMARY KEY,
+    kind       VARCHAR(16) NOT NULL,
+    user_id    VARCHAR(64),
+    created_by VARCHAR(64) NOT NULL,
+    expires_at INTEGER     NOT NULL,
+    used_at    INTEGER,
+    created_at INTEGER     NOT NULL
+);
@@ -0,0 +1,18 @@
+-- a login, kept alive by rotating refresh tokens; access tokens carry its id as `jti`
+CREATE TABLE IF NOT EXISTS sessions
+(
+    id                    VARCHAR(36)  NOT NULL PRIMARY KEY,
+    user_id               VARCHAR(64)  NOT NULL,
+    workspace_id          VARCHAR(64)  NOT NULL,
+    source                VARCHAR(255),
+    refresh_hash          VARCHAR(64)  NOT NULL,
+    previous_refresh_hash VARCHAR(64),
+    created_at            INTEGER      NOT NULL,
+    last_used_at          INTEGER      NOT NULL,
+    expires_at            INTEGER      NOT NULL,
+    revoked_at            INTEGER
+);
+
+CREATE INDEX sessions_user_id_index ON sessions (user_id);
+CREATE UNIQUE INDEX sessions_refresh_hash_uindex ON sessions (refresh_hash);
+CREATE INDEX sessions_previous_refresh_hash_index ON sessions (previous_refresh_hash);
@@ -0,0 +1,18 @@
+-- personal api keys, only the sha256 of a key is stored
+CREATE TABLE IF NOT EXISTS api_keys
+(
+    id           VARCHAR(36)  NOT NULL PRIMARY KEY,
+    user_id      VARCHAR(64)  NOT NULL,
+    workspace_id VARCHAR(64)  NOT NULL,
+    name         VARCHAR(255) NOT NULL,
+    prefix       VARCHAR(16)  NOT NULL,
+    key_hash     VARCHAR(64)  NOT NULL,
+    scopes       VARCHAR(255) NOT NULL,
+    expires_at   INTEGER,
+    last_used_at INTEGER,
+    created_at   INTEGER      NOT NULL,
+    revoked_at   INTEGER
+);
+
+CREATE UNIQUE INDEX api_keys_key_hash_uindex ON api_keys (key_hash);
+CREATE INDEX api_keys_user_id_index ON api_keys (user_id);
@@ -0,0 +1,29 @@
+-- append only, rows are never updated or deleted by the application
+CREATE TABLE IF NOT EXISTS audit_log
+(
+    id         INTEGER      NOT NULL PRIMARY KEY AUTOINCREMENT,
+    actor_id   VARCHAR(64),
+    actor_name VARCHAR(255),
+    action     VARCHAR(64)  NOT NULL,
+    target     VARCHAR(255) NOT NULL,
+    ip         VARCHAR(64),
+    user_agent VARCHAR(512),
+    details    TEXT         NOT NULL,
+    created_at INTEGER      NOT NULL
+);
+
+CREATE INDEX audit_log_created_at_index ON audit_log (created_at);
+CREATE INDEX audit_log_actor_id_index ON audit_log (actor_id);
+CREATE INDEX audit_log_action_index ON audit_log (action);
+
+CREATE TRIGGER audit_log_no_update
+    BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append only');
+END;
+
+CREATE TRIGGER audit_log_no_delete
+    BEFORE DELETE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append only');
+END;
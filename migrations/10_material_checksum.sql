@@ -0,0 +1,4 @@
+-- sha256 of the raw file, used to skip duplicates on import. null for materials stored before.
+ALTER TABLE materials ADD COLUMN checksum VARCHAR(64);
+
+CREATE INDEX materials_workspace_id_checksum_index ON materials (workspace_id, checksum);
@@ -0,0 +1,34 @@
+-- outgoing webhooks of a workspace, `events` is a comma separated list of event types
+CREATE TABLE IF NOT EXISTS webhooks
+(
+    id           VARCHAR(36)   NOT NULL PRIMARY KEY,
+    workspace_id VARCHAR(64)   NOT NULL,
+    url          VARCHAR(2048) NOT NULL,
+    secret       VARCHAR(255)  NOT NULL,
+    events       VARCHAR(255)  NOT NULL,
+    created_by   VARCHAR(64)   NOT NULL,
+    created_at   INTEGER       NOT NULL,
+    deleted_at   INTEGER
+);
+
+CREATE INDEX webhooks_workspace_id_index ON webhooks (workspace_id);
+
+-- one row per event and webhook, also the queue of the dispatcher: pending rows are sent once
+-- `next_attempt_at` has passed
+CREATE TABLE IF NOT EXISTS webhook_deliveries
+(
+    id              VARCHAR(36) NOT NULL PRIMARY KEY,
+    webhook_id      VARCHAR(36) NOT NULL,
+    event           VARCHAR(64) NOT NULL,
+    payload         TEXT        NOT NULL,
+    status          VARCHAR(16) NOT NULL,
+    attempts        INTEGER     NOT NULL DEFAULT 0,
+    next_attempt_at INTEGER     NOT NULL,
+    response_status INTEGER,
+    error           TEXT,
+    created_at      INTEGER     NOT NULL,
+    delivered_at    INTEGER
+);
+
+CREATE INDEX webhook_deliveries_webhook_id_index ON webhook_deliveries (webhook_id, created_at);
+CREATE INDEX webhook_deliveries_due_index ON webhook_deliveries (status, next_attempt_at);
@@ -0,0 +1,16 @@
+-- materials derived from another one, like clips. rows outlive their source.
+CREATE TABLE IF NOT EXISTS material_sources
+(
+    material_id   VARCHAR(36) NOT NULL PRIMARY KEY,
+    source_id     VARCHAR(36) NOT NULL,
+    -- seconds into the source
+    start_seconds REAL        NOT NULL,
+    end_seconds   REAL        NOT NULL,
+    crop_x        INTEGER,
+    crop_y        INTEGER,
+    crop_width    INTEGER,
+    crop_height   INTEGER,
+    created_at    INTEGER     NOT NULL
+);
+
+CREATE INDEX material_sources_source_id_index ON material_sources (source_id);
@@ -0,0 +1,3 @@
+CREATE INDEX materials_workspace_id_size_index ON materials (workspace_id, size);
+
+CREATE INDEX materials_workspace_id_duration_index ON materials (workspace_id, duration);
@@ -0,0 +1,6 @@
+-- process processing a video, see `material::instance`. rows outlive their processing.
+CREATE TABLE IF NOT EXISTS material_processing
+(
+    material_id VARCHAR(36) NOT NULL PRIMARY KEY,
+    instance    VARCHAR(36) NOT NULL
+);
@@ -0,0 +1,210 @@
+use crate::{
+    audit::AuditAction,
+    auth::jwt::Claims,
+    common::{Page, PageResult, Result},
+    db::Db,
+    util::poem::ClientInfo,
+};
+use chrono::{NaiveDateTime, Utc};
+use ioc::Bean;
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{sqlite::SqliteArguments, Arguments, SqlitePool};
+use tracing::warn;
+
+/// An action about to be recorded.
+pub(crate) struct AuditEntry {
+    actor_id: Option<String>,
+    actor_name: Option<String>,
+    action: AuditAction,
+    target: String,
+    details: Value,
+}
+
+impl AuditEntry {
+    pub(crate) fn by(claims: &Claims, action: AuditAction, target: impl Into<String>) -> Self {
+        Self {
+            actor_id: Some(claims.id.clone()),
+            actor_name: Some(claims.name.clone()),
+            action,
+            target: target.into(),
+            details: Value::Null,
+        }
+    }
+
+    /// An action without an authenticated actor, e.g. a failed login.
+    pub(crate) fn anonymous(action: AuditAction, target: impl Into<String>) -> Self {
+        Self {
+            actor_id: None,
+            actor_name: None,
+            action,
+            target: target.into(),
+            details: Value::Null,
+        }
+    }
+
+    pub(crate) fn actor(mut self, id: impl Into<String>, name: impl Into<String>) -> Self {
+        self.actor_id = Some(id.into());
+        self.actor_name = Some(name.into());
+        self
+    }
+
+    pub(crate) fn details(mut self, details: Value) -> Self {
+        self.details = details;
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Object)]
+pub(crate) struct AuditRecord {
+    id: i64,
+    actor_id: Option<String>,
+    actor_name: Option<String>,
+    action: String,
+    target: String,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    details: Value,
+    created_at: NaiveDateTime,
+}
+
+#[derive(sqlx::FromRow)]
+struct AuditRow {
+    id: i64,
+    actor_id: Option<String>,
+    actor_name: Option<String>,
+    action: String,
+    target: String,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    details: String,
+    created_at: NaiveDateTime,
+}
+
+impl From<AuditRow> for AuditRecord {
+    fn from(row: AuditRow) -> Self {
+        Self {
+            id: row.id,
+            actor_id: row.actor_id,
+            actor_name: row.actor_name,
+            action: row.action,
+            target: row.target,
+            ip: row.ip,
+            user_agent: row.user_agent,
+            details: serde_json::from_str(&row.details).unwrap_or(Value::String(row.details)),
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct AuditFilter {
+    pub(crate) actor_id: Option<String>,
+    pub(crate) action: Option<AuditAction>,
+    pub(crate) target: Option<String>,
+    pub(crate) from: Option<NaiveDateTime>,
+    pub(crate) to: Option<NaiveDateTime>,
+}
+
+impl AuditFilter {
+    fn sql(&self, args: &mut SqliteArguments<'_>) -> Result<String> {
+        let mut sql = String::from(" WHERE 1 = 1");
+        if let Some(actor_id) = &self.actor_id {
+            sql.push_str(" AND actor_id = ?");
+            args.add(actor_id.clone())?;
+        }
+        if let Some(action) = &self.action {
+            sql.push_str(" AND action = ?");
+            args.add(action.value())?;
+        }
+        if let Some(target) = &self.target {
+            sql.push_str(" AND target = ?");
+            args.add(target.clone())?;
+        }
+        if let Some(from) = self.from {
+            sql.push_str(" AND created_at >= ?");
+            args.add(from)?;
+        }
+        if let Some(to) = self.to {
+            sql.push_str(" AND created_at < ?");
+            args.add(to)?;
+        }
+        Ok(sql)
+    }
+}
+
+#[derive(Bean)]
+pub(crate) struct AuditService {
+    #[inject(bean = Db)]
+    db: &'static SqlitePool,
+}
+
+impl AuditService {
+    /// Appends the entry. The action it describes has already happened, so a failure to record
+    /// it is logged instead of failing the request.
+    pub(crate) async fn record(&self, entry: AuditEntry, client: &ClientInfo) {
+        if let Err(e) = self.insert(&entry, client).await {
+            warn!("audit {} on {} failed: {e}", entry.action.value(), entry.target);
+        }
+    }
+
+    async fn insert(&self, entry: &AuditEntry, client: &ClientInfo) -> Result<()> {
+        let action = entry.action.value();
+        let details = entry.details.to_string();
+        let now = Utc::now().naive_utc();
+
+        sqlx::query!(
+            r#"
+            INSERT INTO audit_log (actor_id, actor_name, action, target, ip, user_agent, details, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+            entry.actor_id,
+            entry.actor_name,
+            action,
+            entry.target,
+            client.ip,
+            client.user_agent,
+            details,
+            now
+        )
+            .execute(self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Newest first.
+    pub(crate) async fn search(
+        &self,
+        filter: &AuditFilter,
+        page: &Page,
+    ) -> Result<PageResult<AuditRecord>> {
+        let mut args = SqliteArguments::default();
+        let sql_where = filter.sql(&mut args)?;
+        let total: i64 =
+            sqlx::query_scalar_with(&format!("SELECT COUNT(1) FROM audit_log{sql_where}"), args)
+                .fetch_one(self.db)
+                .await?;
+
+        let mut args = SqliteArguments::default();
+        let sql_where = filter.sql(&mut args)?;
+        args.add(page.limit())?;
+        args.add(page.offset())?;
+        let rows: Vec<AuditRow> = sqlx::query_as_with(
+            &format!(
+                r#"
+                SELECT id, actor_id, actor_name, action, target, ip, user_agent, details, created_at
+                FROM audit_log{sql_where}
+                ORDER BY id DESC
+                LIMIT ? OFFSET ?
+                "#
+            ),
+            args,
+        )
+            .fetch_all(self.db)
+            .await?;
+
+        let records = rows.into_iter().map(Into::into).collect();
+        Ok(PageResult::new(page, total as u64, records))
+    }
+}
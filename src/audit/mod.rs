@@ -0,0 +1,53 @@
+use poem_openapi::Enum;
+use serde::{Deserialize, Serialize};
+
+pub mod biz;
+pub mod mvc;
+
+/// Actions recorded in the audit log.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Enum)]
+pub enum AuditAction {
+    #[oai(rename = "auth.login")]
+    #[serde(rename = "auth.login")]
+    Login,
+    #[oai(rename = "auth.login_failed")]
+    #[serde(rename = "auth.login_failed")]
+    LoginFailed,
+    #[oai(rename = "material.update")]
+    #[serde(rename = "material.update")]
+    MaterialUpdate,
+    #[oai(rename = "material.cancel")]
+    #[serde(rename = "material.cancel")]
+    MaterialCancel,
+    #[oai(rename = "material.delete")]
+    #[serde(rename = "material.delete")]
+    MaterialDelete,
+    #[oai(rename = "material.batch_delete")]
+    #[serde(rename = "material.batch_delete")]
+    MaterialBatchDelete,
+    #[oai(rename = "webhook.create")]
+    #[serde(rename = "webhook.create")]
+    WebhookCreate,
+    #[oai(rename = "webhook.delete")]
+    #[serde(rename = "webhook.delete")]
+    WebhookDelete,
+    #[oai(rename = "log.set_level")]
+    #[serde(rename = "log.set_level")]
+    LogLevelChange,
+}
+
+impl AuditAction {
+    pub(crate) fn value(&self) -> &'static str {
+        match self {
+            AuditAction::Login => "auth.login",
+            AuditAction::LoginFailed => "auth.login_failed",
+            AuditAction::MaterialUpdate => "material.update",
+            AuditAction::MaterialCancel => "material.cancel",
+            AuditAction::MaterialDelete => "material.delete",
+            AuditAction::MaterialBatchDelete => "material.batch_delete",
+            AuditAction::WebhookCreate => "webhook.create",
+            AuditAction::WebhookDelete => "webhook.delete",
+            AuditAction::LogLevelChange => "log.set_level",
+        }
+    }
+}
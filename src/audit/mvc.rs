@@ -0,0 +1,58 @@
+use crate::{
+    audit::{
+        biz::{AuditFilter, AuditRecord, AuditService},
+        AuditAction,
+    },
+    auth::{account::AccountService, apikey::JwtAuth},
+    common::{AppError, Page, PageResult, Response, Result},
+    metrics::metered,
+};
+use chrono::NaiveDateTime;
+use ioc::{mvc, Bean, OpenApi};
+use poem_openapi::param::Query;
+
+#[derive(Bean)]
+pub(crate) struct AuditMvc {
+    #[inject(bean)]
+    audit: &'static AuditService,
+    #[inject(bean)]
+    accounts: &'static AccountService,
+}
+
+#[mvc]
+#[OpenApi(prefix_path = "/api/v1")]
+impl AuditMvc {
+    /// Admin only: audit log, newest first; `from` is inclusive and `to` exclusive
+    #[oai(path = "/audit_logs", method = "get", transform = "metered")]
+    #[allow(clippy::too_many_arguments)]
+    async fn search(
+        &self,
+        page: Query<Option<u32>>,
+        size: Query<Option<u32>>,
+        actor_id: Query<Option<String>>,
+        action: Query<Option<AuditAction>>,
+        target: Query<Option<String>>,
+        from: Query<Option<NaiveDateTime>>,
+        to: Query<Option<NaiveDateTime>>,
+        auth: JwtAuth,
+    ) -> Result<Response<PageResult<AuditRecord>>> {
+        if !self.accounts.is_admin(&auth) {
+            return Err(AppError::AdminRequired);
+        }
+
+        let page = Page {
+            page: page.0.unwrap_or(1).max(1),
+            size: size.0.unwrap_or(20).clamp(1, 200),
+        };
+        let filter = AuditFilter {
+            actor_id: actor_id.0,
+            action: action.0,
+            target: target.0,
+            from: from.0,
+            to: to.0,
+        };
+
+        let result = self.audit.search(&filter, &page).await?;
+        Ok(Response::ok(result))
+    }
+}
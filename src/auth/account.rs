@@ -0,0 +1,570 @@
+use crate::{
+    auth::{apikey::JwtAuth, jwt::Claims, login::{AuthedUser, LoginResult, Oauth2}, session::SessionService, state},
+    common::{AppError, LocationContext, PhiTags, Response, Result},
+    db::Db,
+    metrics::metered,
+    util::limit::{Login, RateLimit},
+};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use cfg_rs::impl_enum;
+use chrono::{Duration, NaiveDateTime, Utc};
+use ioc::{mvc, Bean};
+use poem_openapi::{payload::Json, Object, OpenApi};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sqlx::{SqliteExecutor, SqlitePool};
+use std::{borrow::Cow, panic::Location, sync::LazyLock};
+use tokio::task::spawn_blocking;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// User id of the bootstrap admin account.
+pub(crate) const ADMIN_ID: &str = "phi_super_admin";
+
+const INVITE: &str = "invite";
+const RESET: &str = "reset";
+
+pub(crate) enum Registration {
+    Closed,
+    Invite,
+    Open,
+}
+
+impl_enum!(Registration {
+    "closed" => Registration::Closed
+    "invite" => Registration::Invite
+    "open" => Registration::Open
+});
+
+fn argon2_hash(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("hash password failed: {e}"))?;
+    Ok(hash.to_string())
+}
+
+fn argon2_verify(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+        .unwrap_or(false)
+}
+
+/// Verified when there is no hash to verify against, so that a missing user costs as much as
+/// a wrong password and timing doesn't tell which usernames exist.
+static DUMMY_HASH: LazyLock<String> =
+    LazyLock::new(|| argon2_hash("phi-dummy-password").unwrap_or_default());
+
+/// Argon2 takes tens of milliseconds of cpu on purpose, so it runs off the async workers.
+async fn hash_password(password: &str) -> Result<String> {
+    let password = password.to_string();
+    spawn_blocking(move || argon2_hash(&password)).await?
+}
+
+/// See [`hash_password`]; `None` verifies against [`DUMMY_HASH`] and fails.
+async fn verify_password(password: &str, hash: Option<&str>) -> Result<bool> {
+    let password = password.to_string();
+    let hash = hash.map(str::to_string);
+    let valid = spawn_blocking(move || match hash {
+        Some(hash) => argon2_verify(&password, &hash),
+        None => {
+            argon2_verify(&password, &DUMMY_HASH);
+            false
+        }
+    })
+        .await?;
+    Ok(valid)
+}
+
+/// A user with local credentials.
+#[derive(sqlx::FromRow)]
+pub(crate) struct Account {
+    id: String,
+    name: String,
+    password_hash: Option<String>,
+    failed_attempts: i64,
+    locked_until: Option<NaiveDateTime>,
+}
+
+impl Account {
+    /// A new local account, not stored yet.
+    pub(crate) async fn new_local(name: String, password: &str) -> Result<Self> {
+        Ok(Self {
+            id: format!("local_{}", Uuid::new_v4().as_simple()),
+            name,
+            password_hash: Some(hash_password(password).await?),
+            failed_attempts: 0,
+            locked_until: None,
+        })
+    }
+
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub(crate) async fn insert<'e>(
+        &self,
+        executor: impl SqliteExecutor<'e>,
+        username: &str,
+    ) -> Result<()> {
+        let now = Utc::now().naive_utc();
+        sqlx::query!(
+            r#"
+            INSERT INTO users (id, name, source, created_at, username, password_hash)
+            VALUES (?, ?, 'local', ?, ?, ?)
+            "#,
+            self.id,
+            self.name,
+            now,
+            username,
+            self.password_hash
+        )
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+}
+
+impl AuthedUser for Account {
+    fn user_id(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.id)
+    }
+
+    fn name(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.name)
+    }
+}
+
+#[derive(Bean)]
+pub(crate) struct AccountService {
+    #[inject(bean = Db)]
+    db: &'static SqlitePool,
+    #[inject(bean)]
+    sessions: &'static SessionService,
+    #[inject(config = "admin.name")]
+    admin_name: String,
+    #[inject(config = "admin.pass")]
+    admin_pass: String,
+    #[inject(config = "account.registration")]
+    registration: Registration,
+    #[inject(config = "account.min-password-length")]
+    min_password_length: usize,
+    #[inject(config = "account.max-failed-attempts")]
+    max_failed_attempts: i64,
+    #[inject(config = "account.lockout-seconds")]
+    lockout_seconds: i64,
+    #[inject(config = "account.token-expire-seconds")]
+    token_expire_seconds: i64,
+}
+
+impl AccountService {
+    pub(crate) fn is_admin(&self, claims: &Claims) -> bool {
+        claims.id == ADMIN_ID
+    }
+
+    fn require_admin(&self, claims: &Claims) -> Result<()> {
+        if self.is_admin(claims) {
+            Ok(())
+        } else {
+            Err(AppError::AdminRequired)
+        }
+    }
+
+    fn check_password(&self, password: &str) -> Result<()> {
+        if password.chars().count() < self.min_password_length {
+            return Err(AppError::WeakPassword(self.min_password_length));
+        }
+        Ok(())
+    }
+
+    async fn find(&self, username: &str) -> Result<Option<Account>> {
+        let account = sqlx::query_as(
+            r#"
+            SELECT id, name, password_hash, failed_attempts, locked_until
+            FROM users
+            WHERE username = ?
+            "#,
+        )
+            .bind(username)
+            .fetch_optional(self.db)
+            .await?;
+        Ok(account)
+    }
+
+    async fn find_by_id(&self, id: &str) -> Result<Option<Account>> {
+        let account = sqlx::query_as(
+            r#"
+            SELECT id, name, password_hash, failed_attempts, locked_until
+            FROM users
+            WHERE id = ?
+            "#,
+        )
+            .bind(id)
+            .fetch_optional(self.db)
+            .await?;
+        Ok(account)
+    }
+
+    /// Moves the config admin into the `users` table, hashing `admin.pass` unless it already is
+    /// a PHC string. An admin that already has a password is left untouched.
+    async fn bootstrap_admin(&self) -> Result<Option<Account>> {
+        let hash = if PasswordHash::new(&self.admin_pass).is_ok() {
+            self.admin_pass.clone()
+        } else {
+            warn!("admin.pass is plaintext, consider replacing it with an argon2 hash");
+            hash_password(&self.admin_pass).await?
+        };
+        let now = Utc::now().naive_utc();
+
+        sqlx::query!(
+            r#"
+            INSERT INTO users (id, name, source, created_at, username, password_hash)
+            VALUES (?1, ?2, 'buildin', ?3, ?2, ?4)
+            ON CONFLICT (id) DO UPDATE SET username = excluded.username, password_hash = excluded.password_hash
+            WHERE users.password_hash IS NULL
+            "#,
+            ADMIN_ID,
+            self.admin_name,
+            now,
+            hash
+        )
+            .execute(self.db)
+            .await?;
+        info!("admin account {} bootstrapped", self.admin_name);
+
+        self.find(&self.admin_name).await
+    }
+
+    /// Verifies the credentials, locking the account after too many failures.
+    pub(crate) async fn authenticate(&self, username: &str, password: &str) -> Result<Account> {
+        let account = match self.find(username).await? {
+            Some(account) => Some(account),
+            None if username == self.admin_name => self.bootstrap_admin().await?,
+            None => None,
+        };
+        let Some(account) = account else {
+            verify_password(password, None).await?;
+            return Err(AppError::InvalidUsernameOrPassword);
+        };
+
+        let now = Utc::now().naive_utc();
+        if let Some(locked_until) = account.locked_until.filter(|until| *until > now) {
+            return Err(AppError::AccountLocked(locked_until.to_string()));
+        }
+
+        let valid = verify_password(password, account.password_hash.as_deref()).await?;
+
+        if valid {
+            if account.failed_attempts > 0 || account.locked_until.is_some() {
+                sqlx::query!(
+                    "UPDATE users SET failed_attempts = 0, locked_until = NULL WHERE id = ?",
+                    account.id
+                )
+                    .execute(self.db)
+                    .await?;
+            }
+            return Ok(account);
+        }
+
+        let failed_attempts = account.failed_attempts + 1;
+        if failed_attempts >= self.max_failed_attempts {
+            let locked_until = now + Duration::seconds(self.lockout_seconds);
+            sqlx::query!(
+                "UPDATE users SET failed_attempts = 0, locked_until = ? WHERE id = ?",
+                locked_until,
+                account.id
+            )
+                .execute(self.db)
+                .await?;
+            warn!("account {username} locked until {locked_until}");
+        } else {
+            sqlx::query!(
+                "UPDATE users SET failed_attempts = ? WHERE id = ?",
+                failed_attempts,
+                account.id
+            )
+                .execute(self.db)
+                .await?;
+        }
+
+        Err(AppError::InvalidUsernameOrPassword)
+    }
+
+    pub(crate) async fn register(&self, request: &RegisterRequest) -> Result<Account> {
+        match self.registration {
+            Registration::Closed => return Err(AppError::RegistrationClosed),
+            Registration::Invite if request.invitation.is_none() => {
+                return Err(AppError::RegistrationClosed)
+            }
+            _ => {}
+        }
+        self.check_password(&request.password)?;
+        if request.username == self.admin_name || self.find(&request.username).await?.is_some() {
+            return Err(AppError::UsernameTaken(request.username.clone()));
+        }
+
+        let name = request.name.clone().unwrap_or_else(|| request.username.clone());
+        let account = Account::new_local(name, &request.password).await?;
+        let now = Utc::now().naive_utc();
+
+        let mut tx = self.db.begin().await?;
+
+        if let (Registration::Invite, Some(invitation)) = (&self.registration, &request.invitation) {
+            let hash = state::token_hash(invitation);
+            let used = sqlx::query!(
+                r#"
+                UPDATE account_tokens SET used_at = ?1
+                WHERE token_hash = ?2 AND kind = ?3 AND used_at IS NULL AND expires_at > ?1
+                "#,
+                now,
+                hash,
+                INVITE
+            )
+                .execute(&mut *tx)
+                .await?;
+            if used.rows_affected() == 0 {
+                return Err(AppError::InvalidAccountToken);
+            }
+        }
+
+        account.insert(&mut *tx, &request.username).await?;
+
+        tx.commit().await?;
+
+        info!("local account {} registered as {}", request.username, account.id);
+        Ok(account)
+    }
+
+    /// Creates a local account whatever the registration policy, for the admin cli.
+    pub(crate) async fn create_local(&self, username: &str, name: String, password: &str) -> Result<Account> {
+        self.check_password(password)?;
+        if username == self.admin_name || self.find(username).await?.is_some() {
+            return Err(AppError::UsernameTaken(username.to_string()));
+        }
+
+        let account = Account::new_local(name, password).await?;
+        account.insert(self.db, username).await?;
+        info!("local account {username} created");
+        Ok(account)
+    }
+
+    pub(crate) async fn change_password(&self, old: &str, new: &str, claims: &Claims) -> Result<()> {
+        let account = self
+            .find_by_id(&claims.id)
+            .await?
+            .ok_or_else(|| AppError::UserNotFound(claims.id.clone()))?;
+
+        let valid = verify_password(old, account.password_hash.as_deref()).await?;
+        if !valid {
+            return Err(AppError::InvalidUsernameOrPassword);
+        }
+
+        self.set_password(&account.id, new).await
+    }
+
+    async fn set_password(&self, id: &str, password: &str) -> Result<()> {
+        self.check_password(password)?;
+        let hash = hash_password(password).await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE users SET password_hash = ?, failed_attempts = 0, locked_until = NULL
+            WHERE id = ?
+            "#,
+            hash,
+            id
+        )
+            .execute(self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn issue_token(&self, kind: &str, user_id: Option<&str>, claims: &Claims) -> Result<AccountToken> {
+        let token = state::random_token();
+        let hash = state::token_hash(&token);
+        let now = Utc::now().naive_utc();
+        let expires_at = now + Duration::seconds(self.token_expire_seconds);
+
+        sqlx::query!(
+            r#"
+            INSERT INTO account_tokens (token_hash, kind, user_id, created_by, expires_at, created_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+            hash,
+            kind,
+            user_id,
+            claims.id,
+            expires_at,
+            now
+        )
+            .execute(self.db)
+            .await?;
+
+        Ok(AccountToken { token, expires_at })
+    }
+
+    pub(crate) async fn invite(&self, claims: &Claims) -> Result<AccountToken> {
+        self.require_admin(claims)?;
+        self.issue_token(INVITE, None, claims).await
+    }
+
+    /// Issues a reset token for a local account, to be handed to the user out of band.
+    pub(crate) async fn reset_token(&self, user_id: &str, claims: &Claims) -> Result<AccountToken> {
+        self.require_admin(claims)?;
+        let account = self
+            .find_by_id(user_id)
+            .await?
+            .filter(|account| account.password_hash.is_some())
+            .ok_or_else(|| AppError::UserNotFound(user_id.to_string()))?;
+        self.issue_token(RESET, Some(&account.id), claims).await
+    }
+
+    pub(crate) async fn reset_password(&self, token: &str, password: &str) -> Result<()> {
+        self.check_password(password)?;
+        let hash = state::token_hash(token);
+        let now = Utc::now().naive_utc();
+
+        let user_id: Option<String> = sqlx::query_scalar(
+            r#"
+            UPDATE account_tokens SET used_at = ?1
+            WHERE token_hash = ?2 AND kind = ?3 AND used_at IS NULL AND expires_at > ?1
+            RETURNING user_id
+            "#,
+        )
+            .bind(now)
+            .bind(&hash)
+            .bind(RESET)
+            .fetch_optional(self.db)
+            .await?
+            .flatten();
+
+        let user_id = user_id.ok_or(AppError::InvalidAccountToken)?;
+        self.set_password(&user_id, password).await?;
+        self.sessions.revoke_all(&user_id).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub(crate) struct RegisterRequest {
+    username: String,
+    password: String,
+    /// display name, defaults to the username
+    name: Option<String>,
+    /// required when `account.registration` is `invite`
+    invitation: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub(crate) struct ChangePasswordRequest {
+    old_password: String,
+    new_password: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub(crate) struct ResetTokenRequest {
+    user_id: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub(crate) struct ResetPasswordRequest {
+    token: String,
+    new_password: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub(crate) struct AccountToken {
+    token: String,
+    expires_at: NaiveDateTime,
+}
+
+#[derive(Bean)]
+pub struct AccountMvc {
+    #[inject(bean)]
+    accounts: &'static AccountService,
+    #[inject(bean)]
+    oauth: &'static Oauth2,
+}
+
+#[mvc]
+#[OpenApi(prefix_path = "/api/auth", tag = PhiTags::Auth)]
+impl AccountMvc {
+    /// Registers a local account and signs in to its personal workspace
+    #[oai(path = "/register", method = "post", transform = "metered")]
+    async fn register(
+        &self,
+        _limit: RateLimit<Login>,
+        request: Json<RegisterRequest>,
+    ) -> Result<Response<LoginResult>> {
+        let account = self.accounts.register(&request).await?;
+        let result = self
+            .oauth
+            .sign_in(&account, "local", None)
+            .await
+            .location("register failed", Location::caller())?;
+        Ok(Response::ok(result))
+    }
+
+    #[oai(path = "/password", method = "put", transform = "metered")]
+    async fn change_password(
+        &self,
+        auth: JwtAuth,
+        _limit: RateLimit<Login>,
+        request: Json<ChangePasswordRequest>,
+    ) -> Result<Response<String>> {
+        self.accounts
+            .change_password(&request.old_password, &request.new_password, &auth)
+            .await?;
+        Ok(Response::ok("ok".to_string()))
+    }
+
+    /// Admin only: invitation for registering when `account.registration` is `invite`
+    #[oai(path = "/invitations", method = "post", transform = "metered")]
+    async fn invite(&self, auth: JwtAuth) -> Result<Response<AccountToken>> {
+        let token = self.accounts.invite(&auth).await?;
+        Ok(Response::ok(token))
+    }
+
+    /// Admin only: password reset token of a local account
+    #[oai(path = "/password_reset_tokens", method = "post", transform = "metered")]
+    async fn reset_token(
+        &self,
+        request: Json<ResetTokenRequest>,
+        auth: JwtAuth,
+    ) -> Result<Response<AccountToken>> {
+        let token = self.accounts.reset_token(&request.user_id, &auth).await?;
+        Ok(Response::ok(token))
+    }
+
+    #[oai(path = "/password_reset", method = "post", transform = "metered")]
+    async fn reset_password(
+        &self,
+        _limit: RateLimit<Login>,
+        request: Json<ResetPasswordRequest>,
+    ) -> Result<Response<String>> {
+        self.accounts
+            .reset_password(&request.token, &request.new_password)
+            .await?;
+        Ok(Response::ok("ok".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_password_hash() -> anyhow::Result<()> {
+        let hash = hash_password("goodluckxixi").await?;
+
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("goodluckxixi", Some(&hash)).await?);
+        assert!(!verify_password("goodluck", Some(&hash)).await?);
+        assert!(!verify_password("goodluckxixi", Some("goodluckxixi")).await?);
+        assert!(!verify_password("phi-dummy-password", None).await?);
+        Ok(())
+    }
+}
@@ -1,8 +1,22 @@
 use crate::auth::jwt::{Claims, JwtService};
-use ioc::BeanSpec;
+use crate::auth::session::SessionService;
+use crate::auth::state;
+use crate::common::{AppError, PhiTags, Response, Result};
+use crate::db::Db;
+use crate::metrics::metered;
+use crate::workspace::{biz::WorkspaceService, WorkspaceRole};
+use chrono::{Duration, NaiveDateTime, Utc};
+use http::Method;
+use ioc::{mvc, Bean, BeanSpec};
 use poem::Request;
-use poem_openapi::{auth::ApiKey, SecurityScheme};
+use poem_openapi::{auth::ApiKey, param::Path, payload::Json, Enum, Object, OpenApi, SecurityScheme};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
 use std::ops::Deref;
+use uuid::Uuid;
+
+/// Personal api keys start with this, anything else in the `auth` header is a jwt.
+pub(crate) const API_KEY_PREFIX: &str = "phi_";
 
 #[derive(SecurityScheme, Debug)]
 #[oai(
@@ -33,7 +47,345 @@ impl JwtAuth {
     }
 }
 
-async fn api_checker(_req: &Request, api_key: ApiKey) -> poem::Result<Claims> {
+async fn api_checker(req: &Request, api_key: ApiKey) -> poem::Result<Claims> {
+    if api_key.key.starts_with(API_KEY_PREFIX) {
+        let required = required_scope(req.method(), req.uri().path())
+            .ok_or(AppError::ApiKeyNotAllowed)?;
+        let claims = ApiKeyService::get().authenticate(&api_key.key, required).await?;
+        return Ok(claims);
+    }
+
     let claims = JwtService::get().decode(&api_key.key)?;
+    if !SessionService::get().is_active(&claims.jti).await? {
+        return Err(AppError::SessionRevoked.into());
+    }
     Ok(claims)
 }
+
+/// Claims of the `auth` header, for routes that take credentials other ways as well and so
+/// can't require [`JwtAuth`].
+pub(crate) async fn authenticate(req: &Request) -> Result<Claims> {
+    let key = req
+        .headers()
+        .get("auth")
+        .and_then(|value| value.to_str().ok())
+        .ok_or(AppError::MissingCredentials)?;
+    api_checker(req, ApiKey { key: key.to_string() })
+        .await
+        .map_err(AppError::from)
+}
+
+/// Scope an api key needs for the route; `None` for routes only open to interactive sessions.
+fn required_scope(method: &Method, path: &str) -> Option<ApiScope> {
+    if path.starts_with("/api/v1/files/") {
+        return (method == Method::GET).then_some(ApiScope::Read);
+    }
+    let path = path.strip_prefix("/api/v1/materials")?;
+    match (method, path) {
+        (&Method::POST, ":search") => Some(ApiScope::Read),
+        (&Method::POST, "/batch_delete") => Some(ApiScope::Delete),
+        (&Method::POST, "/video" | "/image" | "/remote") => Some(ApiScope::Upload),
+        (&Method::POST, path) if path.ends_with("/cancel") || path.ends_with("/clip") => Some(ApiScope::Upload),
+        (&Method::GET | &Method::HEAD, _) => Some(ApiScope::Read),
+        (&Method::PATCH, _) => Some(ApiScope::Upload),
+        (&Method::DELETE, _) => Some(ApiScope::Delete),
+        _ => None,
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Enum)]
+#[serde(rename_all = "lowercase")]
+#[oai(rename_all = "lowercase")]
+pub(crate) enum ApiScope {
+    Read,
+    Upload,
+    Delete,
+}
+
+impl ApiScope {
+    fn value(&self) -> &'static str {
+        match self {
+            ApiScope::Read => "read",
+            ApiScope::Upload => "upload",
+            ApiScope::Delete => "delete",
+        }
+    }
+
+    fn from_value(value: &str) -> Option<Self> {
+        match value {
+            "read" => Some(ApiScope::Read),
+            "upload" => Some(ApiScope::Upload),
+            "delete" => Some(ApiScope::Delete),
+            _ => None,
+        }
+    }
+
+    fn join(scopes: &[ApiScope]) -> String {
+        scopes.iter().map(ApiScope::value).collect::<Vec<_>>().join(",")
+    }
+
+    fn split(scopes: &str) -> Vec<ApiScope> {
+        scopes.split(',').filter_map(ApiScope::from_value).collect()
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct ApiKeyRow {
+    id: String,
+    user_id: String,
+    name: String,
+    workspace_id: String,
+    scopes: String,
+    expires_at: Option<NaiveDateTime>,
+    last_used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Object)]
+pub(crate) struct ApiKeyInfo {
+    id: String,
+    name: String,
+    workspace_id: String,
+    /// first characters of the key, to tell keys apart
+    prefix: String,
+    scopes: Vec<ApiScope>,
+    expires_at: Option<NaiveDateTime>,
+    last_used_at: Option<NaiveDateTime>,
+    created_at: NaiveDateTime,
+}
+
+#[derive(sqlx::FromRow)]
+struct ApiKeyInfoRow {
+    id: String,
+    name: String,
+    workspace_id: String,
+    prefix: String,
+    scopes: String,
+    expires_at: Option<NaiveDateTime>,
+    last_used_at: Option<NaiveDateTime>,
+    created_at: NaiveDateTime,
+}
+
+impl From<ApiKeyInfoRow> for ApiKeyInfo {
+    fn from(row: ApiKeyInfoRow) -> Self {
+        Self {
+            id: row.id,
+            name: row.name,
+            workspace_id: row.workspace_id,
+            prefix: row.prefix,
+            scopes: ApiScope::split(&row.scopes),
+            expires_at: row.expires_at,
+            last_used_at: row.last_used_at,
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Object)]
+pub(crate) struct NewApiKey {
+    #[oai(flatten)]
+    #[serde(flatten)]
+    info: ApiKeyInfo,
+    /// the key itself, shown only once
+    key: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub(crate) struct ApiKeyRequest {
+    name: String,
+    scopes: Vec<ApiScope>,
+    /// days until the key expires, never when absent
+    expires_in_days: Option<u32>,
+}
+
+#[derive(Bean)]
+pub(crate) struct ApiKeyService {
+    #[inject(bean = Db)]
+    db: &'static SqlitePool,
+    #[inject(bean)]
+    workspaces: &'static WorkspaceService,
+}
+
+impl ApiKeyService {
+    /// Creates a key acting as the caller in the caller's active workspace.
+    pub(crate) async fn create(&self, request: &ApiKeyRequest, claims: &Claims) -> Result<NewApiKey> {
+        if request.scopes.is_empty() {
+            return Err(AppError::ApiKeyScopesRequired);
+        }
+        let key = format!("{API_KEY_PREFIX}{}", state::random_token());
+        let key_hash = state::token_hash(&key);
+        let now = Utc::now().naive_utc();
+
+        let info = ApiKeyInfo {
+            id: Uuid::new_v4().to_string(),
+            name: request.name.clone(),
+            workspace_id: claims.workspace.clone(),
+            prefix: key.chars().take(API_KEY_PREFIX.len() + 6).collect(),
+            scopes: request.scopes.clone(),
+            expires_at: request
+                .expires_in_days
+                .map(|days| now + Duration::days(days.into())),
+            last_used_at: None,
+            created_at: now,
+        };
+        let scopes = ApiScope::join(&info.scopes);
+
+        sqlx::query!(
+            r#"
+            INSERT INTO api_keys (id, user_id, workspace_id, name, prefix, key_hash, scopes, expires_at, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+            info.id,
+            claims.id,
+            info.workspace_id,
+            info.name,
+            info.prefix,
+            key_hash,
+            scopes,
+            info.expires_at,
+            info.created_at
+        )
+            .execute(self.db)
+            .await?;
+
+        Ok(NewApiKey { info, key })
+    }
+
+    pub(crate) async fn list(&self, claims: &Claims) -> Result<Vec<ApiKeyInfo>> {
+        let rows: Vec<ApiKeyInfoRow> = sqlx::query_as(
+            r#"
+            SELECT id, name, workspace_id, prefix, scopes, expires_at, last_used_at, created_at
+            FROM api_keys
+            WHERE user_id = ? AND revoked_at IS NULL
+            ORDER BY created_at DESC
+            "#,
+        )
+            .bind(&claims.id)
+            .fetch_all(self.db)
+            .await?;
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    pub(crate) async fn revoke(&self, id: &str, claims: &Claims) -> Result<()> {
+        let now = Utc::now().naive_utc();
+        let result = sqlx::query!(
+            "UPDATE api_keys SET revoked_at = ? WHERE id = ? AND user_id = ? AND revoked_at IS NULL",
+            now,
+            id,
+            claims.id
+        )
+            .execute(self.db)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::ApiKeyNotFound(id.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Resolves the key to the claims of its owner, checking it grants `required`.
+    pub(crate) async fn authenticate(&self, key: &str, required: ApiScope) -> Result<Claims> {
+        let now = Utc::now().naive_utc();
+        let row: Option<ApiKeyRow> = sqlx::query_as(
+            r#"
+            SELECT k.id, k.user_id, u.name, k.workspace_id, k.scopes, k.expires_at, k.last_used_at
+            FROM api_keys k
+                     JOIN users u ON u.id = k.user_id
+            WHERE k.key_hash = ? AND k.revoked_at IS NULL
+            "#,
+        )
+            .bind(state::token_hash(key))
+            .fetch_optional(self.db)
+            .await?;
+
+        let row = row
+            .filter(|row| row.expires_at.is_none_or(|expires_at| expires_at > now))
+            .ok_or(AppError::InvalidApiKey)?;
+
+        if !ApiScope::split(&row.scopes).contains(&required) {
+            return Err(AppError::ApiKeyScopeMissing(required.value().to_string()));
+        }
+
+        // a key is not revoked when its user leaves the workspace, the membership is checked here
+        self.workspaces
+            .require_role(&row.workspace_id, &row.user_id, WorkspaceRole::Viewer)
+            .await?;
+
+        // a minute is precise enough and saves a write per request
+        if row.last_used_at.is_none_or(|at| now - at > Duration::minutes(1)) {
+            sqlx::query!(
+                "UPDATE api_keys SET last_used_at = ? WHERE id = ?",
+                now,
+                row.id
+            )
+                .execute(self.db)
+                .await?;
+        }
+
+        Ok(Claims {
+            name: row.name,
+            id: row.user_id,
+            workspace: row.workspace_id,
+            jti: row.id,
+            exp: row
+                .expires_at
+                .map(|expires_at| expires_at.and_utc().timestamp() as u64)
+                .unwrap_or(u64::MAX),
+        })
+    }
+}
+
+#[derive(Bean)]
+pub(crate) struct ApiKeyMvc {
+    #[inject(bean)]
+    keys: &'static ApiKeyService,
+}
+
+#[mvc]
+#[OpenApi(prefix_path = "/api/auth", tag = PhiTags::Auth)]
+impl ApiKeyMvc {
+    #[oai(path = "/api_keys", method = "post", transform = "metered")]
+    async fn create(&self, request: Json<ApiKeyRequest>, auth: JwtAuth) -> Result<Response<NewApiKey>> {
+        let key = self.keys.create(&request, &auth).await?;
+        Ok(Response::ok(key))
+    }
+
+    #[oai(path = "/api_keys", method = "get", transform = "metered")]
+    async fn list(&self, auth: JwtAuth) -> Result<Response<Vec<ApiKeyInfo>>> {
+        let keys = self.keys.list(&auth).await?;
+        Ok(Response::ok(keys))
+    }
+
+    #[oai(path = "/api_keys/:id", method = "delete", transform = "metered")]
+    async fn revoke(&self, id: Path<String>, auth: JwtAuth) -> Result<Response<String>> {
+        self.keys.revoke(&id, &auth).await?;
+        Ok(Response::ok("ok".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_required_scope() {
+        let scope = |method: Method, path: &str| required_scope(&method, path);
+
+        assert_eq!(scope(Method::POST, "/api/v1/materials:search"), Some(ApiScope::Read));
+        assert_eq!(scope(Method::GET, "/api/v1/materials/abc"), Some(ApiScope::Read));
+        assert_eq!(scope(Method::POST, "/api/v1/materials/video"), Some(ApiScope::Upload));
+        assert_eq!(scope(Method::POST, "/api/v1/materials/remote"), Some(ApiScope::Upload));
+        assert_eq!(scope(Method::POST, "/api/v1/materials/abc/cancel"), Some(ApiScope::Upload));
+        assert_eq!(scope(Method::POST, "/api/v1/materials/abc/clip"), Some(ApiScope::Upload));
+        assert_eq!(scope(Method::DELETE, "/api/v1/materials/abc"), Some(ApiScope::Delete));
+        assert_eq!(scope(Method::POST, "/api/v1/materials/batch_delete"), Some(ApiScope::Delete));
+        assert_eq!(scope(Method::POST, "/api/auth/api_keys"), None);
+        assert_eq!(scope(Method::GET, "/api/v1/files/ws/abc/720p/slice.m3u8"), Some(ApiScope::Read));
+        assert_eq!(scope(Method::GET, "/api/v1/workspaces"), None);
+    }
+
+    #[test]
+    fn test_scopes_round_trip() {
+        let scopes = vec![ApiScope::Read, ApiScope::Delete];
+        assert_eq!(ApiScope::split(&ApiScope::join(&scopes)), scopes);
+    }
+}
@@ -1,55 +1,121 @@
 use std::{
-    fs::{create_dir_all, exists, read, write},
-    path::Path,
-    path::PathBuf,
+    fs::{create_dir_all, exists, read, read_dir, remove_file, write},
+    path::{Path, PathBuf},
+    sync::RwLock,
 };
 
-use ioc::{bean, BeanSpec, InitContext};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use ioc::{bean, mvc, Bean, BeanSpec, InitContext};
 use jsonwebtoken::{
-    decode, encode, get_current_timestamp, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+    decode, decode_header, encode, errors::ErrorKind, get_current_timestamp, Algorithm,
+    DecodingKey, EncodingKey, Header, Validation,
 };
+use poem_openapi::{payload::Json, Object, OpenApi};
+use rand::{thread_rng, Rng};
 use ring::signature::KeyPair;
 use ring::{rand::SystemRandom, signature::Ed25519KeyPair};
 use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
 
-use crate::common::Result;
+use crate::common::{PhiTags, Result};
+use crate::metrics::metered;
 
+const KEY_EXTENSION: &str = "key";
+
+fn invalid(kind: ErrorKind) -> jsonwebtoken::errors::Error {
+    kind.into()
+}
+
+/// An Ed25519 signing key, stored as `<created_at>_<suffix>.key` where the file stem is the `kid`.
 struct Keys {
+    kid: String,
+    created_at: u64,
     encoding: EncodingKey,
     decoding: DecodingKey,
+    public: Vec<u8>,
 }
 
 impl Keys {
-    fn from(secret: &[u8]) -> ioc::Result<Self> {
+    fn from(kid: String, created_at: u64, secret: &[u8]) -> ioc::Result<Self> {
         let encoding = EncodingKey::from_ed_der(secret);
 
         let pair =
             Ed25519KeyPair::from_pkcs8(secret).map_err(|err| ioc::IocError::Other(err.into()))?;
 
-        let decoding = DecodingKey::from_ed_der(pair.public_key().as_ref());
+        let public = pair.public_key().as_ref().to_vec();
+        let decoding = DecodingKey::from_ed_der(&public);
+
+        Ok(Self {
+            kid,
+            created_at,
+            encoding,
+            decoding,
+            public,
+        })
+    }
+
+    fn path(dir: &Path, kid: &str) -> PathBuf {
+        dir.join(kid).with_extension(KEY_EXTENSION)
+    }
 
-        Ok(Self { encoding, decoding })
+    /// Stores `document` as a new key created now.
+    fn save(dir: &Path, document: &[u8]) -> ioc::Result<Self> {
+        let created_at = get_current_timestamp();
+        let kid = format!("{created_at}_{:04x}", thread_rng().gen::<u16>());
+        create_dir_all(dir)?;
+        write(Self::path(dir, &kid), document)?;
+        Self::from(kid, created_at, document)
     }
 
-    fn new(document_path: impl AsRef<Path>) -> ioc::Result<Self> {
-        let keys = if exists(&document_path)? {
-            let bytes = read(&document_path)?;
-            Self::from(&bytes)?
-        } else {
-            let document = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
-                .map_err(|err| ioc::IocError::Other(err.into()))?;
-            if let Some(parent) = document_path.as_ref().parent() {
-                create_dir_all(parent)?;
+    fn generate(dir: &Path) -> ioc::Result<Self> {
+        let document = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|err| ioc::IocError::Other(err.into()))?;
+        let keys = Self::save(dir, document.as_ref())?;
+        info!("jwt signing key {} generated", keys.kid);
+        Ok(keys)
+    }
+
+    /// Keys of `dir`, oldest first.
+    fn load(dir: &Path) -> ioc::Result<Vec<Self>> {
+        let mut keys = Vec::new();
+        if !exists(dir)? {
+            return Ok(keys);
+        }
+        for entry in read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(KEY_EXTENSION) {
+                continue;
             }
-            write(&document_path, document.as_ref())?;
-            Self::from(document.as_ref())?
-        };
+            let Some(kid) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let Some(created_at) = kid.split('_').next().and_then(|ts| ts.parse().ok()) else {
+                continue;
+            };
+            keys.push(Self::from(kid.to_string(), created_at, &read(&path)?)?);
+        }
+        keys.sort_by_key(|keys| keys.created_at);
         Ok(keys)
     }
+
+    fn jwk(&self) -> Jwk {
+        Jwk {
+            kty: "OKP".to_string(),
+            crv: "Ed25519".to_string(),
+            alg: "EdDSA".to_string(),
+            usage: "sig".to_string(),
+            kid: self.kid.clone(),
+            x: Base64UrlUnpadded::encode_string(&self.public),
+        }
+    }
 }
 
+/// Signs tokens with the newest key, rotating it every `rotation_secs`. Retired keys keep
+/// verifying until every token they signed has expired, then they are deleted.
 pub(crate) struct JwtService {
-    keys: Keys,
+    dir: PathBuf,
+    keys: RwLock<Vec<Keys>>,
+    rotation_secs: u64,
     expire_secs: u64,
 }
 
@@ -57,58 +123,212 @@ pub(crate) struct JwtService {
 mod test {
     use super::*;
 
+    fn service(dir: &str, rotation_secs: u64) -> JwtService {
+        let dir = PathBuf::from(dir);
+        let _ = std::fs::remove_dir_all(&dir);
+        JwtService::open(dir, Path::new("target/none.pem"), rotation_secs, 60).unwrap()
+    }
+
+    fn claims(jwt_service: &JwtService) -> Claims {
+        jwt_service.new_claims(
+            "test".to_string(),
+            "test".to_string(),
+            "test".to_string(),
+            "test".to_string(),
+        )
+    }
+
     #[test]
     fn test_jwt_service() {
-        let document_path = PathBuf::from("target/jwt.pem");
-        let jwt_service = JwtService {
-            keys: Keys::new(document_path).unwrap(),
-            expire_secs: 60,
-        };
-        let claims = jwt_service.new_claims("test".to_string(), "test".to_string());
+        let jwt_service = service("target/jwt-keys", 3600);
+        let claims = claims(&jwt_service);
         let token = jwt_service.encode(&claims).unwrap();
         let result = jwt_service.decode(&token).unwrap();
         assert_eq!(claims, result);
     }
+
+    #[test]
+    fn test_rotated_keys_still_verify() {
+        let jwt_service = service("target/jwt-keys-rotation", 0);
+        let claims = claims(&jwt_service);
+
+        let first = jwt_service.encode(&claims).unwrap();
+        let second = jwt_service.encode(&claims).unwrap();
+
+        let kid = |token: &str| decode_header(token).unwrap().kid;
+        assert_ne!(kid(&first), kid(&second));
+        assert_eq!(jwt_service.decode(&first).unwrap(), claims);
+        assert_eq!(jwt_service.decode(&second).unwrap(), claims);
+        assert!(jwt_service.jwks().keys.len() >= 2);
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Claims {
     pub name: String,
     pub id: String,
+    /// active workspace of the token
+    pub workspace: String,
+    /// session the token was issued for, see `SessionService`
+    pub jti: String,
     pub exp: u64,
 }
 
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub(crate) struct Jwk {
+    kty: String,
+    crv: String,
+    alg: String,
+    #[oai(rename = "use")]
+    #[serde(rename = "use")]
+    usage: String,
+    kid: String,
+    x: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub(crate) struct Jwks {
+    keys: Vec<Jwk>,
+}
+
 #[bean]
 impl BeanSpec for JwtService {
     type Bean = Self;
 
     fn build(ctx: &mut impl InitContext) -> ioc::Result<Self::Bean> {
+        let dir = ctx.get_config::<PathBuf>("jwt.keys-dir")?;
         let document_path = ctx.get_config::<PathBuf>("jwt.document-path")?;
-
-        let keys = Keys::new(document_path)?;
-
+        let rotation_days = ctx.get_config::<u64>("jwt.rotation-days")?;
         let expire_secs = ctx.get_config::<u64>("jwt.expire-seconds")?;
 
-        Ok(JwtService { keys, expire_secs })
+        JwtService::open(dir, &document_path, rotation_days * 24 * 3600, expire_secs)
     }
 }
 
 impl JwtService {
-    pub fn new_claims(&self, name: String, id: String) -> Claims {
+    /// Loads the keys of `dir`. On first start the single key at `document_path`, used before
+    /// rotation existed, is imported so tokens keep their signer.
+    fn open(
+        dir: PathBuf,
+        document_path: &Path,
+        rotation_secs: u64,
+        expire_secs: u64,
+    ) -> ioc::Result<Self> {
+        let mut keys = Keys::load(&dir)?;
+        if keys.is_empty() {
+            let key = if exists(document_path)? {
+                info!("importing jwt key {}", document_path.display());
+                Keys::save(&dir, &read(document_path)?)?
+            } else {
+                Keys::generate(&dir)?
+            };
+            keys.push(key);
+        }
+
+        let service = Self {
+            dir,
+            keys: RwLock::new(keys),
+            rotation_secs,
+            expire_secs,
+        };
+        service.rotate()?;
+        Ok(service)
+    }
+
+    /// Generates a new signing key when the current one is due and drops retired keys whose
+    /// tokens have all expired.
+    fn rotate(&self) -> ioc::Result<()> {
+        let now = get_current_timestamp();
+        let due = |keys: &[Keys]| {
+            keys.last()
+                .is_none_or(|current| current.created_at + self.rotation_secs <= now)
+        };
+
+        if !due(&self.keys.read().unwrap_or_else(|e| e.into_inner())) {
+            return Ok(());
+        }
+
+        let mut keys = self.keys.write().unwrap_or_else(|e| e.into_inner());
+        if !due(&keys) {
+            return Ok(());
+        }
+        keys.push(Keys::generate(&self.dir)?);
+
+        // a key retires when its successor is created
+        let retired: Vec<usize> = keys
+            .windows(2)
+            .enumerate()
+            .filter(|(_, pair)| pair[1].created_at + self.expire_secs < now)
+            .map(|(index, _)| index)
+            .collect();
+        for index in retired.into_iter().rev() {
+            let key = keys.remove(index);
+            // the key is out of use already, a leftover file only costs a load at startup
+            match remove_file(Keys::path(&self.dir, &key.kid)) {
+                Ok(()) => info!("jwt signing key {} removed", key.kid),
+                Err(e) => warn!("remove jwt signing key {} failed: {e}", key.kid),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn new_claims(&self, name: String, id: String, workspace: String, jti: String) -> Claims {
         Claims {
             name,
             id,
+            workspace,
+            jti,
             exp: get_current_timestamp() + self.expire_secs,
         }
     }
 
     pub(crate) fn encode(&self, claims: &Claims) -> Result<String> {
-        encode(&Header::new(Algorithm::EdDSA), claims, &self.keys.encoding).map_err(Into::into)
+        self.rotate()?;
+
+        let keys = self.keys.read().unwrap_or_else(|e| e.into_inner());
+        let current = keys.last().ok_or_else(|| invalid(ErrorKind::InvalidKeyFormat))?;
+
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(current.kid.clone());
+
+        encode(&header, claims, &current.encoding).map_err(Into::into)
     }
 
     pub(crate) fn decode(&self, token: &str) -> Result<Claims> {
+        let kid = decode_header(token)?.kid.ok_or_else(|| invalid(ErrorKind::InvalidToken))?;
+
+        let keys = self.keys.read().unwrap_or_else(|e| e.into_inner());
+        let key = keys
+            .iter()
+            .find(|key| key.kid == kid)
+            .ok_or_else(|| invalid(ErrorKind::InvalidToken))?;
+
         let validation = Validation::new(Algorithm::EdDSA);
-        let result = decode::<Claims>(token, &self.keys.decoding, &validation)?;
+        let result = decode::<Claims>(token, &key.decoding, &validation)?;
         Ok(result.claims)
     }
+
+    /// Public keys still accepted for verification.
+    pub(crate) fn jwks(&self) -> Jwks {
+        let keys = self.keys.read().unwrap_or_else(|e| e.into_inner());
+        Jwks {
+            keys: keys.iter().map(Keys::jwk).collect(),
+        }
+    }
+}
+
+#[derive(Bean)]
+pub(crate) struct JwksMvc {
+    #[inject(bean)]
+    jwt: &'static JwtService,
+}
+
+#[mvc]
+#[OpenApi(prefix_path = "/.well-known", tag = PhiTags::Auth)]
+impl JwksMvc {
+    /// Keys verifying phi tokens, for other services
+    #[oai(path = "/jwks.json", method = "get", transform = "metered")]
+    async fn jwks(&self) -> Json<Jwks> {
+        Json(self.jwt.jwks())
+    }
 }
@@ -1,26 +1,43 @@
-use crate::auth::apikey::JwtAuth;
-use crate::auth::jwt::Claims;
 use crate::auth::user::NewUser;
 use crate::{
-    auth::{jwt::JwtService, user::UserService},
+    audit::{
+        biz::{AuditEntry, AuditService},
+        AuditAction,
+    },
+    auth::{
+        account::AccountService,
+        provider::{OAuthProviders, Provider, ProviderUser},
+        session::SessionService,
+        state::{self, OAuthStates, PendingLogin},
+        user::UserService,
+    },
     client::HttpClient,
-    common::{self, LocationContext, PhiTags},
+    common::{self, LocationContext, PhiTags, ResponseBody},
+    metrics::metered,
+    util::{
+        limit::{Login, RateLimit},
+        poem::ClientInfo,
+    },
+    workspace::biz::WorkspaceService,
 };
 use cfg_rs::impl_enum;
-use common::AppError::InvalidUsernameOrPassword;
 use http::uri::Scheme;
 use ioc::{mvc, Bean};
 use poem::Request;
 use poem_openapi::payload::Json;
-use poem_openapi::{param::Query, Object, OpenApi};
+use poem_openapi::{
+    param::{Path, Query},
+    ApiResponse, Object, OpenApi,
+};
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::{borrow::Cow, panic::Location, str::FromStr};
 use chrono::{DateTime, Local};
 use tracing::{debug, info};
 use tracing::log::warn;
 
-enum RedirectPolicy {
+pub(crate) enum RedirectPolicy {
     Safe,
     Auto,
     Manual,
@@ -37,40 +54,33 @@ pub struct LoginMvc {
     #[inject(bean)]
     oauth: &'static Oauth2,
     #[inject(bean)]
-    jwt: &'static JwtService,
+    sessions: &'static SessionService,
 }
 
 #[derive(Bean)]
 pub struct Oauth2 {
-    #[inject(config = "oauth.authorization-url")]
-    authorization_url: String,
-    #[inject(config = "oauth.client-id")]
-    client_id: String,
-    #[inject(config = "oauth.client-secret")]
-    client_secret: String,
-    #[inject(config = "oauth.scopes")]
-    scopes: Vec<String>,
+    #[inject(bean)]
+    providers: &'static OAuthProviders,
     #[inject(bean)]
     service: &'static UserService,
     #[inject(bean)]
-    jwt: &'static JwtService,
+    accounts: &'static AccountService,
+    #[inject(bean)]
+    audit: &'static AuditService,
+    #[inject(bean)]
+    workspaces: &'static WorkspaceService,
+    #[inject(bean)]
+    sessions: &'static SessionService,
+    #[inject(bean)]
+    states: &'static OAuthStates,
     #[inject(bean)]
     client: &'static HttpClient,
     #[inject(config = "oauth.redirect-policy")]
     redirect_policy: RedirectPolicy,
     #[inject(config = "oauth.redirect-url")]
     redirect_url: String,
-    #[inject(config = "admin.name")]
-    admin_name: String,
-    #[inject(config = "admin.pass")]
-    admin_pass: String,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-struct AccessTokenResult {
-    access_token: String,
-    scope: String,
-    token_type: String,
+    #[inject(config = "oauth.post-login-redirect")]
+    post_login_redirect: String,
 }
 
 pub trait AuthedUser {
@@ -79,27 +89,8 @@ pub trait AuthedUser {
     fn name(&self) -> Cow<'_, str>;
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct AuthedGithubUser {
-    id: i64,
-    login: String,
-    name: String,
-    email: String,
-    avatar_url: String,
-}
-
-impl AuthedUser for AuthedGithubUser {
-    fn user_id(&self) -> Cow<'_, str> {
-        Cow::Owned(format!("gh_{}", self.id))
-    }
-
-    fn name(&self) -> Cow<'_, str> {
-        Cow::Borrowed(&self.name)
-    }
-}
-
 impl Oauth2 {
-    fn redirect_uri(&self, req: &Request) -> Option<Cow<'_, str>> {
+    fn redirect_uri<'a>(&'a self, req: &Request, provider: &'a Provider) -> Option<Cow<'a, str>> {
         match self.redirect_policy {
             RedirectPolicy::Safe => None,
             RedirectPolicy::Auto => {
@@ -121,91 +112,152 @@ impl Oauth2 {
                     .unwrap_or_else(|| Cow::Owned(req.local_addr().to_string()));
 
                 Some(Cow::Owned(format!(
-                    "{scheme}://{host}/api/auth/oauth2_login"
+                    "{scheme}://{host}/api/auth/oauth2_login/{}",
+                    provider.name()
                 )))
             }
-            RedirectPolicy::Manual => Some(Cow::Borrowed(&self.redirect_url)),
+            RedirectPolicy::Manual => Some(Cow::Borrowed(
+                provider.redirect_url().unwrap_or(&self.redirect_url),
+            )),
         }
     }
 
-    pub fn login_url(&self, req: &Request) -> common::Result<Url> {
-        let mut url = Url::parse(&self.authorization_url)?;
+    /// Starts a login: returns the authorization url and the `state` to bind to the browser.
+    pub async fn login_url(
+        &self,
+        provider: &str,
+        req: &Request,
+        workspace: Option<String>,
+    ) -> common::Result<(Url, String)> {
+        let provider = self.providers.get(provider)?;
+        let redirect_uri = self.redirect_uri(req, provider);
+        let authorization = self.states.begin(provider.name(), workspace);
+        let url = provider
+            .login_url(self.client.rest(), redirect_uri.as_deref(), &authorization)
+            .await?;
+        Ok((url, authorization.state))
+    }
 
-        url.query_pairs_mut()
-            .append_pair("client_id", &self.client_id)
-            .append_pair("scope", &self.scopes.join(" "))
-            .append_pair("response_type", "code");
+    /// Finishes a login started by [`Oauth2::login_url`], the `state` must match the one
+    /// bound to the browser by cookie.
+    pub async fn login_by_code(
+        &self,
+        provider: &str,
+        code: impl AsRef<str>,
+        state: &str,
+        req: &Request,
+    ) -> common::Result<LoginResult> {
+        let client = ClientInfo::of(req);
+        let (user, pending) = match self.provider_user(provider, code.as_ref(), state, req).await {
+            Ok(authenticated) => authenticated,
+            Err(e) => {
+                let entry = AuditEntry::anonymous(AuditAction::LoginFailed, provider)
+                    .details(json!({ "reason": e.to_string() }));
+                self.audit.record(entry, &client).await;
+                return Err(e);
+            }
+        };
 
-        if let Some(redirect_uri) = self.redirect_uri(req) {
-            url.query_pairs_mut()
-                .append_pair("redirect_uri", &redirect_uri);
-        }
+        let result = self
+            .sign_in(&user, &user.source, pending.workspace.as_deref())
+            .await?;
 
-        Ok(url)
+        let entry = AuditEntry::anonymous(AuditAction::Login, provider)
+            .actor(user.user_id(), user.name());
+        self.audit.record(entry, &client).await;
+        Ok(result)
     }
 
-    pub async fn login_by_github_code(&self, code: impl AsRef<str>) -> common::Result<String> {
-        let client = self.client.rest();
+    async fn provider_user(
+        &self,
+        provider: &str,
+        code: &str,
+        state: &str,
+        req: &Request,
+    ) -> common::Result<(ProviderUser, PendingLogin)> {
+        let cookie = state::state_cookie(req);
+        let pending = self.states.complete(provider, state, cookie.as_deref())?;
+
+        let provider = self.providers.get(provider)?;
+        let redirect_uri = self.redirect_uri(req, provider);
+
+        let user = provider
+            .authenticate(
+                self.client.rest(),
+                code,
+                &pending.verifier,
+                redirect_uri.as_deref(),
+            )
+            .await?;
+
+        Ok((user, pending))
+    }
 
-        let mut url = Url::parse("https://github.com/login/oauth/access_token")?;
+    /// Responds to the provider callback, redirecting to the frontend when configured.
+    fn callback_response(&self, result: LoginResult) -> CallbackResponse {
+        let cookie = state::clear_state_cookie();
+        if self.post_login_redirect.is_empty() {
+            CallbackResponse::Ok(Json(ResponseBody::ok(result)), cookie)
+        } else {
+            let location = format!(
+                "{}#token={}&refresh_token={}&expires_at={}",
+                self.post_login_redirect, result.token, result.refresh_token, result.expires_at
+            );
+            CallbackResponse::Redirect(location, cookie)
+        }
+    }
 
-        url.query_pairs_mut()
-            .append_pair("grant_type", "authorization_code")
-            .append_pair("client_id", &self.client_id)
-            .append_pair("client_secret", &self.client_secret)
-            .append_pair("code", code.as_ref());
+    /// Signs in with a local account, the config admin included.
+    pub async fn local_login(
+        &self,
+        username: impl AsRef<str>,
+        password: impl AsRef<str>,
+        workspace: Option<&str>,
+        client: &ClientInfo,
+    ) -> common::Result<LoginResult> {
+        let username = username.as_ref();
+        let account = match self.accounts.authenticate(username, password.as_ref()).await {
+            Ok(account) => account,
+            Err(e) => {
+                let entry = AuditEntry::anonymous(AuditAction::LoginFailed, username)
+                    .details(json!({ "reason": e.to_string() }));
+                self.audit.record(entry, client).await;
+                return Err(e);
+            }
+        };
 
-        let result = client
-            .post(url)
-            .send()
-            .await
-            .location("get access_token", Location::caller())?
-            .json::<AccessTokenResult>()
-            .await
-            .location("get access_token parse json failed", Location::caller())?;
+        let result = self.sign_in(&account, "local", workspace).await?;
 
-        let user = client
-            .get("https://api.github.com/user")
-            .bearer_auth(result.access_token)
-            .send()
-            .await
-            .location("get user info", Location::caller())?
-            .json::<AuthedGithubUser>()
-            .await
-            .location("get access_token parse json failed", Location::caller())?;
+        let entry = AuditEntry::anonymous(AuditAction::Login, username)
+            .actor(account.user_id(), account.name());
+        self.audit.record(entry, client).await;
+        Ok(result)
+    }
 
+    /// Creates the user with a personal workspace on first login, then starts a session
+    /// in the requested workspace (or the default one of the user).
+    pub(crate) async fn sign_in(
+        &self,
+        user: &impl AuthedUser,
+        source: &str,
+        workspace: Option<&str>,
+    ) -> common::Result<LoginResult> {
         let user_id = user.user_id();
         let name = user.name();
         if !self.service.exists_by_id(user_id.as_ref()).await? {
-            let new_user = NewUser::new(user_id.as_ref(), name.as_ref(), &user.email);
+            let new_user = NewUser::new(user_id.as_ref(), name.as_ref(), source);
             self.service.create_user(new_user).await?;
             info!("user:{} id:{} created", name, user_id);
         }
+        self.workspaces
+            .create_personal(user_id.as_ref(), name.as_ref())
+            .await?;
 
-        let claims = self.jwt.new_claims(name.into(), user_id.into());
+        let workspace = self.workspaces.resolve(user_id.as_ref(), workspace).await?;
 
-        let token = self.jwt.encode(&claims)?;
-
-        Ok(token)
-    }
-
-    pub async fn admin_login(&self, name: impl AsRef<str>, pass: impl AsRef<str>) -> common::Result<String> {
-        if self.admin_name == name.as_ref() && self.admin_pass == pass.as_ref() {
-            let user_id = "phi_super_admin";
-            let name = &self.admin_name;
-            if !self.service.exists_by_id(user_id).await? {
-                let new_user = NewUser::new(user_id, name, "buildin");
-                self.service.create_user(new_user).await?;
-                info!("user:{} id:{} created", name, user_id);
-            }
-
-            let claims = self.jwt.new_claims(name.into(), user_id.into());
-
-            let token = self.jwt.encode(&claims)?;
-            Ok(token)
-        } else {
-            Err(InvalidUsernameOrPassword)
-        }
+        self.sessions
+            .create(user_id.as_ref(), name.as_ref(), &workspace, source)
+            .await
     }
 }
 
@@ -216,62 +268,152 @@ pub(crate) struct LoginUrl {
 
 #[derive(Serialize, Deserialize, Debug, Object)]
 pub(crate) struct LoginResult {
-    token: String,
+    pub(crate) token: String,
+    /// single use, exchange it at `token_refresh` for a new pair
+    pub(crate) refresh_token: String,
+    /// expiry of `token` in seconds since epoch
+    pub(crate) expires_at: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub(crate) struct RefreshRequest {
+    refresh_token: String,
+    /// switch the session to another workspace of the user
+    workspace: Option<String>,
+}
+
+#[derive(ApiResponse)]
+pub(crate) enum LoginUrlResponse {
+    #[oai(status = 200)]
+    Ok(
+        Json<ResponseBody<LoginUrl>>,
+        #[oai(header = "Set-Cookie")] String,
+    ),
+}
+
+#[derive(ApiResponse)]
+pub(crate) enum CallbackResponse {
+    #[oai(status = 200)]
+    Ok(
+        Json<ResponseBody<LoginResult>>,
+        #[oai(header = "Set-Cookie")] String,
+    ),
+    /// redirect to `oauth.post-login-redirect` with the token in the url fragment
+    #[oai(status = 302)]
+    Redirect(
+        #[oai(header = "Location")] String,
+        #[oai(header = "Set-Cookie")] String,
+    ),
 }
 
 #[derive(Debug, Deserialize, Serialize, Object)]
 pub(crate) struct LoginRequest {
     username: String,
     password: String,
+    /// workspace to sign in to, defaults to the personal workspace
+    workspace: Option<String>,
 }
 
 #[mvc]
 #[OpenApi(prefix_path = "/api/auth", tag = PhiTags::Auth)]
 impl LoginMvc {
-    #[oai(path = "/oauth2_login_url/github", method = "get")]
-    async fn oauth2_login_url(&self, req: &Request) -> common::Result<common::Response<LoginUrl>> {
-        let url = self.oauth.login_url(req)?.to_string();
-        Ok(common::Response::ok(LoginUrl { url }))
+    /// Login url of the provider, `workspace` is the one to sign in to after the callback
+    #[oai(path = "/oauth2_login_url/:provider", method = "get", transform = "metered")]
+    async fn oauth2_login_url(
+        &self,
+        provider: Path<String>,
+        workspace: Query<Option<String>>,
+        req: &Request,
+    ) -> common::Result<LoginUrlResponse> {
+        let (url, state) = self.oauth.login_url(&provider, req, workspace.0).await?;
+        Ok(LoginUrlResponse::Ok(
+            Json(ResponseBody::ok(LoginUrl { url: url.to_string() })),
+            state::set_state_cookie(&state),
+        ))
+    }
+
+    #[oai(path = "/oauth2_login/:provider", method = "get", transform = "metered")]
+    async fn login_by_code(
+        &self,
+        provider: Path<String>,
+        code: Query<String>,
+        state: Query<String>,
+        req: &Request,
+        _limit: RateLimit<Login>,
+    ) -> common::Result<CallbackResponse> {
+        let result = self
+            .oauth
+            .login_by_code(&provider, &code.0, &state.0, req)
+            .await
+            .location("login failed", Location::caller())?;
+        Ok(self.oauth.callback_response(result))
     }
 
-    #[oai(path = "/oauth2_login", method = "get")]
+    /// Callback registered before multiple providers were supported, always GitHub
+    #[oai(path = "/oauth2_login", method = "get", transform = "metered")]
     async fn login_by_github_code(
         &self,
         code: Query<String>,
-    ) -> common::Result<common::Response<LoginResult>> {
-        let token = self
+        state: Query<String>,
+        req: &Request,
+        _limit: RateLimit<Login>,
+    ) -> common::Result<CallbackResponse> {
+        let result = self
             .oauth
-            .login_by_github_code(&code.0)
+            .login_by_code("github", &code.0, &state.0, req)
             .await
             .location("login failed", Location::caller())?;
-        Ok(common::Response::ok(LoginResult { token }))
+        Ok(self.oauth.callback_response(result))
     }
 
-    #[oai(path = "/admin_login", method = "post")]
-    async fn admin_login(
+    /// Login with a local account
+    #[oai(path = "/login", method = "post", transform = "metered")]
+    async fn login(
         &self,
+        _limit: RateLimit<Login>,
         request: Json<LoginRequest>,
+        client: ClientInfo,
     ) -> common::Result<common::Response<LoginResult>> {
-        let token = self
+        let result = self
             .oauth
-            .admin_login(&request.username, &request.password)
+            .local_login(
+                &request.username,
+                &request.password,
+                request.workspace.as_deref(),
+                &client,
+            )
             .await
             .location("login failed", Location::caller())?;
-        Ok(common::Response::ok(LoginResult { token }))
+        Ok(common::Response::ok(result))
     }
 
-    #[oai(path = "/token_refresh", method = "post")]
-    async fn token_refresh(&self, auth: JwtAuth) -> common::Result<common::Response<LoginResult>> {
-        let Claims { name, id, .. } = auth.into_inner();
-
-        let claims = self.jwt.new_claims(name, id);
+    /// Same as `/login`, kept for clients built for the config admin
+    #[oai(path = "/admin_login", method = "post", transform = "metered")]
+    async fn admin_login(
+        &self,
+        limit: RateLimit<Login>,
+        request: Json<LoginRequest>,
+        client: ClientInfo,
+    ) -> common::Result<common::Response<LoginResult>> {
+        self.login(limit, request, client).await
+    }
 
-        let token = self
-            .jwt.encode(&claims)
-            .location("login failed", Location::caller())?;
+    /// Exchange a refresh token for a new token pair, optionally switching to another workspace
+    /// of the user
+    #[oai(path = "/token_refresh", method = "post", transform = "metered")]
+    async fn token_refresh(
+        &self,
+        _limit: RateLimit<Login>,
+        request: Json<RefreshRequest>,
+    ) -> common::Result<common::Response<LoginResult>> {
+        let result = self
+            .sessions
+            .refresh(&request.refresh_token, request.workspace.as_deref())
+            .await
+            .location("refresh failed", Location::caller())?;
 
-        debug!("token {token} will expire at:{:#?}", DateTime::from_timestamp(claims.exp as i64, 0).map(|time| time.with_timezone(&Local)));
+        debug!("token will expire at:{:#?}", DateTime::from_timestamp(result.expires_at as i64, 0).map(|time| time.with_timezone(&Local)));
 
-        Ok(common::Response::ok(LoginResult { token }))
+        Ok(common::Response::ok(result))
     }
 }
@@ -1,4 +1,8 @@
+pub mod account;
 pub mod apikey;
 pub mod jwt;
 pub mod login;
+pub mod provider;
+pub mod session;
+pub mod state;
 pub mod user;
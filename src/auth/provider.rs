@@ -0,0 +1,463 @@
+use crate::{
+    auth::{login::AuthedUser, state::Authorization},
+    common::{self, AppError, LocationContext},
+};
+use cfg_rs::impl_enum;
+use ioc::{bean, BeanSpec, InitContext};
+use reqwest::{Client, Url};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{borrow::Cow, collections::HashMap, panic::Location};
+use tokio::sync::OnceCell;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ProviderKind {
+    Github,
+    Gitlab,
+    Oidc,
+}
+
+impl_enum!(ProviderKind {
+    "github" => ProviderKind::Github
+    "gitlab" => ProviderKind::Gitlab
+    "oidc" => ProviderKind::Oidc
+});
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Endpoints {
+    #[serde(rename = "authorization_endpoint")]
+    authorization_url: String,
+    #[serde(rename = "token_endpoint")]
+    token_url: String,
+    #[serde(rename = "userinfo_endpoint")]
+    userinfo_url: String,
+}
+
+/// An OAuth2 identity provider configured under `oauth.provider.<name>`.
+pub(crate) struct Provider {
+    name: String,
+    kind: ProviderKind,
+    client_id: String,
+    client_secret: String,
+    scopes: Vec<String>,
+    /// prefix of the phi user id, keeps ids of different providers apart
+    id_prefix: String,
+    redirect_url: Option<String>,
+    /// OIDC issuer used for discovery when endpoints are not configured
+    issuer: Option<String>,
+    configured: Option<Endpoints>,
+    discovered: OnceCell<Endpoints>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct AccessTokenResult {
+    access_token: String,
+    #[serde(default)]
+    scope: String,
+    token_type: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GithubUser {
+    id: i64,
+    login: String,
+    name: Option<String>,
+    email: Option<String>,
+}
+
+impl AuthedUser for GithubUser {
+    fn user_id(&self) -> Cow<'_, str> {
+        Cow::Owned(self.id.to_string())
+    }
+
+    fn name(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self.name.as_deref().unwrap_or(&self.login))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GitlabUser {
+    id: i64,
+    username: String,
+    name: Option<String>,
+    email: Option<String>,
+}
+
+impl AuthedUser for GitlabUser {
+    fn user_id(&self) -> Cow<'_, str> {
+        Cow::Owned(self.id.to_string())
+    }
+
+    fn name(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self.name.as_deref().unwrap_or(&self.username))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct OidcUser {
+    sub: String,
+    name: Option<String>,
+    preferred_username: Option<String>,
+    email: Option<String>,
+}
+
+impl AuthedUser for OidcUser {
+    fn user_id(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.sub)
+    }
+
+    fn name(&self) -> Cow<'_, str> {
+        Cow::Borrowed(
+            self.name
+                .as_deref()
+                .or(self.preferred_username.as_deref())
+                .unwrap_or(&self.sub),
+        )
+    }
+}
+
+/// A user authenticated by a provider, with the provider's id prefix applied.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ProviderUser {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    /// email when the provider shares it, otherwise the provider name
+    pub(crate) source: String,
+}
+
+impl AuthedUser for ProviderUser {
+    fn user_id(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.id)
+    }
+
+    fn name(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.name)
+    }
+}
+
+impl Provider {
+    fn default_endpoints(kind: ProviderKind, base_url: Option<&str>) -> Option<Endpoints> {
+        match kind {
+            ProviderKind::Github => Some(Endpoints {
+                authorization_url: "https://github.com/login/oauth/authorize".to_string(),
+                token_url: "https://github.com/login/oauth/access_token".to_string(),
+                userinfo_url: "https://api.github.com/user".to_string(),
+            }),
+            ProviderKind::Gitlab => {
+                let base_url = base_url.unwrap_or("https://gitlab.com").trim_end_matches('/');
+                Some(Endpoints {
+                    authorization_url: format!("{base_url}/oauth/authorize"),
+                    token_url: format!("{base_url}/oauth/token"),
+                    userinfo_url: format!("{base_url}/api/v4/user"),
+                })
+            }
+            ProviderKind::Oidc => None,
+        }
+    }
+
+    fn load(ctx: &mut impl InitContext, name: &str) -> ioc::Result<Self> {
+        let key = |field: &str| format!("oauth.provider.{name}.{field}");
+
+        let kind = ctx.get_config::<ProviderKind>(&key("kind"))?;
+        let base_url = ctx.get_config::<Option<String>>(&key("base-url"))?;
+        let authorization_url = ctx.get_config::<Option<String>>(&key("authorization-url"))?;
+        let token_url = ctx.get_config::<Option<String>>(&key("token-url"))?;
+        let userinfo_url = ctx.get_config::<Option<String>>(&key("userinfo-url"))?;
+
+        let defaults = Self::default_endpoints(kind, base_url.as_deref());
+        let configured = match (authorization_url, token_url, userinfo_url) {
+            (Some(authorization_url), Some(token_url), Some(userinfo_url)) => Some(Endpoints {
+                authorization_url,
+                token_url,
+                userinfo_url,
+            }),
+            (authorization_url, token_url, userinfo_url) => defaults.map(|defaults| Endpoints {
+                authorization_url: authorization_url.unwrap_or(defaults.authorization_url),
+                token_url: token_url.unwrap_or(defaults.token_url),
+                userinfo_url: userinfo_url.unwrap_or(defaults.userinfo_url),
+            }),
+        };
+
+        let issuer = ctx.get_config::<Option<String>>(&key("issuer"))?;
+        if configured.is_none() && issuer.is_none() {
+            return Err(ioc::IocError::Other(anyhow::anyhow!(
+                "oauth provider `{name}` needs either `issuer` or all endpoint urls"
+            )));
+        }
+
+        let default_prefix = match kind {
+            ProviderKind::Github => "gh_".to_string(),
+            _ => format!("{name}_"),
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            kind,
+            client_id: ctx.get_config::<String>(&key("client-id"))?,
+            client_secret: ctx.get_config::<String>(&key("client-secret"))?,
+            scopes: ctx.get_config::<Option<Vec<String>>>(&key("scopes"))?.unwrap_or_default(),
+            id_prefix: ctx
+                .get_config::<Option<String>>(&key("id-prefix"))?
+                .unwrap_or(default_prefix),
+            redirect_url: ctx.get_config::<Option<String>>(&key("redirect-url"))?,
+            issuer,
+            configured,
+            discovered: OnceCell::new(),
+        })
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn redirect_url(&self) -> Option<&str> {
+        self.redirect_url.as_deref()
+    }
+
+    async fn endpoints(&self, client: &Client) -> common::Result<&Endpoints> {
+        if let Some(ref endpoints) = self.configured {
+            return Ok(endpoints);
+        }
+        self.discovered
+            .get_or_try_init(|| async {
+                let issuer = self.issuer.as_deref().unwrap_or_default().trim_end_matches('/');
+                let endpoints = client
+                    .get(format!("{issuer}/.well-known/openid-configuration"))
+                    .send()
+                    .await
+                    .location("oidc discovery", Location::caller())?
+                    .error_for_status()
+                    .location("oidc discovery", Location::caller())?
+                    .json::<Endpoints>()
+                    .await
+                    .location("oidc discovery parse json failed", Location::caller())?;
+                Ok::<_, AppError>(endpoints)
+            })
+            .await
+    }
+
+    pub(crate) async fn login_url(
+        &self,
+        client: &Client,
+        redirect_uri: Option<&str>,
+        authorization: &Authorization,
+    ) -> common::Result<Url> {
+        let endpoints = self.endpoints(client).await?;
+        let mut url = Url::parse(&endpoints.authorization_url)?;
+
+        url.query_pairs_mut()
+            .append_pair("client_id", &self.client_id)
+            .append_pair("scope", &self.scopes.join(" "))
+            .append_pair("response_type", "code")
+            .append_pair("state", &authorization.state)
+            .append_pair("code_challenge", &authorization.challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        if let Some(redirect_uri) = redirect_uri {
+            url.query_pairs_mut()
+                .append_pair("redirect_uri", redirect_uri);
+        }
+
+        Ok(url)
+    }
+
+    /// Exchanges the authorization code, proven by the PKCE `verifier`, and fetches the profile
+    /// of the user.
+    pub(crate) async fn authenticate(
+        &self,
+        client: &Client,
+        code: &str,
+        verifier: &str,
+        redirect_uri: Option<&str>,
+    ) -> common::Result<ProviderUser> {
+        let endpoints = self.endpoints(client).await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("client_id", self.client_id.as_str()),
+            ("client_secret", self.client_secret.as_str()),
+            ("code", code),
+            ("code_verifier", verifier),
+        ];
+        if let Some(redirect_uri) = redirect_uri {
+            form.push(("redirect_uri", redirect_uri));
+        }
+
+        let token = client
+            .post(&endpoints.token_url)
+            .form(&form)
+            .send()
+            .await
+            .location("get access_token", Location::caller())?
+            .error_for_status()
+            .location("get access_token", Location::caller())?
+            .json::<AccessTokenResult>()
+            .await
+            .location("get access_token parse json failed", Location::caller())?;
+
+        match self.kind {
+            ProviderKind::Github => {
+                let user: GithubUser = self.user_info(client, endpoints, &token).await?;
+                Ok(self.provider_user(&user, user.email.as_deref()))
+            }
+            ProviderKind::Gitlab => {
+                let user: GitlabUser = self.user_info(client, endpoints, &token).await?;
+                Ok(self.provider_user(&user, user.email.as_deref()))
+            }
+            ProviderKind::Oidc => {
+                let user: OidcUser = self.user_info(client, endpoints, &token).await?;
+                Ok(self.provider_user(&user, user.email.as_deref()))
+            }
+        }
+    }
+
+    async fn user_info<U: DeserializeOwned>(
+        &self,
+        client: &Client,
+        endpoints: &Endpoints,
+        token: &AccessTokenResult,
+    ) -> common::Result<U> {
+        let user = client
+            .get(&endpoints.userinfo_url)
+            .bearer_auth(&token.access_token)
+            .send()
+            .await
+            .location("get user info", Location::caller())?
+            .error_for_status()
+            .location("get user info", Location::caller())?
+            .json::<U>()
+            .await
+            .location("get user info parse json failed", Location::caller())?;
+        Ok(user)
+    }
+
+    fn provider_user(&self, user: &impl AuthedUser, email: Option<&str>) -> ProviderUser {
+        ProviderUser {
+            id: format!("{}{}", self.id_prefix, user.user_id()),
+            name: user.name().into_owned(),
+            source: email.unwrap_or(&self.name).to_string(),
+        }
+    }
+}
+
+/// All providers listed in `oauth.providers`.
+pub(crate) struct OAuthProviders {
+    providers: HashMap<String, Provider>,
+}
+
+impl OAuthProviders {
+    pub(crate) fn get(&self, name: &str) -> common::Result<&Provider> {
+        self.providers
+            .get(name)
+            .ok_or_else(|| AppError::UnknownOAuthProvider(name.to_string()))
+    }
+}
+
+#[bean]
+impl BeanSpec for OAuthProviders {
+    type Bean = Self;
+
+    fn build(ctx: &mut impl InitContext) -> ioc::Result<Self::Bean> {
+        let names = ctx.get_config::<Vec<String>>("oauth.providers")?;
+
+        let mut providers = HashMap::with_capacity(names.len());
+        for name in names {
+            let provider = Provider::load(ctx, &name)?;
+            providers.insert(name, provider);
+        }
+
+        Ok(Self { providers })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::mock;
+    use poem::{get, handler, post, web::{Form, Json}, Route};
+    use std::collections::HashMap;
+    use serde_json::{json, Value};
+
+    #[handler]
+    fn userinfo() -> Json<Value> {
+        Json(json!({"sub": "42", "preferred_username": "alice", "email": "alice@example.com"}))
+    }
+
+    #[handler]
+    fn token(Form(form): Form<HashMap<String, String>>) -> Json<Value> {
+        // rfc 7636 appendix B pair
+        let pkce = form.get("code_verifier").map(String::as_str)
+            == Some("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk");
+        if pkce {
+            Json(json!({"access_token": "secret", "token_type": "Bearer"}))
+        } else {
+            Json(json!({"error": "invalid_grant"}))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oidc_discovery_and_login() -> anyhow::Result<()> {
+        let issuer = std::sync::Arc::new(tokio::sync::OnceCell::new());
+        let discovery_issuer = issuer.clone();
+        let app = Route::new()
+            .at(
+                "/.well-known/openid-configuration",
+                get(poem::endpoint::make(move |_| {
+                    let issuer: String = discovery_issuer.get().cloned().unwrap_or_default();
+                    async move {
+                        Json(json!({
+                            "authorization_endpoint": format!("{issuer}/authorize"),
+                            "token_endpoint": format!("{issuer}/token"),
+                            "userinfo_endpoint": format!("{issuer}/userinfo"),
+                        }))
+                    }
+                })),
+            )
+            .at("/token", post(token))
+            .at("/userinfo", get(userinfo));
+        let base_url = mock::serve(app).await?;
+        // discovery answers with urls of the stand-in itself, known only once it listens
+        issuer.set(base_url.clone())?;
+
+        let provider = Provider {
+            name: "corp".to_string(),
+            kind: ProviderKind::Oidc,
+            client_id: "phi".to_string(),
+            client_secret: "secret".to_string(),
+            scopes: vec!["openid".to_string(), "profile".to_string()],
+            id_prefix: "corp_".to_string(),
+            redirect_url: None,
+            issuer: Some(base_url.clone()),
+            configured: None,
+            discovered: OnceCell::new(),
+        };
+        let client = Client::new();
+
+        let authorization = Authorization {
+            state: "state".to_string(),
+            challenge: "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM".to_string(),
+        };
+        let url = provider
+            .login_url(&client, Some("http://localhost/cb"), &authorization)
+            .await?;
+        assert!(url.as_str().starts_with(&format!("{base_url}/authorize?client_id=phi")));
+        assert!(url.as_str().contains("code_challenge_method=S256"));
+
+        let user = provider
+            .authenticate(
+                &client,
+                "code",
+                "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk",
+                Some("http://localhost/cb"),
+            )
+            .await?;
+        assert_eq!(
+            user,
+            ProviderUser {
+                id: "corp_42".to_string(),
+                name: "alice".to_string(),
+                source: "alice@example.com".to_string(),
+            }
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,273 @@
+use crate::{
+    auth::{apikey::JwtAuth, jwt::JwtService, login::LoginResult, state},
+    common::{AppError, PhiTags, Response, Result},
+    db::Db,
+    metrics::metered,
+    workspace::biz::WorkspaceService,
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use ioc::{mvc, Bean};
+use poem_openapi::{param::Path, Object, OpenApi};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use tracing::warn;
+use uuid::Uuid;
+
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Object)]
+pub(crate) struct Session {
+    id: String,
+    workspace_id: String,
+    /// how the session was signed in, e.g. `github` or `local`
+    source: Option<String>,
+    created_at: NaiveDateTime,
+    last_used_at: NaiveDateTime,
+    expires_at: NaiveDateTime,
+    /// the session of the token making the request
+    current: bool,
+}
+
+#[derive(sqlx::FromRow)]
+struct RefreshRow {
+    id: String,
+    user_id: String,
+    name: String,
+    workspace_id: String,
+    expires_at: NaiveDateTime,
+    revoked_at: Option<NaiveDateTime>,
+}
+
+/// Server side sessions backing access tokens with rotating refresh tokens.
+#[derive(Bean)]
+pub(crate) struct SessionService {
+    #[inject(bean = Db)]
+    db: &'static SqlitePool,
+    #[inject(bean)]
+    jwt: &'static JwtService,
+    #[inject(bean)]
+    workspaces: &'static WorkspaceService,
+    #[inject(config = "jwt.refresh-expire-seconds")]
+    refresh_expire_seconds: i64,
+}
+
+impl SessionService {
+    fn issue(
+        &self,
+        name: String,
+        user_id: String,
+        workspace: String,
+        session: String,
+        refresh_token: String,
+    ) -> Result<LoginResult> {
+        let claims = self.jwt.new_claims(name, user_id, workspace, session);
+        let token = self.jwt.encode(&claims)?;
+        Ok(LoginResult {
+            token,
+            refresh_token,
+            expires_at: claims.exp,
+        })
+    }
+
+    /// Starts a session and issues its first token pair.
+    pub(crate) async fn create(
+        &self,
+        user_id: &str,
+        name: &str,
+        workspace: &str,
+        source: &str,
+    ) -> Result<LoginResult> {
+        let id = Uuid::new_v4().to_string();
+        let refresh_token = state::random_token();
+        let refresh_hash = state::token_hash(&refresh_token);
+        let now = Utc::now().naive_utc();
+        let expires_at = now + Duration::seconds(self.refresh_expire_seconds);
+
+        sqlx::query!(
+            r#"
+            INSERT INTO sessions (id, user_id, workspace_id, source, refresh_hash, created_at, last_used_at, expires_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6, ?7)
+            "#,
+            id,
+            user_id,
+            workspace,
+            source,
+            refresh_hash,
+            now,
+            expires_at
+        )
+            .execute(self.db)
+            .await?;
+
+        self.issue(name.to_string(), user_id.to_string(), workspace.to_string(), id, refresh_token)
+    }
+
+    /// Rotates the refresh token, optionally switching workspace. Presenting a refresh token that
+    /// was already rotated revokes the whole session, as it has likely been stolen.
+    pub(crate) async fn refresh(&self, refresh_token: &str, workspace: Option<&str>) -> Result<LoginResult> {
+        let hash = state::token_hash(refresh_token);
+        let now = Utc::now().naive_utc();
+
+        let row: Option<RefreshRow> = sqlx::query_as(
+            r#"
+            SELECT s.id, s.user_id, u.name, s.workspace_id, s.expires_at, s.revoked_at
+            FROM sessions s
+                     JOIN users u ON u.id = s.user_id
+            WHERE s.refresh_hash = ?
+            "#,
+        )
+            .bind(&hash)
+            .fetch_optional(self.db)
+            .await?;
+
+        let Some(row) = row else {
+            let reused = sqlx::query!(
+                "UPDATE sessions SET revoked_at = ? WHERE previous_refresh_hash = ? AND revoked_at IS NULL",
+                now,
+                hash
+            )
+                .execute(self.db)
+                .await?;
+            if reused.rows_affected() > 0 {
+                warn!("refresh token reused, session revoked");
+            }
+            return Err(AppError::InvalidRefreshToken);
+        };
+
+        if row.revoked_at.is_some() || row.expires_at <= now {
+            return Err(AppError::InvalidRefreshToken);
+        }
+
+        let workspace = self
+            .workspaces
+            .resolve(&row.user_id, Some(workspace.unwrap_or(&row.workspace_id)))
+            .await?;
+
+        let refresh_token = state::random_token();
+        let refresh_hash = state::token_hash(&refresh_token);
+        let expires_at = now + Duration::seconds(self.refresh_expire_seconds);
+
+        let rotated = sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET refresh_hash = ?, previous_refresh_hash = refresh_hash, workspace_id = ?, last_used_at = ?, expires_at = ?
+            WHERE id = ? AND refresh_hash = ?
+            "#,
+            refresh_hash,
+            workspace,
+            now,
+            expires_at,
+            row.id,
+            hash
+        )
+            .execute(self.db)
+            .await?;
+
+        // lost a race with a concurrent refresh of the same token
+        if rotated.rows_affected() == 0 {
+            return Err(AppError::InvalidRefreshToken);
+        }
+
+        self.issue(row.name, row.user_id, workspace, row.id, refresh_token)
+    }
+
+    /// Whether tokens of the session are still accepted.
+    pub(crate) async fn is_active(&self, id: &str) -> Result<bool> {
+        let now = Utc::now().naive_utc();
+        let count: i64 = sqlx::query_scalar(
+            "SELECT COUNT(1) FROM sessions WHERE id = ? AND revoked_at IS NULL AND expires_at > ?",
+        )
+            .bind(id)
+            .bind(now)
+            .fetch_one(self.db)
+            .await?;
+        Ok(count > 0)
+    }
+
+    pub(crate) async fn list(&self, user_id: &str, current: &str) -> Result<Vec<Session>> {
+        let now = Utc::now().naive_utc();
+        let sessions = sqlx::query_as(
+            r#"
+            SELECT id, workspace_id, source, created_at, last_used_at, expires_at, id = ? AS current
+            FROM sessions
+            WHERE user_id = ? AND revoked_at IS NULL AND expires_at > ?
+            ORDER BY last_used_at DESC
+            "#,
+        )
+            .bind(current)
+            .bind(user_id)
+            .bind(now)
+            .fetch_all(self.db)
+            .await?;
+        Ok(sessions)
+    }
+
+    pub(crate) async fn revoke(&self, id: &str, user_id: &str) -> Result<()> {
+        let now = Utc::now().naive_utc();
+        let result = sqlx::query!(
+            "UPDATE sessions SET revoked_at = ? WHERE id = ? AND user_id = ? AND revoked_at IS NULL",
+            now,
+            id,
+            user_id
+        )
+            .execute(self.db)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::SessionNotFound(id.to_string()));
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn revoke_all(&self, user_id: &str) -> Result<u64> {
+        let now = Utc::now().naive_utc();
+        let result = sqlx::query!(
+            "UPDATE sessions SET revoked_at = ? WHERE user_id = ? AND revoked_at IS NULL",
+            now,
+            user_id
+        )
+            .execute(self.db)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub(crate) struct RevokedSessions {
+    revoked: u64,
+}
+
+#[derive(Bean)]
+pub(crate) struct SessionMvc {
+    #[inject(bean)]
+    sessions: &'static SessionService,
+}
+
+#[mvc]
+#[OpenApi(prefix_path = "/api/auth", tag = PhiTags::Auth)]
+impl SessionMvc {
+    /// Revokes the session of the token
+    #[oai(path = "/logout", method = "post", transform = "metered")]
+    async fn logout(&self, auth: JwtAuth) -> Result<Response<String>> {
+        self.sessions.revoke(&auth.jti, &auth.id).await?;
+        Ok(Response::ok("ok".to_string()))
+    }
+
+    /// Active sessions of the caller
+    #[oai(path = "/sessions", method = "get", transform = "metered")]
+    async fn list(&self, auth: JwtAuth) -> Result<Response<Vec<Session>>> {
+        let sessions = self.sessions.list(&auth.id, &auth.jti).await?;
+        Ok(Response::ok(sessions))
+    }
+
+    #[oai(path = "/sessions/:id", method = "delete", transform = "metered")]
+    async fn revoke(&self, id: Path<String>, auth: JwtAuth) -> Result<Response<String>> {
+        self.sessions.revoke(&id, &auth.id).await?;
+        Ok(Response::ok("ok".to_string()))
+    }
+
+    /// Signs out everywhere, the calling session included
+    #[oai(path = "/sessions", method = "delete", transform = "metered")]
+    async fn revoke_all(&self, auth: JwtAuth) -> Result<Response<RevokedSessions>> {
+        let revoked = self.sessions.revoke_all(&auth.id).await?;
+        Ok(Response::ok(RevokedSessions { revoked }))
+    }
+}
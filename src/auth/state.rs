@@ -0,0 +1,169 @@
+use crate::common::{AppError, Result};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use ioc::{bean, BeanSpec, InitContext};
+use poem::Request;
+use rand::{thread_rng, RngCore};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Cookie binding a pending OAuth login to the browser that started it.
+pub(crate) const STATE_COOKIE: &str = "phi_oauth_state";
+
+const STATE_TTL: Duration = Duration::from_secs(600);
+
+/// A login started by `oauth2_login_url`, waiting for the provider callback.
+pub(crate) struct PendingLogin {
+    pub(crate) provider: String,
+    /// PKCE code verifier, sent with the token exchange
+    pub(crate) verifier: String,
+    pub(crate) workspace: Option<String>,
+    created_at: Instant,
+}
+
+pub(crate) struct Authorization {
+    pub(crate) state: String,
+    /// PKCE S256 code challenge of the verifier
+    pub(crate) challenge: String,
+}
+
+/// Server side store of OAuth `state` values and their PKCE verifiers.
+pub(crate) struct OAuthStates {
+    pending: Mutex<HashMap<String, PendingLogin>>,
+}
+
+#[bean]
+impl BeanSpec for OAuthStates {
+    type Bean = Self;
+
+    fn build(_: &mut impl InitContext) -> ioc::Result<Self::Bean> {
+        Ok(Self {
+            pending: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+pub(crate) fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    thread_rng().fill_bytes(&mut bytes);
+    Base64UrlUnpadded::encode_string(&bytes)
+}
+
+/// Digest stored in place of a bearer secret such as a refresh or reset token.
+pub(crate) fn token_hash(token: &str) -> String {
+    Base64UrlUnpadded::encode_string(&Sha256::digest(token.as_bytes()))
+}
+
+pub(crate) fn challenge(verifier: &str) -> String {
+    Base64UrlUnpadded::encode_string(&Sha256::digest(verifier.as_bytes()))
+}
+
+/// Value of the state cookie sent by the browser.
+pub(crate) fn state_cookie(req: &Request) -> Option<String> {
+    req.headers()
+        .get_all(http::header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == STATE_COOKIE)
+        .map(|(_, value)| value.to_string())
+}
+
+pub(crate) fn set_state_cookie(state: &str) -> String {
+    format!(
+        "{STATE_COOKIE}={state}; Path=/api/auth; Max-Age={}; HttpOnly; SameSite=Lax",
+        STATE_TTL.as_secs()
+    )
+}
+
+pub(crate) fn clear_state_cookie() -> String {
+    format!("{STATE_COOKIE}=; Path=/api/auth; Max-Age=0; HttpOnly; SameSite=Lax")
+}
+
+impl OAuthStates {
+    pub(crate) fn begin(&self, provider: &str, workspace: Option<String>) -> Authorization {
+        let state = random_token();
+        let verifier = random_token();
+        let challenge = challenge(&verifier);
+
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        pending.retain(|_, login| login.created_at.elapsed() < STATE_TTL);
+        pending.insert(
+            state.clone(),
+            PendingLogin {
+                provider: provider.to_string(),
+                verifier,
+                workspace,
+                created_at: Instant::now(),
+            },
+        );
+
+        Authorization { state, challenge }
+    }
+
+    /// Consumes the pending login of `state`; it must come from the same browser and provider
+    /// and be used only once.
+    pub(crate) fn complete(&self, provider: &str, state: &str, cookie: Option<&str>) -> Result<PendingLogin> {
+        if cookie != Some(state) {
+            return Err(AppError::InvalidOAuthState);
+        }
+
+        let login = self
+            .pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(state)
+            .ok_or(AppError::InvalidOAuthState)?;
+
+        if login.provider != provider || login.created_at.elapsed() >= STATE_TTL {
+            return Err(AppError::InvalidOAuthState);
+        }
+
+        Ok(login)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn states() -> OAuthStates {
+        OAuthStates {
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    #[test]
+    fn test_pkce_challenge() {
+        // RFC 7636 appendix B
+        assert_eq!(
+            challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn test_state_is_bound_and_single_use() {
+        let states = states();
+        let auth = states.begin("github", None);
+
+        assert!(states.complete("github", &auth.state, Some("other")).is_err());
+
+        let login = states.complete("github", &auth.state, Some(&auth.state)).unwrap();
+        assert_eq!(challenge(&login.verifier), auth.challenge);
+
+        assert!(states.complete("github", &auth.state, Some(&auth.state)).is_err());
+    }
+
+    #[test]
+    fn test_state_is_bound_to_provider() {
+        let states = states();
+        let auth = states.begin("github", None);
+
+        assert!(states.complete("corp", &auth.state, Some(&auth.state)).is_err());
+    }
+}
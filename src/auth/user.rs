@@ -7,6 +7,7 @@ use std::borrow::Cow;
 
 use crate::common::Response;
 use crate::db::Db;
+use crate::metrics::metered;
 
 #[derive(Bean)]
 pub struct UserRepo {
@@ -16,7 +17,7 @@ pub struct UserRepo {
 
 #[mvc]
 impl UserRepo {
-    #[oai(path = "/manager/users", method = "get")]
+    #[oai(path = "/manager/users", method = "get", transform = "metered")]
     async fn users(&self) -> crate::common::Result<Response<Vec<User>>> {
         let result: Vec<User> = sqlx::query_as("SELECT id, name, source, created_at FROM users")
             .fetch_all(self.db)
@@ -0,0 +1,345 @@
+//! Admin subcommands of the `phi` binary. They initialise the container like the server does,
+//! from the same configuration, and use its beans.
+
+use std::{
+    io::{self, BufRead, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use chrono::NaiveDateTime;
+use clap::Subcommand;
+use ioc::{run, BeanSpec};
+use tokio::sync::mpsc::channel;
+
+use crate::{
+    auth::{
+        account::{AccountService, ADMIN_ID},
+        jwt::Claims,
+    },
+    common::{FormatedEvent, Result},
+    db::Db,
+    ffmpeg,
+    material::{
+        biz::MaterialsService,
+        import::{self, ImportOptions, ImportRecord, Report},
+        storage::Id,
+    },
+    webhook::biz::WebhookService,
+    workspace::{biz::WorkspaceService, WorkspaceRole},
+};
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Run the server, the default without a command
+    Serve,
+    /// Apply pending database migrations
+    Migrate,
+    /// Manage users
+    User {
+        #[command(subcommand)]
+        command: UserCommand,
+    },
+    /// Manage materials
+    Material {
+        #[command(subcommand)]
+        command: MaterialCommand,
+    },
+    /// Manage the storage directory
+    Storage {
+        #[command(subcommand)]
+        command: StorageCommand,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Manage the ffmpeg binaries used for transcoding
+    Ffmpeg {
+        #[command(subcommand)]
+        command: FfmpegCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum UserCommand {
+    /// List all users
+    List,
+    /// Create a local account and its personal workspace
+    Create {
+        username: String,
+        /// Display name, defaults to the username
+        #[arg(long)]
+        name: Option<String>,
+        /// Password, read from stdin when omitted
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Set the role of a user in a workspace
+    SetRole {
+        /// User id
+        user: String,
+        /// Workspace id
+        workspace: String,
+        /// One of viewer, editor, admin, owner
+        role: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum MaterialCommand {
+    /// Import the videos and images of a directory, recursively, or the files of a CSV manifest
+    /// with the columns `path,name,description,tags`
+    Import {
+        source: PathBuf,
+        /// User id the materials are created by
+        #[arg(long)]
+        user: String,
+        /// Workspace id, defaults to the personal workspace of the user
+        #[arg(long)]
+        workspace: Option<String>,
+        /// Tags added to every imported material
+        #[arg(long)]
+        tag: Vec<String>,
+        /// Hard link the files into the storage instead of copying them
+        #[arg(long)]
+        link: bool,
+        /// Report of the import, running again with the same report resumes it
+        #[arg(long, default_value = "import-report.jsonl")]
+        report: PathBuf,
+    },
+    /// Transcode a video again, e.g. after a failed transcode
+    Reprocess { id: String },
+    /// Fill in size and duration of materials stored before they were recorded, so sorting by
+    /// them doesn't lump these materials together at one end
+    Backfill,
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum StorageCommand {
+    /// Remove stored files of materials that no longer exist
+    Gc {
+        /// Only list what would be removed
+        #[arg(long)]
+        dry_run: bool,
+        /// Keep directories modified within this many hours, they may belong to running uploads
+        #[arg(long, default_value = "24")]
+        min_age_hours: u64,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum ConfigCommand {
+    /// Validate the configuration and the ffmpeg binaries by building every bean of the server,
+    /// without starting it
+    Check,
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum FfmpegCommand {
+    /// Download the latest ffmpeg release
+    Install {
+        /// Directory to install into, should match `ffmpeg.sidecar_parent`
+        #[arg(long, default_value = "x64")]
+        dir: PathBuf,
+    },
+}
+
+impl Command {
+    /// Runs a command other than [`Command::Serve`], on the beans of a container without the web
+    /// server.
+    pub(crate) fn run(self, config_dir: &str, profile: &str) -> Result<()> {
+        if let Command::Ffmpeg {
+            command: FfmpegCommand::Install { dir },
+        } = self
+        {
+            let version = ffmpeg::common::install(&dir)?;
+            println!("ffmpeg {version} installed into {}", dir.display());
+            return Ok(());
+        }
+
+        // beans are built outside of a runtime, like on server startup; the web server is a
+        // bean of the ioc crate, which is left out
+        run!(
+            debug = false;
+            dir = config_dir;
+            profile = profile;
+        )?;
+        tokio::runtime::Runtime::new()?.block_on(async {
+            // events of a failed command were still raised, the loop of the deliveries dies
+            // with the process
+            let result = self.exec().await;
+            WebhookService::get().drain().await;
+            result
+        })
+    }
+
+    async fn exec(self) -> Result<()> {
+        match self {
+            Command::Serve | Command::Ffmpeg { .. } => unreachable!("handled by main"),
+            // by `Db`, when the container was initialised
+            Command::Migrate => println!("database migrated"),
+            Command::User { command } => command.exec().await?,
+            Command::Material { command } => command.exec().await?,
+            Command::Storage { command } => command.exec().await?,
+            Command::Config { command } => command.exec(),
+        }
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct UserRow {
+    id: String,
+    name: String,
+    source: Option<String>,
+    username: Option<String>,
+    created_at: NaiveDateTime,
+}
+
+fn read_password() -> Result<String> {
+    print!("password: ");
+    io::stdout().flush()?;
+    let mut password = String::new();
+    io::stdin().lock().read_line(&mut password)?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+impl UserCommand {
+    async fn exec(self) -> Result<()> {
+        let db = Db::get();
+        match self {
+            UserCommand::List => {
+                let users: Vec<UserRow> = sqlx::query_as(
+                    "SELECT id, name, source, username, created_at FROM users ORDER BY created_at",
+                )
+                    .fetch_all(db)
+                    .await?;
+
+                for user in users {
+                    let admin = if user.id == ADMIN_ID { " (admin)" } else { "" };
+                    println!(
+                        "{}\t{}\t{}\t{}\t{}{admin}",
+                        user.id,
+                        user.username.as_deref().unwrap_or("-"),
+                        user.name,
+                        user.source.as_deref().unwrap_or("-"),
+                        user.created_at
+                    );
+                }
+            }
+            UserCommand::Create { username, name, password } => {
+                let password = match password {
+                    Some(password) => password,
+                    None => read_password()?,
+                };
+                let name = name.unwrap_or_else(|| username.clone());
+                let account = AccountService::get().create_local(&username, name.clone(), &password).await?;
+                WorkspaceService::get().create_personal(account.id(), &name).await?;
+                println!("created user {}", account.id());
+            }
+            UserCommand::SetRole { user, workspace, role } => {
+                let role = WorkspaceRole::from_value(&role)
+                    .ok_or_else(|| anyhow::anyhow!("unknown role `{role}`"))?;
+                let exists: i64 = sqlx::query_scalar("SELECT COUNT(1) FROM workspaces WHERE id = ?")
+                    .bind(&workspace)
+                    .fetch_one(db)
+                    .await?;
+                if exists == 0 {
+                    Err(anyhow::anyhow!("workspace {workspace} not found"))?;
+                }
+
+                WorkspaceService::get().grant(&workspace, &user, role).await?;
+                println!("{user} is {} of {workspace}", role.value());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MaterialCommand {
+    async fn exec(self) -> Result<()> {
+        let materials = MaterialsService::get();
+        match self {
+            MaterialCommand::Import { source, user, workspace, tag, link, report } => {
+                let workspaces = WorkspaceService::get();
+                let workspace = workspaces.resolve(&user, workspace.as_deref()).await?;
+                workspaces.require_role(&workspace, &user, WorkspaceRole::Editor).await?;
+                let claims = Claims {
+                    name: user.clone(),
+                    id: user,
+                    workspace,
+                    jti: String::new(),
+                    exp: 0,
+                };
+
+                let (records, mut rx) = channel::<ImportRecord>(64);
+                let printer = tokio::spawn(async move {
+                    while let Some(record) = rx.recv().await {
+                        let id = record.id.as_deref().unwrap_or("-");
+                        let error = record.error.as_deref().unwrap_or_default();
+                        println!("{:?}\t{id}\t{}\t{error}", record.status, record.path);
+                    }
+                });
+
+                let files = import::files(&source).await?;
+                let options = ImportOptions { link, tags: tag };
+                let opened = Report::open(&report).await?;
+                let summary = import::import(materials, files, opened, &options, &claims, &records).await?;
+                drop(records);
+                printer.await?;
+                println!(
+                    "imported {}, duplicates {}, failed {}, skipped {}; see {}",
+                    summary.imported,
+                    summary.duplicates,
+                    summary.failed,
+                    summary.skipped,
+                    report.display()
+                );
+            }
+            MaterialCommand::Reprocess { id } => {
+                // progress is only of interest to sse clients, here the command waits for the result
+                let (tx, mut rx) = channel::<FormatedEvent>(64);
+                tokio::spawn(async move { while rx.recv().await.is_some() {} });
+                materials.reprocess(&Id(id.clone()), &tx).await?;
+                println!("reprocessed {id}");
+            }
+            MaterialCommand::Backfill => {
+                let filled = materials.backfill_media().await?;
+                for id in &filled {
+                    println!("{id}");
+                }
+                println!("filled in {} materials", filled.len());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StorageCommand {
+    async fn exec(self) -> Result<()> {
+        match self {
+            StorageCommand::Gc { dry_run, min_age_hours } => {
+                let min_age = Duration::from_secs(min_age_hours * 3600);
+                let removed = MaterialsService::get().storage_gc(min_age, dry_run).await?;
+                for path in &removed {
+                    println!("{}", path.display());
+                }
+                let verb = if dry_run { "would remove" } else { "removed" };
+                println!("{verb} {} directories", removed.len());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ConfigCommand {
+    /// Runs once the container was initialised, so every bean of the server found its
+    /// configuration, including `ffmpeg` with the version and encoder checks of startup.
+    fn exec(self) {
+        match self {
+            ConfigCommand::Check => println!("config ok"),
+        }
+    }
+}
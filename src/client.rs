@@ -1,24 +1,26 @@
-use crate::common::LocationContext;
+use crate::common::{AppError, LocationContext, Result};
 use http::HeaderValue;
 use ioc::{bean, BeanSpec, InitContext};
-use reqwest::header::{HeaderMap, ACCEPT};
-use std::panic::Location;
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    header::{HeaderMap, ACCEPT},
+    redirect::{Attempt, Policy},
+};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    panic::Location,
+    sync::Arc,
+};
+use tokio::net::lookup_host;
+use url::{Host, Url};
 
 pub(crate) struct HttpClient {
     client: reqwest::Client,
 }
 
 impl HttpClient {
-    pub(crate) fn rest(&self) -> &reqwest::Client {
-        &self.client
-    }
-}
-
-#[bean]
-impl BeanSpec for HttpClient {
-    type Bean = HttpClient;
-
-    fn build(_: &mut impl InitContext) -> ioc::Result<Self::Bean> {
+    pub(crate) fn new() -> anyhow::Result<Self> {
         let mut headers = HeaderMap::new();
         headers.insert(
             reqwest::header::USER_AGENT,
@@ -31,6 +33,198 @@ impl BeanSpec for HttpClient {
             .build()
             .location("build http client", Location::caller())?;
 
-        Ok(Self::Bean { client })
+        Ok(Self { client })
+    }
+
+    pub(crate) fn rest(&self) -> &reqwest::Client {
+        &self.client
+    }
+}
+
+#[bean]
+impl BeanSpec for HttpClient {
+    type Bean = HttpClient;
+
+    fn build(_: &mut impl InitContext) -> ioc::Result<Self::Bean> {
+        Ok(Self::new()?)
+    }
+}
+
+/// Whether `ip` is reachable on the internet, as opposed to loopback, private or link local
+/// addresses of the server's own network, multicast or reserved ones.
+pub(crate) fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                // "this network" 0.0.0.0/8
+                || a == 0
+                // multicast 224.0.0.0/4, reserved 240.0.0.0/4 and broadcast
+                || a >= 224
+                // shared address space of carrier-grade nat
+                || (a == 100 && (64..128).contains(&b))
+                // benchmarking 198.18.0.0/15
+                || (a == 198 && (b & 0xfe) == 18))
+        }
+        IpAddr::V6(ip) => match embedded_ipv4(ip) {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // unique local fc00::/7 and link local fe80::/10
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// The ipv4 address an ipv6 one stands for: mapped ::ffff:0:0/96, nat64 64:ff9b::/96 or 6to4
+/// 2002::/16. Connecting to these reaches the ipv4 address.
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let segments = ip.segments();
+    let octets = ip.octets();
+    match segments {
+        [0x64, 0xff9b, 0, 0, 0, 0, ..] => Some(Ipv4Addr::new(octets[12], octets[13], octets[14], octets[15])),
+        [0x2002, ..] => Some(Ipv4Addr::new(octets[2], octets[3], octets[4], octets[5])),
+        _ => ip.to_ipv4_mapped(),
+    }
+}
+
+/// Whether every address `host` resolves to is public, see [`is_public`].
+pub(crate) async fn resolves_public(host: &str, port: u16) -> io::Result<bool> {
+    Ok(lookup_host((host, port)).await?.all(|addr| is_public(addr.ip())))
+}
+
+/// Parses a url given by a user, refusing schemes other than http(s) and, unless
+/// `allow_private_networks`, hosts resolving to addresses that aren't public. This fails early
+/// with a clear reason, clients of [`user_url_client`] check again when they connect.
+pub(crate) async fn check_user_url(url: &str, allow_private_networks: bool) -> Result<Url> {
+    let invalid = |reason: &str| AppError::InvalidRemoteUrl(format!("{url}: {reason}"));
+    let url = Url::parse(url).map_err(|e| invalid(&e.to_string()))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(invalid("only http and https are supported"));
+    }
+    let host = url.host_str().ok_or_else(|| invalid("no host"))?;
+    let port = url.port_or_known_default().unwrap_or(80);
+
+    if !allow_private_networks {
+        let public = resolves_public(host, port)
+            .await
+            .map_err(|e| invalid(&e.to_string()))?;
+        if !public {
+            return Err(invalid("resolves to a private address"));
+        }
+    }
+    Ok(url)
+}
+
+/// Resolver leaving out addresses that aren't public, so a client connects only to what it
+/// checked, even when the name resolves differently than at an earlier check.
+struct PublicResolver;
+
+impl PublicResolver {
+    async fn lookup(name: Name) -> std::result::Result<Addrs, Box<dyn std::error::Error + Send + Sync>> {
+        let addrs: Vec<SocketAddr> = lookup_host((name.as_str(), 0))
+            .await?
+            .filter(|addr| is_public(addr.ip()))
+            .collect();
+        if addrs.is_empty() {
+            let reason = format!("{} resolves to no public address", name.as_str());
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, reason).into());
+        }
+        Ok(Box::new(addrs.into_iter()))
+    }
+}
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(Self::lookup(name))
+    }
+}
+
+/// Follows up to 10 redirects to http(s) urls. Hosts given by name are left to the resolver,
+/// addresses are checked here since they are connected to without resolving.
+fn follow_public(attempt: Attempt) -> reqwest::redirect::Action {
+    let url = attempt.url();
+    let public = match url.host() {
+        Some(Host::Domain(_)) => true,
+        Some(Host::Ipv4(ip)) => is_public(IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) => is_public(IpAddr::V6(ip)),
+        None => false,
+    };
+    let refused = (!matches!(url.scheme(), "http" | "https") || !public)
+        .then(|| format!("redirect to {url} refused"));
+
+    if let Some(reason) = refused {
+        attempt.error(io::Error::new(io::ErrorKind::PermissionDenied, reason))
+    } else if attempt.previous().len() >= 10 {
+        attempt.error(io::Error::other("too many redirects"))
+    } else {
+        attempt.follow()
+    }
+}
+
+/// Client for urls given by users, like remote imports and webhooks. Unless
+/// `allow_private_networks`, it connects to public addresses only, following redirects to
+/// them when `follow_redirects`.
+pub(crate) fn user_url_client(
+    allow_private_networks: bool,
+    follow_redirects: bool,
+) -> anyhow::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder().user_agent("phi_server");
+    if allow_private_networks {
+        if !follow_redirects {
+            builder = builder.redirect(Policy::none());
+        }
+    } else {
+        // a proxy would connect on our behalf, out of reach of the resolver
+        builder = builder
+            .no_proxy()
+            .dns_resolver(Arc::new(PublicResolver))
+            .redirect(match follow_redirects {
+                true => Policy::custom(follow_public),
+                false => Policy::none(),
+            });
+    }
+    builder.build().location("build user url client", Location::caller())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_public() {
+        assert!(is_public("93.184.216.34".parse().unwrap()));
+        assert!(is_public("2606:2800:220:1::".parse().unwrap()));
+        assert!(is_public("198.20.0.1".parse().unwrap()));
+        // nat64 and 6to4 of a public address
+        assert!(is_public("64:ff9b::5db8:d822".parse().unwrap()));
+        assert!(is_public("2002:5db8:d822::".parse().unwrap()));
+        for ip in ["127.0.0.1", "10.1.2.3", "192.168.0.1", "169.254.169.254", "100.64.0.1", "::1", "fd00::1", "::ffff:127.0.0.1"] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["0.1.2.3", "224.0.0.1", "239.255.255.250", "240.0.0.1", "255.255.255.255", "198.18.0.1", "198.19.255.255"] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["ff02::1", "ff0e::1", "64:ff9b::7f00:1", "64:ff9b::a9fe:a9fe", "2002:a00:1::", "2002:7f00:1::1"] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_private_refused() -> anyhow::Result<()> {
+        let client = user_url_client(false, true)?;
+        assert!(client.get("http://localhost:9/").send().await.is_err());
+        assert!(!resolves_public("localhost", 80).await?);
+        assert!(check_user_url("http://127.0.0.1:8080/hook", false).await.is_err());
+        assert!(check_user_url("http://127.0.0.1:8080/hook", true).await.is_ok());
+        assert!(check_user_url("file:///etc/passwd", true).await.is_err());
+        Ok(())
     }
 }
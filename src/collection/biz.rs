@@ -0,0 +1,258 @@
+use crate::{
+    auth::jwt::Claims,
+    common::{AppError, Page, Result},
+    db::Db,
+    material::{
+        biz::{MaterialsService, SearchResult},
+        mvc::SearchCondition,
+        MaterialType, SortDirection, SortField,
+    },
+    util::poem::BaseUrl,
+};
+use chrono::{NaiveDateTime, Utc};
+use ioc::Bean;
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+/// The part of a [`SearchCondition`] that is worth saving: everything but paging.
+#[derive(Debug, Clone, Deserialize, Serialize, Object)]
+pub(crate) struct SavedCondition {
+    pub(crate) tags: Option<Vec<String>>,
+    pub(crate) query: Option<String>,
+    pub(crate) r#type: Option<MaterialType>,
+    pub(crate) sort: Option<SortField>,
+    pub(crate) direction: Option<SortDirection>,
+}
+
+impl SavedCondition {
+    fn to_search(
+        &self,
+        page: Page,
+        cursor: Option<String>,
+        created_from: Option<NaiveDateTime>,
+    ) -> SearchCondition {
+        SearchCondition {
+            tags: self.tags.clone(),
+            page,
+            query: self.query.clone(),
+            r#type: self.r#type,
+            sort: self.sort,
+            direction: self.direction,
+            cursor,
+            created_from,
+            created_to: None,
+            facets: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Object)]
+pub(crate) struct SavedSearch {
+    id: String,
+    name: String,
+    condition: SavedCondition,
+    /// smart collections are re-evaluated on every run and track what is new since last viewed
+    smart: bool,
+    last_viewed_at: Option<NaiveDateTime>,
+    created_at: NaiveDateTime,
+    /// matches created since `last_viewed_at`, only for smart collections
+    #[oai(skip_serializing_if_is_none)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    new_matches: Option<u64>,
+}
+
+#[derive(sqlx::FromRow)]
+struct SavedSearchRow {
+    id: String,
+    name: String,
+    condition: String,
+    smart: bool,
+    last_viewed_at: Option<NaiveDateTime>,
+    created_at: NaiveDateTime,
+}
+
+impl SavedSearchRow {
+    fn condition(&self) -> Result<SavedCondition> {
+        serde_json::from_str(&self.condition).map_err(|e| AppError::Other(e.into()))
+    }
+}
+
+/// Whether a run starts viewing the collection, rather than fetching a further page.
+fn is_first_page(page: &Page, cursor: Option<&str>) -> bool {
+    cursor.is_none() && page.page <= 1
+}
+
+#[derive(Bean)]
+pub(crate) struct CollectionService {
+    #[inject(bean = Db)]
+    db: &'static SqlitePool,
+    #[inject(bean)]
+    materials_svc: &'static MaterialsService,
+}
+
+impl CollectionService {
+    pub(crate) async fn create(
+        &self,
+        name: &str,
+        condition: &SavedCondition,
+        smart: bool,
+        claims: &Claims,
+    ) -> Result<SavedSearch> {
+        let id = Uuid::new_v4().as_simple().to_string();
+        let json = serde_json::to_string(condition).map_err(|e| AppError::Other(e.into()))?;
+        let now = Utc::now().naive_utc();
+
+        sqlx::query!(
+            r#"
+            INSERT INTO saved_searches (id, workspace_id, owner, name, condition, smart, last_viewed_at, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+            id,
+            claims.workspace,
+            claims.id,
+            name,
+            json,
+            smart,
+            now,
+            now
+        )
+            .execute(self.db)
+            .await?;
+
+        Ok(SavedSearch {
+            id,
+            name: name.to_string(),
+            condition: condition.clone(),
+            smart,
+            last_viewed_at: Some(now),
+            created_at: now,
+            new_matches: smart.then_some(0),
+        })
+    }
+
+    pub(crate) async fn list(&self, claims: &Claims) -> Result<Vec<SavedSearch>> {
+        let rows: Vec<SavedSearchRow> = sqlx::query_as(
+            r#"
+            SELECT id, name, condition, smart, last_viewed_at, created_at
+            FROM saved_searches
+            WHERE workspace_id = ? AND owner = ?
+            ORDER BY created_at
+            "#,
+        )
+            .bind(&claims.workspace)
+            .bind(&claims.id)
+            .fetch_all(self.db)
+            .await?;
+
+        let conditions = rows
+            .iter()
+            .map(SavedSearchRow::condition)
+            .collect::<Result<Vec<_>>>()?;
+
+        // new matches of all smart collections are counted in a single query
+        let since: Vec<SearchCondition> = rows
+            .iter()
+            .zip(&conditions)
+            .filter(|(row, _)| row.smart)
+            .map(|(row, condition)| condition.to_search(Page { page: 1, size: 1 }, None, row.last_viewed_at))
+            .collect();
+        let mut counts = self.materials_svc.counts(&since, claims).await?.into_iter();
+
+        let searches = rows
+            .into_iter()
+            .zip(conditions)
+            .map(|(row, condition)| SavedSearch {
+                new_matches: if row.smart { counts.next() } else { None },
+                id: row.id,
+                name: row.name,
+                condition,
+                smart: row.smart,
+                last_viewed_at: row.last_viewed_at,
+                created_at: row.created_at,
+            })
+            .collect();
+        Ok(searches)
+    }
+
+    /// Runs the saved search against the current library; viewing the first page of a smart
+    /// collection resets its new match count.
+    pub(crate) async fn run(
+        &self,
+        id: &str,
+        page: Page,
+        cursor: Option<String>,
+        base_url: BaseUrl,
+        claims: Claims,
+    ) -> Result<SearchResult> {
+        let row = self.get(id, &claims).await?;
+        let first_page = is_first_page(&page, cursor.as_deref());
+        let condition = row.condition()?.to_search(page, cursor, None);
+
+        let result = self.materials_svc.search(condition, base_url, claims.clone()).await?;
+
+        // paging through the matches is still the same view
+        if row.smart && first_page {
+            let now = Utc::now().naive_utc();
+            sqlx::query!(
+                r#"
+                UPDATE saved_searches SET last_viewed_at = ? WHERE id = ?
+                "#,
+                now,
+                id
+            )
+                .execute(self.db)
+                .await?;
+        }
+
+        Ok(result)
+    }
+
+    pub(crate) async fn delete(&self, id: &str, claims: &Claims) -> Result<()> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM saved_searches WHERE id = ? AND workspace_id = ? AND owner = ?
+            "#,
+            id,
+            claims.workspace,
+            claims.id
+        )
+            .execute(self.db)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::SavedSearchNotFound(id.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn get(&self, id: &str, claims: &Claims) -> Result<SavedSearchRow> {
+        let row = sqlx::query_as(
+            r#"
+            SELECT id, name, condition, smart, last_viewed_at, created_at
+            FROM saved_searches
+            WHERE id = ? AND workspace_id = ? AND owner = ?
+            "#,
+        )
+            .bind(id)
+            .bind(&claims.workspace)
+            .bind(&claims.id)
+            .fetch_optional(self.db)
+            .await?;
+
+        row.ok_or_else(|| AppError::SavedSearchNotFound(id.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_first_page() {
+        assert!(is_first_page(&Page { page: 1, size: 20 }, None));
+        assert!(!is_first_page(&Page { page: 2, size: 20 }, None));
+        assert!(!is_first_page(&Page { page: 1, size: 20 }, Some("cursor")));
+    }
+}
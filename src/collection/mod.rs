@@ -0,0 +1,2 @@
+pub mod biz;
+pub mod mvc;
@@ -0,0 +1,77 @@
+use crate::{
+    auth::apikey::JwtAuth,
+    collection::biz::{CollectionService, SavedCondition, SavedSearch},
+    common::{Page, Response, Result},
+    material::biz::SearchResult,
+    metrics::metered,
+    util::poem::BaseUrl,
+};
+use ioc::{mvc, Bean, OpenApi};
+use poem_openapi::{param::Path, payload::Json, Object};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub(crate) struct SavedSearchRequest {
+    pub(crate) name: String,
+    #[serde(flatten)]
+    #[oai(flatten)]
+    pub(crate) condition: SavedCondition,
+    /// save as a smart collection
+    pub(crate) smart: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub(crate) struct RunRequest {
+    #[serde(flatten)]
+    #[oai(flatten = true)]
+    pub(crate) page: Page,
+    pub(crate) cursor: Option<String>,
+}
+
+#[derive(Bean)]
+pub(crate) struct CollectionMvc {
+    #[inject(bean)]
+    collections: &'static CollectionService,
+}
+
+#[mvc]
+#[OpenApi(prefix_path = "/api/v1")]
+impl CollectionMvc {
+    #[oai(path = "/saved_searches", method = "post", transform = "metered")]
+    async fn create(&self, request: Json<SavedSearchRequest>, auth: JwtAuth) -> Result<Response<SavedSearch>> {
+        let request = request.0;
+        let saved = self
+            .collections
+            .create(&request.name, &request.condition, request.smart.unwrap_or(false), &auth)
+            .await?;
+        Ok(Response::ok(saved))
+    }
+
+    #[oai(path = "/saved_searches", method = "get", transform = "metered")]
+    async fn list(&self, auth: JwtAuth) -> Result<Response<Vec<SavedSearch>>> {
+        let saved = self.collections.list(&auth).await?;
+        Ok(Response::ok(saved))
+    }
+
+    #[oai(path = "/saved_searches/:id/run", method = "post", transform = "metered")]
+    async fn run(
+        &self,
+        id: Path<String>,
+        request: Json<RunRequest>,
+        base_url: BaseUrl,
+        auth: JwtAuth,
+    ) -> Result<Response<SearchResult>> {
+        let RunRequest { page, cursor } = request.0;
+        let result = self
+            .collections
+            .run(&id, page, cursor, base_url, auth.into())
+            .await?;
+        Ok(Response::ok(result))
+    }
+
+    #[oai(path = "/saved_searches/:id", method = "delete", transform = "metered")]
+    async fn delete(&self, id: Path<String>, auth: JwtAuth) -> Result<Response<String>> {
+        self.collections.delete(&id, &auth).await?;
+        Ok(Response::ok("ok".to_string()))
+    }
+}
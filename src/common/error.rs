@@ -1,17 +1,22 @@
 use crate::common::FormatedEvent;
 use anyhow::Context;
 use http::StatusCode;
+use ioc::{Bean, BeanSpec};
 use poem::{error::ResponseError, Body, Error, Response as PoemResponse};
 use poem_openapi::{
-    registry::{MetaResponses, Registry},
-    ApiResponse,
+    registry::{MetaHeader, MetaMediaType, MetaResponse, MetaResponses, Registry},
+    types::Type,
+    ApiResponse, Object,
 };
+use serde::Serialize;
 use serde::de::StdError;
 use std::fmt::Display;
 use std::io;
 use std::panic::Location;
+use std::sync::LazyLock;
 use thiserror::Error;
 use tokio::{sync::mpsc::error::SendError, task::JoinError};
+use tracing::error;
 
 #[derive(Error, Debug)]
 pub(crate) enum AppError {
@@ -53,6 +58,76 @@ pub(crate) enum AppError {
     WrongMaterialType(u16),
     #[error("wrong username or password")]
     InvalidUsernameOrPassword,
+    #[error("no access to workspace: `{0}`")]
+    WorkspaceForbidden(String),
+    #[error("user not found: `{0}`")]
+    UserNotFound(String),
+    #[error("invalid cursor: `{0}`")]
+    InvalidCursor(String),
+    #[error("saved search not found: `{0}`")]
+    SavedSearchNotFound(String),
+    #[error("unknown oauth provider: `{0}`")]
+    UnknownOAuthProvider(String),
+    #[error("invalid oauth state")]
+    InvalidOAuthState,
+    #[error("account locked until `{0}`")]
+    AccountLocked(String),
+    #[error("registration is closed")]
+    RegistrationClosed,
+    #[error("username already taken: `{0}`")]
+    UsernameTaken(String),
+    #[error("password must have at least {0} characters")]
+    WeakPassword(usize),
+    #[error("invalid or expired token")]
+    InvalidAccountToken,
+    #[error("admin required")]
+    AdminRequired,
+    #[error("invalid or expired refresh token")]
+    InvalidRefreshToken,
+    #[error("session revoked or expired")]
+    SessionRevoked,
+    #[error("session not found: `{0}`")]
+    SessionNotFound(String),
+    #[error("invalid or expired api key")]
+    InvalidApiKey,
+    #[error("api key lacks scope: `{0}`")]
+    ApiKeyScopeMissing(String),
+    #[error("api keys are not accepted here")]
+    ApiKeyNotAllowed,
+    #[error("api key needs at least one scope")]
+    ApiKeyScopesRequired,
+    #[error("api key not found: `{0}`")]
+    ApiKeyNotFound(String),
+    #[error("webhook not found: `{0}`")]
+    WebhookNotFound(String),
+    #[error("not under an import root: `{0}`")]
+    ImportSourceForbidden(String),
+    #[error("invalid remote url: `{0}`")]
+    InvalidRemoteUrl(String),
+    #[error("download exceeds {0} bytes")]
+    DownloadTooLarge(u64),
+    #[error("invalid clip: {0}")]
+    InvalidClip(String),
+    #[error("{0}")]
+    CropOutOfBounds(String),
+    #[error("material is not being processed: `{0}`")]
+    MaterialNotProcessing(String),
+    #[error("material is being processed: `{0}`")]
+    MaterialProcessing(String),
+    #[error("an import with the report `{0}` is running")]
+    ImportRunning(String),
+    #[error("transcode cancelled")]
+    TranscodeCancelled,
+    #[error("workspace `{0}` would have no owner left")]
+    LastOwner(String),
+    #[error("invalid metrics token")]
+    InvalidMetricsToken,
+    #[error("invalid or expired file url")]
+    InvalidFileToken,
+    #[error("missing `auth` header")]
+    MissingCredentials,
+    #[error("too many requests, retry after {0}s")]
+    TooManyRequests(u64),
     #[error(transparent)]
     Other(#[from] anyhow::Error),
     #[error("`{0}`")]
@@ -65,36 +140,278 @@ impl From<poem::Error> for AppError {
     }
 }
 
+/// Declares [`ErrorCode`] from its catalogue, so every code is listed in [`ErrorCode::ALL`] and
+/// the OpenAPI spec.
+macro_rules! error_codes {
+    ($($variant:ident => ($code:literal, $name:literal, $status:ident),)*) => {
+        /// Stable, machine-readable error codes; clients should branch on these, not on `msg`.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub(crate) enum ErrorCode {
+            $($variant,)*
+        }
+
+        impl ErrorCode {
+            /// Every code, in the order documented in the OpenAPI spec.
+            pub(crate) const ALL: &'static [ErrorCode] = &[$(ErrorCode::$variant,)*];
+
+            /// `(code, name, status)` of the catalogue.
+            const fn entry(&self) -> (i32, &'static str, StatusCode) {
+                match self {
+                    $(ErrorCode::$variant => ($code, $name, StatusCode::$status),)*
+                }
+            }
+        }
+    };
+}
+
+// Codes never change once released.
+error_codes! {
+    Internal => (500, "internal", INTERNAL_SERVER_ERROR),
+    InvalidToken => (1001, "invalid_token", UNAUTHORIZED),
+    InvalidCredentials => (1002, "invalid_credentials", UNAUTHORIZED),
+    SessionRevoked => (1003, "session_revoked", UNAUTHORIZED),
+    InvalidRefreshToken => (1004, "invalid_refresh_token", UNAUTHORIZED),
+    InvalidApiKey => (1005, "invalid_api_key", UNAUTHORIZED),
+    InvalidOAuthState => (1006, "invalid_oauth_state", BAD_REQUEST),
+    AccountLocked => (1007, "account_locked", FORBIDDEN),
+    WorkspaceForbidden => (1101, "workspace_forbidden", FORBIDDEN),
+    AdminRequired => (1102, "admin_required", FORBIDDEN),
+    ApiKeyScope => (1103, "api_key_scope", FORBIDDEN),
+    RegistrationClosed => (1104, "registration_closed", FORBIDDEN),
+    ImportSourceForbidden => (1105, "import_source_forbidden", FORBIDDEN),
+    MaterialNotFound => (2001, "material_not_found", NOT_FOUND),
+    UserNotFound => (2002, "user_not_found", NOT_FOUND),
+    SavedSearchNotFound => (2003, "saved_search_not_found", NOT_FOUND),
+    SessionNotFound => (2004, "session_not_found", NOT_FOUND),
+    ApiKeyNotFound => (2005, "api_key_not_found", NOT_FOUND),
+    UnknownOAuthProvider => (2006, "unknown_oauth_provider", NOT_FOUND),
+    WebhookNotFound => (2007, "webhook_not_found", NOT_FOUND),
+    NotFound => (2000, "not_found", NOT_FOUND),
+    BadRequest => (3000, "bad_request", BAD_REQUEST),
+    InvalidCursor => (3001, "invalid_cursor", BAD_REQUEST),
+    PayloadTooLarge => (3002, "payload_too_large", PAYLOAD_TOO_LARGE),
+    InvalidRemoteUrl => (3003, "invalid_remote_url", BAD_REQUEST),
+    CropOutOfBounds => (3004, "crop_out_of_bounds", BAD_REQUEST),
+    WrongMaterialType => (3101, "wrong_material_type", UNPROCESSABLE_ENTITY),
+    WeakPassword => (3102, "weak_password", UNPROCESSABLE_ENTITY),
+    ApiKeyScopesRequired => (3103, "api_key_scopes_required", UNPROCESSABLE_ENTITY),
+    InvalidAccountToken => (3104, "invalid_account_token", UNPROCESSABLE_ENTITY),
+    InvalidClip => (3105, "invalid_clip", UNPROCESSABLE_ENTITY),
+    UsernameTaken => (4001, "username_taken", CONFLICT),
+    MaterialNotProcessing => (4002, "material_not_processing", CONFLICT),
+    TranscodeCancelled => (4003, "transcode_cancelled", CONFLICT),
+    LastOwner => (4004, "last_owner", CONFLICT),
+    MaterialProcessing => (4005, "material_processing", CONFLICT),
+    ImportRunning => (4006, "import_running", CONFLICT),
+    TooManyRequests => (4291, "too_many_requests", TOO_MANY_REQUESTS),
+}
+
+impl ErrorCode {
+    pub(crate) const fn value(&self) -> i32 {
+        self.entry().0
+    }
+
+    pub(crate) const fn name(&self) -> &'static str {
+        self.entry().1
+    }
+
+    pub(crate) const fn status(&self) -> StatusCode {
+        self.entry().2
+    }
+}
+
+/// Body of every error response.
+#[derive(Debug, Serialize, Object)]
+pub(crate) struct ErrorBody {
+    /// see the error code catalogue
+    code: i32,
+    /// name of the code, e.g. `material_not_found`
+    error: String,
+    msg: String,
+}
+
+impl AppError {
+    /// The error a handler raised, looking through context added by [`LocationContext`].
+    fn cause(&self) -> &AppError {
+        match self {
+            AppError::Other(e) => match e.downcast_ref::<AppError>() {
+                Some(inner) => inner.cause(),
+                None => self,
+            },
+            _ => self,
+        }
+    }
+
+    pub(crate) fn code(&self) -> ErrorCode {
+        match self.cause() {
+            AppError::JwtError(_)
+            | AppError::InvalidMetricsToken
+            | AppError::InvalidFileToken
+            | AppError::MissingCredentials => ErrorCode::InvalidToken,
+            AppError::InvalidUsernameOrPassword => ErrorCode::InvalidCredentials,
+            AppError::SessionRevoked => ErrorCode::SessionRevoked,
+            AppError::InvalidRefreshToken => ErrorCode::InvalidRefreshToken,
+            AppError::InvalidApiKey => ErrorCode::InvalidApiKey,
+            AppError::InvalidOAuthState => ErrorCode::InvalidOAuthState,
+            AppError::AccountLocked(_) => ErrorCode::AccountLocked,
+            AppError::WorkspaceForbidden(_) => ErrorCode::WorkspaceForbidden,
+            AppError::AdminRequired => ErrorCode::AdminRequired,
+            AppError::ApiKeyScopeMissing(_) | AppError::ApiKeyNotAllowed => ErrorCode::ApiKeyScope,
+            AppError::RegistrationClosed => ErrorCode::RegistrationClosed,
+            AppError::ImportSourceForbidden(_) => ErrorCode::ImportSourceForbidden,
+            AppError::MaterialNotFound(_) => ErrorCode::MaterialNotFound,
+            AppError::UserNotFound(_) => ErrorCode::UserNotFound,
+            AppError::SavedSearchNotFound(_) => ErrorCode::SavedSearchNotFound,
+            AppError::SessionNotFound(_) => ErrorCode::SessionNotFound,
+            AppError::ApiKeyNotFound(_) => ErrorCode::ApiKeyNotFound,
+            AppError::UnknownOAuthProvider(_) => ErrorCode::UnknownOAuthProvider,
+            AppError::WebhookNotFound(_) => ErrorCode::WebhookNotFound,
+            AppError::InvalidCursor(_) => ErrorCode::InvalidCursor,
+            AppError::InvalidRemoteUrl(_) => ErrorCode::InvalidRemoteUrl,
+            AppError::CropOutOfBounds(_) => ErrorCode::CropOutOfBounds,
+            AppError::DownloadTooLarge(_) => ErrorCode::PayloadTooLarge,
+            AppError::WrongMaterialType(_) => ErrorCode::WrongMaterialType,
+            AppError::WeakPassword(_) => ErrorCode::WeakPassword,
+            AppError::ApiKeyScopesRequired => ErrorCode::ApiKeyScopesRequired,
+            AppError::InvalidAccountToken => ErrorCode::InvalidAccountToken,
+            AppError::InvalidClip(_) => ErrorCode::InvalidClip,
+            AppError::UsernameTaken(_) => ErrorCode::UsernameTaken,
+            AppError::MaterialNotProcessing(_) => ErrorCode::MaterialNotProcessing,
+            AppError::MaterialProcessing(_) => ErrorCode::MaterialProcessing,
+            AppError::ImportRunning(_) => ErrorCode::ImportRunning,
+            AppError::TranscodeCancelled => ErrorCode::TranscodeCancelled,
+            AppError::LastOwner(_) => ErrorCode::LastOwner,
+            AppError::TooManyRequests(_) => ErrorCode::TooManyRequests,
+            AppError::PoemError(e) => match e.status() {
+                StatusCode::PAYLOAD_TOO_LARGE => ErrorCode::PayloadTooLarge,
+                StatusCode::NOT_FOUND => ErrorCode::NotFound,
+                StatusCode::UNAUTHORIZED => ErrorCode::InvalidToken,
+                status if status.is_client_error() => ErrorCode::BadRequest,
+                _ => ErrorCode::Internal,
+            },
+            AppError::DbSqlxError(_)
+            | AppError::BoxDynErrorError(_)
+            | AppError::JoinError(_)
+            | AppError::SseError(_)
+            | AppError::ParseFloatError(_)
+            | AppError::IoError(_)
+            | AppError::IocError(_)
+            | AppError::HttpError(_)
+            | AppError::InvalidUri(_)
+            | AppError::UrlParseError(_)
+            | AppError::ReqwestError(_)
+            | AppError::UnspecifiedRingError(_)
+            | AppError::Other(_)
+            | AppError::DbError(_) => ErrorCode::Internal,
+        }
+    }
+
+    /// Message shown to clients. Internal errors may leak paths, queries or upstream responses,
+    /// so they are replaced with a generic message unless `error.expose-internal` is set.
+    fn client_message(&self, code: ErrorCode) -> String {
+        if code == ErrorCode::Internal && !ErrorSettings::get().expose_internal {
+            "internal server error".to_string()
+        } else {
+            self.cause().to_string()
+        }
+    }
+}
+
+#[derive(Bean)]
+pub(crate) struct ErrorSettings {
+    #[inject(config = "error.expose-internal")]
+    expose_internal: bool,
+}
+
 impl ResponseError for AppError {
     fn status(&self) -> StatusCode {
-        StatusCode::INTERNAL_SERVER_ERROR
+        self.code().status()
     }
 
     fn as_response(&self) -> PoemResponse {
-        let code = match self {
-            AppError::JwtError(_) => 1001,
-            _ => 500,
-        };
-
-        let body = Body::from_json(serde_json::json!({
-            "code": code,
-            "msg": format!("{self}"),
-        }))
+        let code = self.code();
+        if code == ErrorCode::Internal {
+            error!("{self:?}");
+        }
+
+        let body = Body::from_json(ErrorBody {
+            code: code.value(),
+            error: code.name().to_string(),
+            msg: self.client_message(code),
+        })
             .unwrap();
 
-        PoemResponse::builder().status(self.status()).body(body)
+        let mut builder = PoemResponse::builder().status(code.status());
+        if let AppError::TooManyRequests(retry_after) = self.cause() {
+            builder = builder.header(http::header::RETRY_AFTER, retry_after.to_string());
+        }
+        builder.body(body)
     }
 }
 
 impl ApiResponse for AppError {
     fn meta() -> MetaResponses {
-        let responses = Vec::new();
-        MetaResponses {
-            responses,
+        let mut responses: Vec<MetaResponse> = Vec::new();
+        for code in ErrorCode::ALL {
+            let status = code.status().as_u16();
+            if responses.iter().any(|response| response.status == Some(status)) {
+                continue;
+            }
+            let headers = match code {
+                ErrorCode::TooManyRequests => vec![MetaHeader {
+                    name: "Retry-After".to_string(),
+                    description: Some("seconds to wait before retrying".to_string()),
+                    required: true,
+                    deprecated: false,
+                    schema: u64::schema_ref(),
+                }],
+                _ => Vec::new(),
+            };
+            responses.push(MetaResponse {
+                description: status_description(status),
+                status: Some(status),
+                status_range: None,
+                content: vec![MetaMediaType {
+                    content_type: "application/json; charset=utf-8",
+                    schema: ErrorBody::schema_ref(),
+                }],
+                headers,
+            });
+        }
+        MetaResponses { responses }
+    }
+
+    fn register(registry: &mut Registry) {
+        ErrorBody::register(registry);
+    }
+}
+
+/// Descriptions of the error statuses, each listing the codes it carries.
+static STATUS_DESCRIPTIONS: LazyLock<Vec<(u16, String)>> = LazyLock::new(|| {
+    let mut descriptions: Vec<(u16, String)> = Vec::new();
+    for code in ErrorCode::ALL {
+        let status = code.status();
+        let entry = format!("{} {}", code.value(), code.name());
+        match descriptions.iter_mut().find(|(known, _)| *known == status.as_u16()) {
+            Some((_, description)) => {
+                description.push_str(", ");
+                description.push_str(&entry);
+            }
+            None => {
+                let reason = status.canonical_reason().unwrap_or("error").to_lowercase();
+                descriptions.push((status.as_u16(), format!("{reason}: {entry}")));
+            }
         }
     }
+    descriptions
+});
 
-    fn register(_: &mut Registry) {}
+fn status_description(status: u16) -> &'static str {
+    STATUS_DESCRIPTIONS
+        .iter()
+        .find(|(known, _)| *known == status)
+        .map(|(_, description)| description.as_str())
+        .unwrap_or_default()
 }
 
 pub trait LocationContext<T, E>: Context<T, E> {
@@ -110,3 +427,34 @@ pub trait LocationContext<T, E>: Context<T, E> {
 impl<T, E, C> LocationContext<T, E> for C where C: Context<T, E> {}
 
 pub(crate) type Result<T> = std::result::Result<T, AppError>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_codes() {
+        let located = Err::<(), _>(AppError::MaterialNotFound("m".to_string()))
+            .location("get", Location::caller())
+            .unwrap_err();
+        let error = AppError::from(located);
+        assert_eq!(error.code(), ErrorCode::MaterialNotFound);
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+
+        assert_eq!(AppError::UsernameTaken("u".to_string()).status(), StatusCode::CONFLICT);
+        assert_eq!(AppError::TooManyRequests(3).code().value(), 4291);
+        assert_eq!(AppError::DbError("boom".to_string()).code(), ErrorCode::Internal);
+
+        let mut codes: Vec<i32> = ErrorCode::ALL.iter().map(ErrorCode::value).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), ErrorCode::ALL.len());
+
+        assert_eq!(
+            status_description(409),
+            "conflict: 4001 username_taken, 4002 material_not_processing, 4003 transcode_cancelled, \
+             4004 last_owner, 4005 material_processing, 4006 import_running"
+        );
+        assert_eq!(status_description(500), "internal server error: 500 internal");
+    }
+}
@@ -35,6 +35,10 @@ pub(crate) struct PageResult<T: Type + ParseFromJSON + ToJSON + Serialize> {
     pub(crate) total: u64,
     #[serde(default)]
     pub(crate) records: Vec<T>,
+    /// cursor to fetch the records after this page, absent on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[oai(skip_serializing_if_is_none)]
+    pub(crate) next_cursor: Option<String>,
 }
 
 impl<T> From<Page> for PageResult<T>
@@ -47,6 +51,7 @@ where
             size: page.size,
             total: 0,
             records: Vec::new(),
+            next_cursor: None,
         }
     }
 }
@@ -61,9 +66,15 @@ where
             size: page.size,
             total,
             records,
+            next_cursor: None,
         }
     }
 
+    pub(crate) fn with_next_cursor(mut self, next_cursor: Option<String>) -> Self {
+        self.next_cursor = next_cursor;
+        self
+    }
+
     pub(crate) fn transfer<U, F>(self, method: F) -> super::Result<PageResult<U>>
     where
         U: Type + ParseFromJSON + ToJSON + Serialize,
@@ -78,6 +89,7 @@ where
             size: self.size,
             total: self.total,
             records,
+            next_cursor: self.next_cursor,
         })
     }
 }
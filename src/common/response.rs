@@ -51,6 +51,6 @@ where
         Self::Ok(Json(ResponseBody::ok(data)))
     }
     pub fn not_found() -> Self {
-        Self::Ok(Json(ResponseBody::not_found()))
+        Self::NotFound(Json(ResponseBody::not_found()))
     }
 }
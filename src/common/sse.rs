@@ -1,9 +1,13 @@
 use poem_openapi::Object;
 use serde::{Deserialize, Serialize};
 
-#[derive(Object, Serialize, Deserialize)]
+#[derive(Object, Serialize, Deserialize, Clone, Debug)]
 pub(crate) struct FormatedEvent {
     pub(crate) id: String,
     pub(crate) progress: i16,
     pub(crate) state: String,
+    /// place in the transcode queue while `queued`, 1 is next
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[oai(skip_serializing_if_is_none)]
+    pub(crate) position: Option<usize>,
 }
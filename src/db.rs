@@ -3,12 +3,16 @@ use std::str::FromStr;
 use anyhow::Result as AnyResult;
 use ioc::{bean, BeanSpec, InitContext};
 use sqlx::{
+    migrate::Migrator,
     sqlite::{SqliteConnectOptions, SqliteJournalMode::Wal, SqlitePoolOptions},
     SqlitePool,
 };
 use tokio::runtime::Builder;
 use tracing::info;
 
+/// Migrations embedded at build time, applied on startup.
+pub(crate) static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
 pub(crate) struct Db {}
 
 #[bean]
@@ -27,7 +31,8 @@ impl BeanSpec for Db {
     }
 }
 
-async fn init(database_url: &str, max_connections: u32) -> AnyResult<SqlitePool> {
+/// Connects and applies pending migrations.
+pub(crate) async fn init(database_url: &str, max_connections: u32) -> AnyResult<SqlitePool> {
     info!("connecting to {database_url}:");
     let options = SqliteConnectOptions::from_str(database_url)?.journal_mode(Wal);
 
@@ -38,7 +43,7 @@ async fn init(database_url: &str, max_connections: u32) -> AnyResult<SqlitePool>
     info!("build connection pool success!");
 
     info!("migrating DB:");
-    sqlx::migrate!("./migrations").run(&db).await?;
+    MIGRATOR.run(&db).await?;
     info!("migrate DB success!");
     Ok(db)
 }
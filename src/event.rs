@@ -0,0 +1,329 @@
+//! Live updates of the library: [`MaterialsService`](crate::material::biz::MaterialsService)
+//! publishes to the [`EventBus`] and every open `GET /api/v1/events` stream forwards the events
+//! of its workspace.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::Utc;
+use ioc::{bean, mvc, Bean, BeanSpec, InitContext, OpenApi};
+use poem::web::sse::Event;
+use poem_openapi::{param::Header, payload::EventStream, Enum, Object};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    spawn,
+    sync::{broadcast, broadcast::error::RecvError, mpsc::channel},
+};
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::{
+    auth::apikey::JwtAuth,
+    common::Result,
+    metrics::metered,
+    webhook::{MaterialEvent, WebhookEvent},
+    workspace::{biz::WorkspaceService, WorkspaceRole},
+};
+
+/// Comments sent on idle streams, so proxies don't close them.
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Enum)]
+pub(crate) enum EventKind {
+    #[oai(rename = "material.created")]
+    #[serde(rename = "material.created")]
+    Created,
+    /// percent of the transcode done
+    #[oai(rename = "material.progress")]
+    #[serde(rename = "material.progress")]
+    Progress,
+    #[oai(rename = "material.ready")]
+    #[serde(rename = "material.ready")]
+    Ready,
+    #[oai(rename = "material.failed")]
+    #[serde(rename = "material.failed")]
+    Failed,
+    #[oai(rename = "material.cancelled")]
+    #[serde(rename = "material.cancelled")]
+    Cancelled,
+    #[oai(rename = "material.updated")]
+    #[serde(rename = "material.updated")]
+    Updated,
+    #[oai(rename = "material.deleted")]
+    #[serde(rename = "material.deleted")]
+    Deleted,
+    /// events were missed, e.g. the `Last-Event-ID` is too old: reload the library
+    #[oai(rename = "reset")]
+    #[serde(rename = "reset")]
+    Reset,
+}
+
+impl EventKind {
+    fn value(&self) -> &'static str {
+        match self {
+            EventKind::Created => "material.created",
+            EventKind::Progress => "material.progress",
+            EventKind::Ready => "material.ready",
+            EventKind::Failed => "material.failed",
+            EventKind::Cancelled => "material.cancelled",
+            EventKind::Updated => "material.updated",
+            EventKind::Deleted => "material.deleted",
+            EventKind::Reset => "reset",
+        }
+    }
+
+    /// The event webhooks subscribe to, progress is only streamed. For webhooks a cancelled
+    /// transcode failed, with `cancelled` as the error.
+    pub(crate) fn webhook(&self) -> Option<WebhookEvent> {
+        match self {
+            EventKind::Created => Some(WebhookEvent::MaterialCreated),
+            EventKind::Ready => Some(WebhookEvent::MaterialReady),
+            EventKind::Failed | EventKind::Cancelled => Some(WebhookEvent::MaterialFailed),
+            EventKind::Updated => Some(WebhookEvent::MaterialUpdated),
+            EventKind::Deleted => Some(WebhookEvent::MaterialDeleted),
+            EventKind::Progress | EventKind::Reset => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Object)]
+pub(crate) struct LibraryEvent {
+    /// increasing, also the id of the sse event; absent on `reset` events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[oai(skip_serializing_if_is_none)]
+    id: Option<u64>,
+    event: EventKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[oai(skip_serializing_if_is_none)]
+    material: Option<MaterialEvent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[oai(skip_serializing_if_is_none)]
+    progress: Option<u16>,
+}
+
+impl LibraryEvent {
+    fn reset() -> Self {
+        Self {
+            id: None,
+            event: EventKind::Reset,
+            material: None,
+            progress: None,
+        }
+    }
+
+    fn workspace(&self) -> Option<&str> {
+        self.material.as_ref().map(|material| material.workspace_id.as_str())
+    }
+
+    fn to_sse(&self) -> Event {
+        let data = serde_json::to_string(self).unwrap_or_default();
+        let event = Event::message(data).event_type(self.event.value());
+        match self.id {
+            Some(id) => event.id(id.to_string()),
+            None => event,
+        }
+    }
+}
+
+/// The latest events, replayed to streams reconnecting with a `Last-Event-ID`.
+struct Recent {
+    events: VecDeque<Arc<LibraryEvent>>,
+    next_id: u64,
+}
+
+impl Recent {
+    /// Events after `last_id`, `None` when some of them are no longer kept.
+    fn since(&self, last_id: u64) -> Option<Vec<Arc<LibraryEvent>>> {
+        let oldest = self
+            .events
+            .front()
+            .and_then(|event| event.id)
+            .unwrap_or(self.next_id);
+        if last_id.saturating_add(1) < oldest || last_id >= self.next_id {
+            return None;
+        }
+        Some(
+            self.events
+                .iter()
+                .filter(|event| event.id.is_some_and(|id| id > last_id))
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+pub(crate) struct EventBus {
+    sender: broadcast::Sender<Arc<LibraryEvent>>,
+    recent: Mutex<Recent>,
+    replay_size: usize,
+}
+
+#[bean]
+impl BeanSpec for EventBus {
+    type Bean = Self;
+
+    fn build(ctx: &mut impl InitContext) -> ioc::Result<Self::Bean> {
+        let capacity = ctx.get_config::<usize>("events.capacity")?;
+        let replay_size = ctx.get_config::<usize>("events.replay-size")?;
+        Ok(Self::new(capacity, replay_size))
+    }
+}
+
+impl EventBus {
+    pub(crate) fn new(capacity: usize, replay_size: usize) -> Self {
+        // ids of a restarted server start past those of the previous run, so an old
+        // `Last-Event-ID` is never mistaken for a recent one
+        let first_id = Utc::now().timestamp_millis() as u64 * 1000;
+        Self {
+            sender: broadcast::channel(capacity.max(1)).0,
+            recent: Mutex::new(Recent {
+                events: VecDeque::with_capacity(replay_size),
+                next_id: first_id,
+            }),
+            replay_size,
+        }
+    }
+
+    pub(crate) fn publish(&self, kind: EventKind, material: MaterialEvent, progress: Option<u16>) {
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        let event = Arc::new(LibraryEvent {
+            id: Some(recent.next_id),
+            event: kind,
+            material: Some(material),
+            progress,
+        });
+        recent.next_id += 1;
+        recent.events.push_back(event.clone());
+        while recent.events.len() > self.replay_size {
+            recent.events.pop_front();
+        }
+        // sent under the lock, so a new subscriber gets every event either replayed or live;
+        // an error only means nobody listens
+        let _ = self.sender.send(event);
+    }
+
+    /// Subscribes to events published from now on, together with the kept ones after
+    /// `last_id`, see [`Recent::since`].
+    fn subscribe(
+        &self,
+        last_id: Option<u64>,
+    ) -> (broadcast::Receiver<Arc<LibraryEvent>>, Option<Vec<Arc<LibraryEvent>>>) {
+        let recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        let replay = match last_id {
+            Some(last_id) => recent.since(last_id),
+            None => Some(Vec::new()),
+        };
+        (self.sender.subscribe(), replay)
+    }
+}
+
+#[derive(Bean)]
+pub(crate) struct EventMvc {
+    #[inject(bean)]
+    events: &'static EventBus,
+    #[inject(bean)]
+    workspaces: &'static WorkspaceService,
+}
+
+#[mvc]
+#[OpenApi(prefix_path = "/api/v1")]
+impl EventMvc {
+    /// Material events of the active workspace as they happen. Reconnecting with the id of the
+    /// last event received replays what was missed, or starts with a `reset` event when that
+    /// is no longer possible.
+    #[oai(path = "/events", method = "get", transform = "metered")]
+    async fn events(
+        &self,
+        #[oai(name = "Last-Event-ID")] last_event_id: Header<Option<u64>>,
+        auth: JwtAuth,
+    ) -> Result<EventStream<ReceiverStream<LibraryEvent>>> {
+        self.workspaces.require(&auth, WorkspaceRole::Viewer).await?;
+        let workspace = auth.into_inner().workspace;
+        let (mut rx, replay) = self.events.subscribe(last_event_id.0);
+
+        let (tx, stream) = channel(32);
+        spawn(async move {
+            let replay = replay.unwrap_or_else(|| vec![Arc::new(LibraryEvent::reset())]);
+            let replay = replay.into_iter().filter(|event| {
+                event.event == EventKind::Reset || event.workspace() == Some(workspace.as_str())
+            });
+            for event in replay {
+                if tx.send(event.as_ref().clone()).await.is_err() {
+                    return;
+                }
+            }
+
+            loop {
+                let event = tokio::select! {
+                    received = rx.recv() => match received {
+                        Ok(event) if event.workspace() == Some(workspace.as_str()) => event.as_ref().clone(),
+                        Ok(_) => continue,
+                        Err(RecvError::Lagged(_)) => LibraryEvent::reset(),
+                        Err(RecvError::Closed) => return,
+                    },
+                    _ = tx.closed() => return,
+                };
+                if tx.send(event).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        Ok(EventStream::new(ReceiverStream::new(stream))
+            .keep_alive(KEEP_ALIVE)
+            .to_event(|event| event.to_sse()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn material(workspace: &str) -> MaterialEvent {
+        MaterialEvent::deleted("m", workspace)
+    }
+
+    #[test]
+    fn test_replay() {
+        let bus = EventBus::new(16, 2);
+        let (_, replay) = bus.subscribe(None);
+        assert_eq!(replay.map(|events| events.len()), Some(0));
+
+        for _ in 0..3 {
+            bus.publish(EventKind::Deleted, material("w"), None);
+        }
+        let (mut rx, _) = bus.subscribe(None);
+        let ids: Vec<u64> = bus.recent.lock().unwrap().events.iter().filter_map(|e| e.id).collect();
+        let [second, third] = ids[..] else { panic!("{ids:?}") };
+
+        let (_, replay) = bus.subscribe(Some(second));
+        let replayed: Vec<Option<u64>> = replay.unwrap().iter().map(|event| event.id).collect();
+        assert_eq!(replayed, vec![Some(third)]);
+        assert_eq!(bus.subscribe(Some(third)).1.map(|events| events.len()), Some(0));
+
+        // the first event is no longer kept, and ids of another run are unknown
+        assert!(bus.subscribe(Some(second - 2)).1.is_none());
+        assert!(bus.subscribe(Some(third + 1)).1.is_none());
+
+        bus.publish(EventKind::Created, material("w"), None);
+        let live = rx.try_recv().unwrap();
+        assert_eq!(live.id, Some(third + 1));
+        assert_eq!(live.event, EventKind::Created);
+    }
+
+    #[test]
+    fn test_sse() {
+        let event = LibraryEvent {
+            id: Some(7),
+            event: EventKind::Progress,
+            material: Some(material("w")),
+            progress: Some(40),
+        };
+        let sse = event.to_sse().to_string();
+        assert!(sse.contains("event: material.progress"), "{sse}");
+        assert!(sse.contains("id: 7"), "{sse}");
+        assert!(sse.contains(r#""progress":40"#), "{sse}");
+    }
+}
@@ -0,0 +1,135 @@
+//! Cuts part of a video into a file of its own, re-encoded so the clip starts exactly at the
+//! requested time rather than at the previous key frame.
+
+use ffmpeg_sidecar::{
+    command::FfmpegCommand,
+    event::{FfmpegEvent, LogLevel},
+};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::{
+    common::{AppError, Result},
+    ffmpeg::slice::Cancel,
+};
+
+/// Area of the frame to keep, in pixels of the source.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Object)]
+pub(crate) struct Crop {
+    /// from the left edge
+    pub(crate) x: u32,
+    /// from the top edge
+    pub(crate) y: u32,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+impl Crop {
+    /// Why the encoder can't produce frames of this size, if it can't.
+    pub(crate) fn check(&self) -> std::result::Result<(), String> {
+        if self.width < 2 || self.height < 2 || self.width % 2 != 0 || self.height % 2 != 0 {
+            return Err(format!(
+                "crop size {}x{} must be even and at least 2x2",
+                self.width, self.height
+            ));
+        }
+        Ok(())
+    }
+
+    /// Why the crop isn't inside a frame of `width` by `height`, if it isn't.
+    pub(crate) fn check_within(&self, width: u32, height: u32) -> std::result::Result<(), String> {
+        let right = self.x as u64 + self.width as u64;
+        let bottom = self.y as u64 + self.height as u64;
+        if right > width as u64 || bottom > height as u64 {
+            return Err(format!(
+                "crop {}x{} at {},{} is outside the {width}x{height} source",
+                self.width, self.height, self.x, self.y
+            ));
+        }
+        Ok(())
+    }
+
+    fn filter(&self) -> String {
+        format!("crop={}:{}:{}:{}", self.width, self.height, self.x, self.y)
+    }
+}
+
+/// Writes the part of `input` from `start` to `end`, in seconds, to `output` as mp4, killing
+/// ffmpeg once `cancel` is.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn clip(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    start: f64,
+    end: f64,
+    crop: Option<Crop>,
+    ffmpeg_path: &Path,
+    threads: usize,
+    cancel: Cancel,
+) -> Result<()> {
+    let mut cmd = FfmpegCommand::new_with_path(ffmpeg_path);
+    // seeking before the input is fast, and exact since the clip is re-encoded
+    cmd.args(["-ss", &start.to_string()])
+        .input(input.as_ref().to_string_lossy())
+        .args(["-t", &(end - start).to_string()]);
+    if let Some(crop) = crop {
+        cmd.args(["-filter:v", &crop.filter()]);
+    }
+    cmd.codec_video("libx264")
+        .args(["-threads", &threads.to_string()])
+        .codec_audio("aac")
+        // the output has no extension to guess the format from
+        .args(["-f", "mp4", "-movflags", "+faststart"])
+        .overwrite()
+        .output(output.as_ref().to_string_lossy());
+
+    let mut child = cmd.spawn()?;
+    let mut errors = Vec::new();
+    // drained, so ffmpeg never blocks on a full pipe
+    for event in child.iter()? {
+        if cancel.is_cancelled() {
+            child.kill()?;
+            child.wait()?;
+            return Err(AppError::TranscodeCancelled);
+        }
+        if let FfmpegEvent::Error(e) | FfmpegEvent::Log(LogLevel::Error | LogLevel::Fatal, e) = event {
+            errors.push(e);
+        }
+    }
+
+    let status = child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("Failed to clip {status}: {}", errors.join("; ")))?
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crop() {
+        let crop = Crop {
+            x: 10,
+            y: 20,
+            width: 640,
+            height: 360,
+        };
+        assert!(crop.check().is_ok());
+        assert_eq!(crop.filter(), "crop=640:360:10:20");
+
+        let odd = Crop { width: 641, ..crop };
+        assert!(odd.check().is_err());
+        let empty = Crop { height: 0, ..crop };
+        assert!(empty.check().is_err());
+
+        assert!(crop.check_within(650, 380).is_ok());
+        assert!(crop.check_within(649, 380).is_err());
+        assert!(crop.check_within(650, 379).is_err());
+        let far = Crop { x: u32::MAX, ..crop };
+        assert!(far.check_within(650, 380).is_err());
+    }
+}
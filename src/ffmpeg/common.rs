@@ -1,23 +1,37 @@
 use crate::ffmpeg::{
-    slice::{Slice, SliceEvent},
-    thumbnail::thumbnail,
+    clip::{clip, Crop},
+    slice::{Cancel, Slice, SliceEvent},
+    thumbnail::{dimensions, duration, thumbnail},
 };
+use crate::metrics::Metrics;
 use ffmpeg_sidecar::{
     download::{check_latest_version, download_ffmpeg_package, ffmpeg_download_url, unpack_ffmpeg},
     version::ffmpeg_version_with_path,
 };
+use cfg_rs::impl_enum;
 use ioc::{bean, BeanSpec, InitContext};
-use std::{fs, path::{Path, PathBuf}, process::{Command, Stdio}};
-use tokio::sync::mpsc::{channel, Receiver};
+use std::{fs, path::{Path, PathBuf}, process::{Command, Stdio}, sync::Arc};
+use tokio::sync::{
+    mpsc::{channel, Receiver},
+    OwnedSemaphorePermit, Semaphore,
+};
 use tokio::task::spawn_blocking;
-use tracing::info;
+use tracing::{info, warn};
 
 #[derive(Debug)]
 pub(crate) struct FFmpegUtils {
     ffmpeg_path: PathBuf,
     ffprobe_path: PathBuf,
+    /// caps the uploads transcoding or waiting for a worker of the
+    /// [`Scheduler`](crate::ffmpeg::scheduler::Scheduler)
+    transcodes: Arc<Semaphore>,
+    /// passed to each encoder, 0 leaves it to ffmpeg
+    threads: usize,
 }
 
+/// Seconds a client is told to wait when every upload slot is taken.
+const TRANSCODE_RETRY_AFTER: u64 = 30;
+
 fn sidecar_path(sidecar_parent: impl AsRef<Path>, name: &str) -> PathBuf {
     let mut path = sidecar_parent.as_ref().join(name);
     if cfg!(windows) {
@@ -34,8 +48,8 @@ fn path(sidecar_parent: impl AsRef<Path>, name: &str) -> PathBuf {
     }
 }
 
-fn is_installed(sidecar_parent: impl AsRef<Path>, name: &str) -> bool {
-    Command::new(path(sidecar_parent, name))
+fn runs(path: &Path) -> bool {
+    Command::new(path)
         .arg("-version")
         .stderr(Stdio::null())
         .stdout(Stdio::null())
@@ -44,53 +58,152 @@ fn is_installed(sidecar_parent: impl AsRef<Path>, name: &str) -> bool {
         .unwrap_or_else(|_| false)
 }
 
-impl FFmpegUtils {
-    fn init(sidecar_parent: PathBuf) -> ioc::Result<Self> {
-        if is_installed(&sidecar_parent, "ffmpeg") {
-            let ffmpeg_path = path(&sidecar_parent, "ffmpeg");
-            let version = ffmpeg_version_with_path(&ffmpeg_path)?;
-            info!("FFmpeg({version}) is already installed! 🎉");
-            let ffprobe_path = path(&sidecar_parent, "ffprobe");
-            info!("ffmpeg_path: {}", ffmpeg_path.display());
-            info!("ffprobe_path: {}", ffprobe_path.display());
-            Ok(Self {
-                ffmpeg_path,
-                ffprobe_path,
-            })
-        } else {
-            let version = check_latest_version()?;
-            info!("FFmpeg Latest available version: {version}");
-
-            let download_url = ffmpeg_download_url()?;
-            let destination = sidecar_parent.as_path();
-
-            if !fs::exists(destination)? {
-                fs::create_dir_all(destination)?;
+/// How missing binaries are handled at startup.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Provisioning {
+    /// only system or `sidecar_parent` binaries, startup fails without them
+    Offline,
+    /// download the latest release into `sidecar_parent` when missing
+    Download,
+}
+
+impl_enum!(Provisioning {
+    "offline" => Provisioning::Offline
+    "download" => Provisioning::Download
+});
+
+/// What the transcoding pipeline needs from ffmpeg.
+#[derive(Debug)]
+pub(crate) struct Requirements {
+    pub(crate) min_version: String,
+    pub(crate) encoders: Vec<String>,
+}
+
+/// Leading numeric components of a version like `6.1.1-essentials_build` or `n7.0`.
+fn parse_version(version: &str) -> Option<Vec<u32>> {
+    let version = version.trim().trim_start_matches('n');
+    let numbers: Vec<u32> = version
+        .split(|c: char| !c.is_ascii_digit() && c != '.')
+        .next()?
+        .split('.')
+        .map_while(|part| part.parse().ok())
+        .collect();
+    (!numbers.is_empty()).then_some(numbers)
+}
+
+/// Encoder names from the output of `ffmpeg -encoders`, listed after the `------` line.
+fn parse_encoders(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("---"))
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(ToString::to_string)
+        .collect()
+}
+
+impl Requirements {
+    /// Everything keeping the binaries from being used, empty when they are fine.
+    pub(crate) fn problems(&self, ffmpeg_path: &Path, ffprobe_path: &Path) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !runs(ffprobe_path) {
+            problems.push(format!("ffprobe not found or not executable: {}", ffprobe_path.display()));
+        }
+        if !runs(ffmpeg_path) {
+            problems.push(format!("ffmpeg not found or not executable: {}", ffmpeg_path.display()));
+            return problems;
+        }
+
+        match ffmpeg_version_with_path(ffmpeg_path) {
+            Ok(version) => match (parse_version(&version), parse_version(&self.min_version)) {
+                (Some(actual), Some(min)) if actual < min => {
+                    problems.push(format!("ffmpeg {version} is older than {}", self.min_version))
+                }
+                (None, _) => warn!("unknown ffmpeg version {version}, skipping the version check"),
+                _ => {}
+            },
+            Err(e) => problems.push(format!("ffmpeg version unknown: {e}")),
+        }
+
+        match Command::new(ffmpeg_path).args(["-hide_banner", "-encoders"]).output() {
+            Ok(output) => {
+                let available = parse_encoders(&String::from_utf8_lossy(&output.stdout));
+                let missing: Vec<&str> = self
+                    .encoders
+                    .iter()
+                    .filter(|encoder| !available.contains(encoder))
+                    .map(String::as_str)
+                    .collect();
+                if !missing.is_empty() {
+                    problems.push(format!("missing encoders: {}", missing.join(", ")));
+                }
             }
+            Err(e) => problems.push(format!("listing ffmpeg encoders failed: {e}")),
+        }
+        problems
+    }
+}
+
+/// Downloads the latest ffmpeg release into `sidecar_parent`, returning the installed version.
+pub(crate) fn install(sidecar_parent: &Path) -> crate::common::Result<String> {
+    let version = check_latest_version()?;
+    info!("FFmpeg Latest available version: {version}");
+
+    let download_url = ffmpeg_download_url()?;
+
+    if !fs::exists(sidecar_parent)? {
+        fs::create_dir_all(sidecar_parent)?;
+    }
 
-            info!("Downloading from: {:?} to {:?}", download_url, destination);
-            let archive_path = download_ffmpeg_package(download_url, destination)?;
-            info!("Downloaded package: {:?}", archive_path);
+    info!("Downloading from: {:?} to {:?}", download_url, sidecar_parent);
+    let archive_path = download_ffmpeg_package(download_url, sidecar_parent)?;
+    info!("Downloaded package: {:?}", archive_path);
 
-            info!("Extracting to {} ...", destination.display());
-            unpack_ffmpeg(&archive_path, destination)?;
+    info!("Extracting to {} ...", sidecar_parent.display());
+    unpack_ffmpeg(&archive_path, sidecar_parent)?;
 
-            let ffmpeg_path = path(&sidecar_parent, "ffmpeg");
-            let ffprobe_path = path(&sidecar_parent, "ffprobe");
+    let version = ffmpeg_version_with_path(path(sidecar_parent, "ffmpeg"))?;
+    info!("Done! 🏁");
+    Ok(version)
+}
 
-            let version = ffmpeg_version_with_path(&ffmpeg_path)?;
-            info!("FFmpeg version: {}", version);
+impl FFmpegUtils {
+    pub(crate) fn init(
+        sidecar_parent: PathBuf,
+        max_transcodes: usize,
+        threads: usize,
+        provisioning: Provisioning,
+        requirements: &Requirements,
+    ) -> ioc::Result<Self> {
+        let transcodes = Arc::new(Semaphore::new(max_transcodes.max(1)));
 
-            info!("Done! 🏁");
+        if !runs(&path(&sidecar_parent, "ffmpeg")) && provisioning == Provisioning::Download {
+            install(&sidecar_parent).map_err(|e| ioc::IocError::Other(e.into()))?;
+        }
 
-            info!("ffmpeg_path: {}", ffmpeg_path.display());
-            info!("ffprobe_path: {}", ffprobe_path.display());
+        let ffmpeg_path = path(&sidecar_parent, "ffmpeg");
+        let ffprobe_path = path(&sidecar_parent, "ffprobe");
 
-            Ok(Self {
-                ffmpeg_path,
-                ffprobe_path,
-            })
+        let problems = requirements.problems(&ffmpeg_path, &ffprobe_path);
+        if !problems.is_empty() {
+            let message = format!(
+                "ffmpeg is not usable: {}. install ffmpeg into `{}` or PATH, or run `phi ffmpeg install`",
+                problems.join("; "),
+                sidecar_parent.display()
+            );
+            return Err(ioc::IocError::Other(anyhow::anyhow!(message).into()));
         }
+
+        let version = ffmpeg_version_with_path(&ffmpeg_path)?;
+        info!("FFmpeg({version}) is already installed! 🎉");
+        info!("ffmpeg_path: {}", ffmpeg_path.display());
+        info!("ffprobe_path: {}", ffprobe_path.display());
+        Ok(Self {
+            ffmpeg_path,
+            ffprobe_path,
+            transcodes,
+            threads,
+        })
     }
 }
 
@@ -100,24 +213,79 @@ impl BeanSpec for FFmpegUtils {
 
     fn build(ctx: &mut impl InitContext) -> ioc::Result<Self::Bean> {
         let sidecar_parent = ctx.get_config::<PathBuf>("ffmpeg.sidecar_parent")?;
-        Self::init(sidecar_parent)
+        let max_transcodes = ctx.get_config::<usize>("ffmpeg.max-concurrent-transcodes")?;
+        let threads = ctx.get_config::<usize>("ffmpeg.threads")?;
+        let provisioning = ctx.get_config::<Provisioning>("ffmpeg.provisioning")?;
+        let requirements = Requirements {
+            min_version: ctx.get_config::<String>("ffmpeg.min-version")?,
+            encoders: ctx.get_config::<Vec<String>>("ffmpeg.required-encoders")?,
+        };
+        Self::init(sidecar_parent, max_transcodes, threads, provisioning, &requirements)
     }
 }
 
 impl FFmpegUtils {
+    /// Reserves an upload slot, held until the permit is dropped. Background work isn't limited,
+    /// it waits for a worker with a lower priority.
+    pub(crate) fn reserve_transcode(&self) -> crate::common::Result<OwnedSemaphorePermit> {
+        self.transcodes
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| crate::common::AppError::TooManyRequests(TRANSCODE_RETRY_AFTER))
+    }
+
     pub(crate) async fn slice2(
         &self,
         input: impl AsRef<Path>,
         output_dir: impl AsRef<Path>,
+        metrics: &'static Metrics,
+        cancel: Cancel,
     ) -> crate::common::Result<Receiver<SliceEvent>> {
         let (tx, rx) = channel(64);
-        let slice = Slice::new(input, output_dir, &self.ffmpeg_path, tx)?;
+        let slice = Slice::new(input, output_dir, &self.ffmpeg_path, self.threads, tx, cancel)?;
 
-        spawn_blocking(|| slice.run());
+        spawn_blocking(move || {
+            let transcode = metrics.transcode_started();
+            let result = slice.run();
+            transcode.finish(&result);
+        });
 
         Ok(rx)
     }
 
+    /// Checks the ffmpeg and ffprobe binaries still run.
+    pub(crate) fn check(&self) -> crate::common::Result<()> {
+        for path in [&self.ffmpeg_path, &self.ffprobe_path] {
+            if !runs(path) {
+                Err(anyhow::anyhow!("{} -version failed", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Cuts `input` from `start` to `end` seconds into `output`, optionally cropped.
+    pub(crate) fn clip(
+        &self,
+        input: impl AsRef<Path>,
+        output: impl AsRef<Path>,
+        start: f64,
+        end: f64,
+        crop: Option<Crop>,
+        cancel: Cancel,
+    ) -> crate::common::Result<()> {
+        clip(input, output, start, end, crop, self.ffmpeg_path.as_path(), self.threads, cancel)
+    }
+
+    /// duration of the media in seconds
+    pub(crate) fn duration(&self, path: impl AsRef<Path>) -> crate::common::Result<f64> {
+        duration(path, self.ffprobe_path.as_path())
+    }
+
+    /// width and height of the video in pixels
+    pub(crate) fn dimensions(&self, path: impl AsRef<Path>) -> crate::common::Result<(u32, u32)> {
+        dimensions(path, self.ffprobe_path.as_path())
+    }
+
     pub(crate) fn thumbnail(
         &self,
         path: impl AsRef<Path>,
@@ -131,3 +299,28 @@ impl FFmpegUtils {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("6.1.1-essentials_build-www.gyan.dev"), Some(vec![6, 1, 1]));
+        assert_eq!(parse_version("n7.0"), Some(vec![7, 0]));
+        assert_eq!(parse_version("N-112345-g0123abcd"), None);
+        assert!(parse_version("4.4.2").unwrap() >= parse_version("4.4").unwrap());
+        assert!(parse_version("4.3.1").unwrap() < parse_version("4.4").unwrap());
+    }
+
+    #[test]
+    fn test_parse_encoders() {
+        let output = "Encoders:
+ V..... = Video
+ ------
+ V....D libx264              libx264 H.264 / AVC (codec h264)
+ A....D aac                  AAC (Advanced Audio Coding)
+";
+        assert_eq!(parse_encoders(output), vec!["libx264", "aac"]);
+    }
+}
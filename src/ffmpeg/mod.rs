@@ -1,3 +1,5 @@
+pub mod clip;
 pub mod common;
+pub mod scheduler;
 pub mod slice;
 pub mod thumbnail;
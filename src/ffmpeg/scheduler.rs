@@ -0,0 +1,271 @@
+//! Transcodes run on a fixed number of workers, so simultaneous uploads don't each start their
+//! own encoders. Waiting ones start by priority class, then with the user running the fewest,
+//! then in order of arrival.
+
+use std::{collections::HashMap, mem, sync::Mutex};
+
+use ioc::{bean, BeanSpec, InitContext};
+use tokio::sync::{oneshot, watch};
+
+/// Classes of transcodes, a waiting `Interactive` one always starts before a `Bulk` one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Priority {
+    /// someone is watching the progress, e.g. an upload
+    Interactive,
+    /// imports and reprocessing
+    Bulk,
+}
+
+struct Waiting {
+    seq: u64,
+    user: String,
+    priority: Priority,
+    grant: oneshot::Sender<()>,
+    position: watch::Sender<usize>,
+}
+
+#[derive(Default)]
+struct State {
+    /// running transcodes per user
+    running: HashMap<String, usize>,
+    waiting: Vec<Waiting>,
+    next_seq: u64,
+}
+
+impl State {
+    /// Indexes of the waiting transcodes in the order they would start, as far as known now.
+    fn order(&self) -> Vec<usize> {
+        let mut running = self.running.clone();
+        let mut left: Vec<usize> = (0..self.waiting.len()).collect();
+        let mut order = Vec::with_capacity(left.len());
+        while let Some(at) = (0..left.len()).min_by_key(|&at| {
+            let waiting = &self.waiting[left[at]];
+            let running = running.get(&waiting.user).copied().unwrap_or(0);
+            (waiting.priority, running, waiting.seq)
+        }) {
+            let index = left.swap_remove(at);
+            *running.entry(self.waiting[index].user.clone()).or_default() += 1;
+            order.push(index);
+        }
+        order
+    }
+
+    /// Starts waiting transcodes while workers are free, then tells the others their position.
+    fn dispatch(&mut self, workers: usize) {
+        let free = workers.saturating_sub(self.running.values().sum());
+        let mut order = self.order();
+        let mut starting: Vec<usize> = order.drain(..free.min(order.len())).collect();
+        // removed from the back, so the indexes left stay valid
+        starting.sort_unstable_by(|a, b| b.cmp(a));
+        for index in starting {
+            let waiting = self.waiting.remove(index);
+            *self.running.entry(waiting.user).or_default() += 1;
+            let _ = waiting.grant.send(());
+        }
+
+        for (position, index) in self.order().into_iter().enumerate() {
+            let position = position + 1;
+            self.waiting[index]
+                .position
+                .send_if_modified(|current| mem::replace(current, position) != position);
+        }
+    }
+
+    fn release(&mut self, user: &str) {
+        if let Some(running) = self.running.get_mut(user) {
+            *running -= 1;
+            if *running == 0 {
+                self.running.remove(user);
+            }
+        }
+    }
+}
+
+pub(crate) struct Scheduler {
+    workers: usize,
+    state: Mutex<State>,
+}
+
+#[bean]
+impl BeanSpec for Scheduler {
+    type Bean = Self;
+
+    fn build(ctx: &mut impl InitContext) -> ioc::Result<Self::Bean> {
+        let workers = ctx.get_config::<usize>("ffmpeg.workers")?;
+        Ok(Self::new(workers))
+    }
+}
+
+impl Scheduler {
+    pub(crate) fn new(workers: usize) -> Self {
+        Self {
+            workers: workers.max(1),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Queues a transcode of `user`, which may start once [`Queued::turn`] says so.
+    pub(crate) fn enqueue(&'static self, user: &str, priority: Priority) -> Queued {
+        let (grant, granted) = oneshot::channel();
+        let (position, position_rx) = watch::channel(0);
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.waiting.push(Waiting {
+            seq,
+            user: user.to_string(),
+            priority,
+            grant,
+            position,
+        });
+        state.dispatch(self.workers);
+
+        Queued {
+            scheduler: self,
+            ticket: Some((seq, user.to_string())),
+            granted,
+            position: position_rx,
+        }
+    }
+
+    /// Transcodes waiting for a worker.
+    pub(crate) fn queued(&self) -> usize {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).waiting.len()
+    }
+
+    /// Forgets a transcode that no longer waits or runs.
+    fn leave(&self, seq: u64, user: &str) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.waiting.iter().position(|waiting| waiting.seq == seq) {
+            Some(index) => drop(state.waiting.remove(index)),
+            // started before anyone took its slot
+            None => state.release(user),
+        }
+        state.dispatch(self.workers);
+    }
+}
+
+/// What a queued transcode learns from [`Queued::turn`].
+pub(crate) enum Turn {
+    /// place in the queue, 1 is next
+    Waiting(usize),
+    Started(Slot),
+}
+
+/// A transcode waiting for a worker, dropping it leaves the queue.
+pub(crate) struct Queued {
+    scheduler: &'static Scheduler,
+    ticket: Option<(u64, String)>,
+    granted: oneshot::Receiver<()>,
+    position: watch::Receiver<usize>,
+}
+
+impl Queued {
+    /// Waits for the next change: a new position in the queue, or the start of the transcode.
+    /// Not to be called again once started.
+    pub(crate) async fn turn(&mut self) -> Turn {
+        tokio::select! {
+            biased;
+            _ = &mut self.granted => Turn::Started(Slot {
+                scheduler: self.scheduler,
+                user: self.ticket.take().map(|(_, user)| user).unwrap_or_default(),
+            }),
+            Ok(()) = self.position.changed() => Turn::Waiting(*self.position.borrow_and_update()),
+        }
+    }
+}
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        if let Some((seq, user)) = self.ticket.take() {
+            self.scheduler.leave(seq, &user);
+        }
+    }
+}
+
+/// A worker taken by a running transcode, freed when dropped.
+pub(crate) struct Slot {
+    scheduler: &'static Scheduler,
+    user: String,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut state = self.scheduler.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.release(&self.user);
+        state.dispatch(self.scheduler.workers);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn scheduler(workers: usize) -> &'static Scheduler {
+        Box::leak(Box::new(Scheduler::new(workers)))
+    }
+
+    async fn started(queued: &mut Queued) -> Slot {
+        loop {
+            if let Turn::Started(slot) = queued.turn().await {
+                return slot;
+            }
+        }
+    }
+
+    fn position(queued: &Queued) -> usize {
+        *queued.position.borrow()
+    }
+
+    #[tokio::test]
+    async fn test_fair_order() {
+        let scheduler = scheduler(1);
+        let mut running = scheduler.enqueue("a", Priority::Interactive);
+        let running = started(&mut running).await;
+
+        let a_bulk = scheduler.enqueue("a", Priority::Bulk);
+        let mut a_second = scheduler.enqueue("a", Priority::Interactive);
+        let a_third = scheduler.enqueue("a", Priority::Interactive);
+        let b_first = scheduler.enqueue("b", Priority::Interactive);
+        assert_eq!(scheduler.queued(), 4);
+
+        // b has nothing running while a has, bulk work comes last
+        assert_eq!(position(&b_first), 1);
+        assert_eq!(position(&a_second), 2);
+        assert_eq!(position(&a_third), 3);
+        assert_eq!(position(&a_bulk), 4);
+
+        // once a has nothing running either, arrival decides
+        drop(running);
+        let _a = started(&mut a_second).await;
+        assert_eq!(position(&b_first), 1);
+        assert_eq!(position(&a_third), 2);
+        assert_eq!(position(&a_bulk), 3);
+
+        // leaving the queue moves the ones behind
+        drop(b_first);
+        assert_eq!(position(&a_third), 1);
+        assert_eq!(position(&a_bulk), 2);
+        assert_eq!(scheduler.queued(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_workers() {
+        let scheduler = scheduler(2);
+        let mut first = scheduler.enqueue("a", Priority::Bulk);
+        let mut second = scheduler.enqueue("a", Priority::Bulk);
+        let mut third = scheduler.enqueue("a", Priority::Bulk);
+        let first = started(&mut first).await;
+        let _second = started(&mut second).await;
+        assert!(matches!(third.turn().await, Turn::Waiting(1)));
+
+        // a granted transcode dropped before starting frees its worker too
+        let fourth = scheduler.enqueue("a", Priority::Bulk);
+        drop(first);
+        drop(third);
+        assert_eq!(scheduler.queued(), 0);
+        drop(fourth);
+        assert_eq!(scheduler.state.lock().unwrap().running.values().sum::<usize>(), 1);
+    }
+}
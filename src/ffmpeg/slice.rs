@@ -4,10 +4,14 @@ use std::{
     fs,
     future::Future,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 use tokio::{
     runtime::Handle,
-    sync::mpsc::{error::SendError, Sender},
+    sync::{
+        mpsc::{error::SendError, Sender},
+        watch,
+    },
 };
 use tracing::debug;
 
@@ -106,6 +110,9 @@ mod tests {
     }
 }
 
+/// Renditions produced by [`Slice`], each in a sub directory of the output.
+pub(crate) const RENDITIONS: [&str; 2] = ["720p", "1080p"];
+
 #[derive(Debug)]
 pub(crate) enum SliceEvent {
     Wip(FfmpegEvent),
@@ -113,10 +120,38 @@ pub(crate) enum SliceEvent {
     Err(anyhow::Error),
 }
 
+/// Stops a running [`Slice`], ffmpeg is killed at its next progress event.
+#[derive(Debug, Clone)]
+pub(crate) struct Cancel(Arc<watch::Sender<bool>>);
+
+impl Default for Cancel {
+    fn default() -> Self {
+        Self(Arc::new(watch::channel(false).0))
+    }
+}
+
+impl Cancel {
+    pub(crate) fn cancel(&self) {
+        self.0.send_replace(true);
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Completes once cancelled, for work waiting rather than running.
+    pub(crate) async fn cancelled(&self) {
+        let mut rx = self.0.subscribe();
+        // the sender lives as long as `self`
+        let _ = rx.wait_for(|cancelled| *cancelled).await;
+    }
+}
+
 pub(crate) struct Slice {
     cmd: FfmpegCommand,
     output: PathBuf,
     tx: Sender<SliceEvent>,
+    cancel: Cancel,
 }
 
 fn run_async<F: Future>(fut: F) -> F::Output {
@@ -134,7 +169,9 @@ impl Slice {
         input: impl AsRef<Path>,
         output_dir: impl AsRef<Path>,
         ffmpeg_path: impl AsRef<OsStr>,
+        threads: usize,
         tx: Sender<SliceEvent>,
+        cancel: Cancel,
     ) -> Result<Self> {
         let output = output_dir.as_ref().to_path_buf();
 
@@ -144,9 +181,13 @@ impl Slice {
         let slice_1080p = output_dir.as_ref().join("1080p");
         fs::create_dir_all(&slice_1080p)?;
 
+        // per encoder, 0 leaves it to ffmpeg
+        let threads = threads.to_string();
+
         let mut cmd = FfmpegCommand::new_with_path(ffmpeg_path);
         cmd.input(input.as_ref().to_string_lossy())
             .codec_video("libx264")
+            .args(["-threads", &threads])
             .args(["-filter:v", "scale=1280:-1", "-g", "30"])
             .args(["-profile:v", "main", "-level", "4.0"])
             .args(["-b:v", "1500k", "-maxrate", "1500k", "-bufsize", "2250k"])
@@ -162,6 +203,7 @@ impl Slice {
             ])
             .arg(slice_720p.join("slice.m3u8"))
             .codec_video("libx264")
+            .args(["-threads", &threads])
             .args(["-filter:v", "scale=1280:-1", "-g", "30"])
             .args(["-profile:v", "main", "-level", "4.2"])
             .args(["-b:v", "3000k", "-maxrate", "3000k", "-bufsize", "4500k"])
@@ -177,7 +219,12 @@ impl Slice {
             ])
             .arg(slice_1080p.join("slice.m3u8"));
 
-        Ok(Self { cmd, output, tx })
+        Ok(Self {
+            cmd,
+            output,
+            tx,
+            cancel,
+        })
     }
 
     pub(crate) fn run(self) -> Result<()> {
@@ -185,11 +232,17 @@ impl Slice {
             mut cmd,
             output,
             tx,
+            cancel,
         } = self;
 
         let mut child = cmd.spawn()?;
 
         for e in child.iter()? {
+            if cancel.is_cancelled() {
+                child.kill()?;
+                child.wait()?;
+                return Err(AppError::TranscodeCancelled);
+            }
             run_async(tx.send(SliceEvent::Wip(e)))?;
         }
 
@@ -212,6 +265,7 @@ impl Slice {
                 "Failed to get slice {}",
                 status
             ))))?;
+            Err(anyhow::anyhow!("Failed to get slice {}", status))?;
         }
         Ok(())
     }
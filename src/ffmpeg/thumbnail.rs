@@ -2,11 +2,37 @@ use ffmpeg_sidecar::command::FfmpegCommand;
 use rand::{thread_rng, Rng};
 use std::{ffi::OsStr, path::Path, process::Command};
 
+use tracing::debug;
+
 use crate::common::Result;
 
-fn duration(path: impl AsRef<Path>, ffprobe: impl AsRef<OsStr>) -> Result<f64> {
-    println!("haha:{:?}, {:?}", path.as_ref(), ffprobe.as_ref());
-    let output = dbg!(Command::new(ffprobe)
+/// Width and height of the first video stream, in pixels.
+pub(crate) fn dimensions(path: impl AsRef<Path>, ffprobe: impl AsRef<OsStr>) -> Result<(u32, u32)> {
+    let output = Command::new(ffprobe)
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("v:0")
+        .arg("-show_entries")
+        .arg("stream=width,height")
+        .arg("-of")
+        .arg("csv=s=x:p=0")
+        .arg(path.as_ref())
+        .output()?;
+
+    if !output.status.success() {
+        Err(anyhow::anyhow!("Failed to get dimensions"))?
+    }
+    let string = String::from_utf8_lossy(&output.stdout);
+    let (width, height) = string
+        .trim()
+        .split_once('x')
+        .ok_or_else(|| anyhow::anyhow!("Failed to parse dimensions"))?;
+    Ok((width.parse()?, height.parse()?))
+}
+
+pub(crate) fn duration(path: impl AsRef<Path>, ffprobe: impl AsRef<OsStr>) -> Result<f64> {
+    let output = Command::new(ffprobe)
         .arg("-v")
         .arg("error")
         .arg("-show_entries")
@@ -14,11 +40,12 @@ fn duration(path: impl AsRef<Path>, ffprobe: impl AsRef<OsStr>) -> Result<f64> {
         .arg("-of")
         .arg("default=noprint_wrappers=1")
         .arg(path.as_ref())
-        .output()?);
+        .output()?;
 
     if output.status.success() {
-        let string = dbg!(String::from_utf8_lossy(&output.stdout));
-        if dbg!(string.starts_with("duration=")) {
+        let string = String::from_utf8_lossy(&output.stdout);
+        debug!("duration of {:?}: {}", path.as_ref(), string.trim());
+        if string.starts_with("duration=") {
             Ok(string.trim_start_matches("duration=").trim().parse()?)
         } else {
             Err(anyhow::anyhow!("Failed to parse duration"))?
@@ -65,12 +92,8 @@ mod tests {
     fn test_thumbnail() {
         auto_download().unwrap();
 
-        let time = std::time::Instant::now();
-
         let ffmpeg = ffmpeg_path();
         let ffprobe = ffprobe_path();
         thumbnail("./video_01.mp4", "1.jpeg", &ffmpeg, &ffprobe).expect("");
-
-        dbg!(time.elapsed());
     }
 }
@@ -0,0 +1,204 @@
+use std::{
+    collections::HashSet,
+    future::Future,
+    path::PathBuf,
+    time::Instant,
+};
+
+use ioc::{mvc, Bean};
+use poem_openapi::{payload::Json, ApiResponse, Enum, Object};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use tokio::{fs, task::spawn_blocking};
+
+use crate::{
+    common::Result,
+    db::{Db, MIGRATOR},
+    ffmpeg::common::FFmpegUtils,
+    metrics::metered,
+};
+
+/// Outcome of a check, an `Ok` carries an optional detail and an `Err` the reason it failed.
+type CheckResult = std::result::Result<Option<String>, String>;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Enum)]
+#[serde(rename_all = "lowercase")]
+#[oai(rename_all = "lowercase")]
+pub(crate) enum CheckStatus {
+    Ok,
+    Fail,
+}
+
+#[derive(Serialize, Debug, Object)]
+pub(crate) struct Check {
+    name: String,
+    status: CheckStatus,
+    /// what failed, or a measurement like free space for passing checks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[oai(skip_serializing_if_is_none)]
+    detail: Option<String>,
+    elapsed_ms: u64,
+}
+
+#[derive(Serialize, Debug, Object)]
+pub(crate) struct Health {
+    status: CheckStatus,
+    checks: Vec<Check>,
+}
+
+impl Health {
+    fn of(checks: Vec<Check>) -> Self {
+        let status = if checks.iter().all(|check| check.status == CheckStatus::Ok) {
+            CheckStatus::Ok
+        } else {
+            CheckStatus::Fail
+        };
+        Self { status, checks }
+    }
+}
+
+#[derive(ApiResponse)]
+pub(crate) enum HealthResponse {
+    #[oai(status = 200)]
+    Ok(Json<Health>),
+    #[oai(status = 503)]
+    Unavailable(Json<Health>),
+}
+
+impl From<Health> for HealthResponse {
+    fn from(health: Health) -> Self {
+        match health.status {
+            CheckStatus::Ok => Self::Ok(Json(health)),
+            CheckStatus::Fail => Self::Unavailable(Json(health)),
+        }
+    }
+}
+
+async fn check<F>(name: &str, run: F) -> Check
+where
+    F: Future<Output = CheckResult>,
+{
+    let started = Instant::now();
+    let (status, detail) = match run.await {
+        Ok(detail) => (CheckStatus::Ok, detail),
+        Err(reason) => (CheckStatus::Fail, Some(reason)),
+    };
+    Check {
+        name: name.to_string(),
+        status,
+        detail,
+        elapsed_ms: started.elapsed().as_millis() as u64,
+    }
+}
+
+#[derive(Bean)]
+pub(crate) struct HealthMvc {
+    #[inject(bean = Db)]
+    db: &'static SqlitePool,
+    #[inject(bean)]
+    ffmpeg: &'static FFmpegUtils,
+    #[inject(config = "storage.dir")]
+    storage_dir: PathBuf,
+    /// storage is not ready below this many free megabytes
+    #[inject(config = "health.min-free-mb")]
+    min_free_mb: u64,
+}
+
+impl HealthMvc {
+    async fn database(&self) -> CheckResult {
+        sqlx::query("SELECT 1")
+            .execute(self.db)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(None)
+    }
+
+    async fn migrations(&self) -> CheckResult {
+        let applied: HashSet<i64> =
+            sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success = 1")
+                .fetch_all(self.db)
+                .await
+                .map_err(|e| e.to_string())?
+                .into_iter()
+                .collect();
+
+        let pending: Vec<String> = MIGRATOR
+            .iter()
+            .filter(|migration| !applied.contains(&migration.version))
+            .map(|migration| format!("{}_{}", migration.version, migration.description))
+            .collect();
+
+        if pending.is_empty() {
+            Ok(Some(format!("{} applied", applied.len())))
+        } else {
+            Err(format!("pending: {}", pending.join(", ")))
+        }
+    }
+
+    async fn ffmpeg(&self) -> CheckResult {
+        let ffmpeg = self.ffmpeg;
+        spawn_blocking(move || ffmpeg.check())
+            .await
+            .map_err(|e| e.to_string())?
+            .map(|_| None)
+            .map_err(|e| e.to_string())
+    }
+
+    async fn storage(&self) -> CheckResult {
+        let probe = self.storage_dir.join(".readyz");
+        let not_writable = |e: std::io::Error| format!("{} is not writable: {e}", self.storage_dir.display());
+        fs::write(&probe, b"ok").await.map_err(not_writable)?;
+        fs::remove_file(&probe).await.map_err(not_writable)?;
+
+        let dir = self.storage_dir.clone();
+        let free = spawn_blocking(move || fs2::available_space(dir))
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.to_string())?;
+
+        let free_mb = free / 1024 / 1024;
+        if free_mb < self.min_free_mb {
+            Err(format!("{free_mb}MB free, below {}MB", self.min_free_mb))
+        } else {
+            Ok(Some(format!("{free_mb}MB free")))
+        }
+    }
+}
+
+#[mvc]
+impl HealthMvc {
+    /// Liveness, fails only when the database can't be reached
+    #[oai(path = "/healthz", method = "get", transform = "metered")]
+    async fn healthz(&self) -> Result<HealthResponse> {
+        let checks = vec![check("database", self.database()).await];
+        Ok(Health::of(checks).into())
+    }
+
+    /// Readiness, checks every dependency needed to serve requests
+    #[oai(path = "/readyz", method = "get", transform = "metered")]
+    async fn readyz(&self) -> Result<HealthResponse> {
+        let (database, migrations, ffmpeg, storage) = tokio::join!(
+            check("database", self.database()),
+            check("migrations", self.migrations()),
+            check("ffmpeg", self.ffmpeg()),
+            check("storage", self.storage()),
+        );
+        Ok(Health::of(vec![database, migrations, ffmpeg, storage]).into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_health_status() {
+        let ok = check("ok", async { Ok(None) }).await;
+        let fail = check("fail", async { Err("down".to_string()) }).await;
+        assert_eq!(fail.detail.as_deref(), Some("down"));
+
+        assert_eq!(Health::of(vec![ok]).status, CheckStatus::Ok);
+        let ok = check("ok", async { Ok(None) }).await;
+        assert_eq!(Health::of(vec![ok, fail]).status, CheckStatus::Fail);
+    }
+}
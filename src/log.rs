@@ -1,9 +1,19 @@
 use ioc::{log::LogPatcher, mvc, Bean};
 use poem_openapi::{payload::Json, Object};
 use serde::Deserialize;
+use serde_json::json;
 use tracing::{debug, info, trace};
 
-use crate::common::{Response, Result};
+use crate::{
+    audit::{
+        biz::{AuditEntry, AuditService},
+        AuditAction,
+    },
+    auth::{account::AccountService, apikey::JwtAuth},
+    common::{AppError, Response, Result},
+    metrics::metered,
+    util::poem::ClientInfo,
+};
 
 #[derive(Deserialize, Object)]
 struct LogDirective {
@@ -15,11 +25,15 @@ struct LogDirective {
 pub struct Logger {
     #[inject(bean)]
     patcher: &'static LogPatcher,
+    #[inject(bean)]
+    accounts: &'static AccountService,
+    #[inject(bean)]
+    audit: &'static AuditService,
 }
 
 #[mvc]
 impl Logger {
-    #[oai(path = "/loggers", method = "get")]
+    #[oai(path = "/loggers", method = "get", transform = "metered")]
     async fn index(&self) -> Result<Response<String>> {
         info!("get logger");
         debug!("debug get logger: {:?}", self.patcher.to_string());
@@ -27,10 +41,25 @@ impl Logger {
         Ok(Response::ok(self.patcher.to_string()?))
     }
 
-    #[oai(path = "/loggers", method = "post")]
-    async fn set_logger(&self, body: Json<LogDirective>) -> Result<Response<String>> {
+    /// Admin only, recorded in the audit log
+    #[oai(path = "/loggers", method = "post", transform = "metered")]
+    async fn set_logger(
+        &self,
+        body: Json<LogDirective>,
+        auth: JwtAuth,
+        client: ClientInfo,
+    ) -> Result<Response<String>> {
+        if !self.accounts.is_admin(&auth) {
+            return Err(AppError::AdminRequired);
+        }
+
+        let previous = self.patcher.to_string()?;
         let split = body.value.split(',');
         self.patcher.reload(split)?;
+
+        let entry = AuditEntry::by(&auth, AuditAction::LogLevelChange, "loggers")
+            .details(json!({ "from": previous, "to": body.value }));
+        self.audit.record(entry, &client).await;
         Ok(Response::ok("ok".to_string()))
     }
 }
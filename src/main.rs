@@ -4,14 +4,22 @@ extern crate core;
 use clap::Parser;
 use ioc::{export, run};
 
+mod audit;
 mod auth;
+mod cli;
 mod client;
+mod collection;
 mod common;
 mod db;
+mod event;
 mod ffmpeg;
+mod health;
 mod log;
 mod material;
+mod metrics;
 mod util;
+mod webhook;
+mod workspace;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -27,6 +35,9 @@ struct Args {
     /// Profile to use
     #[arg(short, long, default_value = "prod")]
     profile: String,
+
+    #[command(subcommand)]
+    command: Option<cli::Command>,
 }
 
 export!(root = "src/main.rs");
@@ -36,6 +47,11 @@ fn main() -> common::Result<()> {
 
     println!("{args:?}!");
 
+    match args.command {
+        None | Some(cli::Command::Serve) => {}
+        Some(command) => return command.run(&args.config_dir, &args.profile),
+    }
+
     let _ = run!(
         debug = args.debug;
         dir = args.config_dir.as_str();
@@ -1,36 +1,74 @@
 use crate::common::AppError::WrongMaterialType;
-use crate::ffmpeg::slice::SliceEvent;
-use crate::material::mvc::{ImagesUploadPayload, MaterialPatchRequest};
+use crate::ffmpeg::{
+    clip::Crop,
+    scheduler::{Priority, Scheduler, Slot, Turn},
+    slice::{Cancel, SliceEvent},
+};
+use crate::material::mvc::{ClipRequest, ImagesUploadPayload, MaterialPatchRequest};
 use crate::{
+    audit::{
+        biz::{AuditEntry, AuditService},
+        AuditAction,
+    },
     auth::jwt::Claims,
     common::{AppError, FormatedEvent, PageResult, Result},
     db::Db,
+    event::{EventBus, EventKind},
     ffmpeg::common::FFmpegUtils,
+    metrics::Metrics,
     material::{
+        cursor::{Cursor, CursorKey},
+        instance::Instance,
         mvc::{SearchCondition, UploadPayload},
-        storage::{Id, LocalStorage, SavedId, Storage},
-        STATE_OK, TYPE_IMAGE, TYPE_VIDEO,
+        progress::ProgressRegistry,
+        storage::{checksum, Id, LocalStorage, SavedId, Storage},
+        MaterialState, MaterialType, SortField, STATE_CANCELLED, STATE_FAILED, STATE_OK,
+        STATE_PROCESSING, TYPE_IMAGE, TYPE_VIDEO,
     },
-    util::poem::BaseUrl,
+    util::poem::{BaseUrl, ClientInfo},
+    webhook::{biz::WebhookService, MaterialEvent},
+    workspace::{biz::WorkspaceService, WorkspaceRole},
 };
 use chrono::{NaiveDateTime, Utc};
 use ioc::Bean;
 use poem_openapi::{Object, Union};
 use serde::{Deserialize, Serialize};
-use sqlx::{query_as_with, query_scalar_with, Arguments, QueryBuilder, SqlitePool};
-use std::{borrow::Cow, ops::Deref};
-use tokio::{sync::mpsc::Sender, task::spawn_blocking};
-use tracing::{debug, info, warn};
+use serde_json::json;
+use sqlx::{
+    query_as_with, query_scalar_with, query_with, sqlite::SqliteArguments, Arguments, QueryBuilder, Row,
+    SqlitePool,
+};
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    ops::Deref,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    fs::File,
+    io::AsyncRead,
+    sync::{broadcast::error::RecvError, mpsc::Sender, OwnedSemaphorePermit},
+    task::{spawn, spawn_blocking},
+    time::sleep,
+};
+use tracing::{debug, error, info, warn};
 
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "state")]
 pub(crate) enum VideoUploadEvent<'a> {
     #[serde(rename(serialize = "already_existed"))]
     Existed { id: Cow<'a, Id> },
+    #[serde(rename(serialize = "queued"))]
+    Queued { id: Cow<'a, Id>, position: usize },
     #[serde(rename(serialize = "wip"))]
     Progress { id: Cow<'a, Id>, progress: u16 },
     #[serde(rename(serialize = "ok"))]
     Ok { id: Cow<'a, Id> },
+    #[serde(rename(serialize = "failed"))]
+    Failed { id: Cow<'a, Id> },
+    #[serde(rename(serialize = "cancelled"))]
+    Cancelled { id: Cow<'a, Id> },
 }
 
 impl<'a> VideoUploadEvent<'a> {
@@ -40,6 +78,13 @@ impl<'a> VideoUploadEvent<'a> {
         }
     }
 
+    pub(crate) fn queued(id: &'a Id, position: usize) -> Self {
+        Self::Queued {
+            id: Cow::Borrowed(id),
+            position,
+        }
+    }
+
     pub(crate) fn wip(id: &'a Id, progress: u16) -> Self {
         Self::Progress {
             id: Cow::Borrowed(id),
@@ -52,6 +97,18 @@ impl<'a> VideoUploadEvent<'a> {
             id: Cow::Borrowed(id),
         }
     }
+
+    pub(crate) fn failed(id: &'a Id) -> Self {
+        Self::Failed {
+            id: Cow::Borrowed(id),
+        }
+    }
+
+    pub(crate) fn cancelled(id: &'a Id) -> Self {
+        Self::Cancelled {
+            id: Cow::Borrowed(id),
+        }
+    }
 }
 
 impl From<VideoUploadEvent<'_>> for FormatedEvent {
@@ -61,16 +118,37 @@ impl From<VideoUploadEvent<'_>> for FormatedEvent {
                 id: id.to_string(),
                 progress: -1,
                 state: "already_existed".to_string(),
+                position: None,
+            },
+            VideoUploadEvent::Queued { id, position } => Self {
+                id: id.to_string(),
+                progress: 15,
+                state: "queued".to_string(),
+                position: Some(position),
             },
             VideoUploadEvent::Progress { id, progress } => Self {
                 id: id.to_string(),
                 progress: progress as i16,
                 state: "wip".to_string(),
+                position: None,
             },
             VideoUploadEvent::Ok { id } => Self {
                 id: id.to_string(),
                 progress: 100,
                 state: "ok".to_string(),
+                position: None,
+            },
+            VideoUploadEvent::Failed { id } => Self {
+                id: id.to_string(),
+                progress: -1,
+                state: "failed".to_string(),
+                position: None,
+            },
+            VideoUploadEvent::Cancelled { id } => Self {
+                id: id.to_string(),
+                progress: -1,
+                state: "cancelled".to_string(),
+                position: None,
             },
         }
     }
@@ -79,10 +157,69 @@ impl From<VideoUploadEvent<'_>> for FormatedEvent {
 #[cfg(test)]
 mod test {
     use crate::common::FormatedEvent;
-    use crate::material::biz::VideoUploadEvent;
+    use crate::ffmpeg::clip::Crop;
+    use crate::common::Page;
+    use crate::material::biz::{check_clip, counts_query, VideoUploadEvent};
+    use crate::material::mvc::SearchCondition;
+    use crate::material::MaterialType;
+    use crate::material::mvc::ClipRequest;
     use crate::material::storage::Id;
     use serde_json::{json, Value};
 
+    #[test]
+    fn test_check_clip() {
+        let clip = |start: f64, end: f64, crop: Option<Crop>| ClipRequest {
+            start,
+            end,
+            crop,
+            name: None,
+            desc: None,
+            tags: None,
+        };
+        assert!(check_clip(&clip(1.5, 10.0, None), Some(60.0)).is_ok());
+        assert!(check_clip(&clip(1.5, 90.0, None), None).is_ok());
+        assert!(check_clip(&clip(1.5, 90.0, None), Some(60.0)).is_err());
+        assert!(check_clip(&clip(10.0, 10.0, None), Some(60.0)).is_err());
+        assert!(check_clip(&clip(-1.0, 10.0, None), Some(60.0)).is_err());
+        assert!(check_clip(&clip(f64::NAN, 10.0, None), Some(60.0)).is_err());
+
+        let odd = Crop {
+            x: 0,
+            y: 0,
+            width: 101,
+            height: 100,
+        };
+        assert!(check_clip(&clip(1.5, 10.0, Some(odd)), Some(60.0)).is_err());
+    }
+
+    #[test]
+    fn test_counts_query() -> anyhow::Result<()> {
+        let condition = |query: Option<&str>, r#type: Option<MaterialType>| SearchCondition {
+            tags: None,
+            page: Page { page: 1, size: 1 },
+            query: query.map(str::to_string),
+            r#type,
+            sort: None,
+            direction: None,
+            cursor: None,
+            created_from: None,
+            created_to: None,
+            facets: None,
+        };
+
+        let conditions = [condition(Some("cat"), None), condition(None, Some(MaterialType::Image))];
+        let (sql, _) = counts_query(&conditions, "ws")?;
+
+        assert_eq!(
+            sql,
+            "SELECT \
+             COALESCE(SUM(CASE WHEN workspace_id = ? AND (name LIKE ? or description LIKE ?) THEN 1 ELSE 0 END), 0), \
+             COALESCE(SUM(CASE WHEN workspace_id = ? AND type = ? THEN 1 ELSE 0 END), 0) \
+             FROM materials WHERE workspace_id = ?"
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_already_existed_event() -> anyhow::Result<()> {
         let test: FormatedEvent = VideoUploadEvent::existed(&Id("test".to_string())).into();
@@ -125,6 +262,28 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_queued() -> anyhow::Result<()> {
+        let test: FormatedEvent = VideoUploadEvent::queued(&Id("test".to_string()), 3).into();
+
+        let string = serde_json::to_string(&test)?;
+        let json: Value = serde_json::from_str(&string)?;
+
+        assert_eq!(
+            json,
+            json!(
+                {
+                    "id": "test",
+                    "progress": 15,
+                    "state": "queued",
+                    "position": 3
+                }
+            )
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_ok() -> anyhow::Result<()> {
         let test: FormatedEvent = VideoUploadEvent::ok(&Id("test".to_string())).into();
@@ -155,15 +314,96 @@ pub(crate) struct MaterialsService {
     repo: &'static MaterialsRepo,
     #[inject(bean)]
     ffmpeg: &'static FFmpegUtils,
+    #[inject(bean)]
+    workspaces: &'static WorkspaceService,
+    #[inject(bean)]
+    audit: &'static AuditService,
+    #[inject(bean)]
+    metrics: &'static Metrics,
+    #[inject(bean)]
+    webhooks: &'static WebhookService,
+    #[inject(bean)]
+    events: &'static EventBus,
+    #[inject(bean)]
+    progress: &'static ProgressRegistry,
+    #[inject(bean)]
+    scheduler: &'static Scheduler,
+    #[inject(bean)]
+    instance: &'static Instance,
 }
 
 #[derive(Serialize, Deserialize, Debug, Object)]
 pub(crate) struct MaterialVideo {
     id: Id,
     name: String,
+    /// slices are only available once `ok`
+    state: MaterialState,
     raw: String,
     thumbnail: String,
     description: String,
+    /// what a clip was cut from, only in the detail of a material
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[oai(skip_serializing_if_is_none)]
+    source: Option<ClipSource>,
+}
+
+/// Part of a material a clip was cut from.
+#[derive(Serialize, Deserialize, Debug, Clone, Object)]
+pub(crate) struct ClipSource {
+    /// the source material, which may be deleted since
+    id: String,
+    /// seconds into the source
+    start: f64,
+    end: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[oai(skip_serializing_if_is_none)]
+    crop: Option<Crop>,
+}
+
+#[derive(sqlx::FromRow)]
+struct ClipSourceRow {
+    source_id: String,
+    start_seconds: f64,
+    end_seconds: f64,
+    crop_x: Option<i64>,
+    crop_y: Option<i64>,
+    crop_width: Option<i64>,
+    crop_height: Option<i64>,
+}
+
+impl From<ClipSourceRow> for ClipSource {
+    fn from(row: ClipSourceRow) -> Self {
+        let crop = match (row.crop_x, row.crop_y, row.crop_width, row.crop_height) {
+            (Some(x), Some(y), Some(width), Some(height)) => Some(Crop {
+                x: x as u32,
+                y: y as u32,
+                width: width as u32,
+                height: height as u32,
+            }),
+            _ => None,
+        };
+        Self {
+            id: row.source_id,
+            start: row.start_seconds,
+            end: row.end_seconds,
+            crop,
+        }
+    }
+}
+
+/// Checks a clip of a video lasting `duration` seconds, when known, can be cut as requested.
+fn check_clip(request: &ClipRequest, duration: Option<f64>) -> Result<()> {
+    let (start, end) = (request.start, request.end);
+    if !(start >= 0.0 && start < end && end.is_finite()) {
+        return Err(AppError::InvalidClip(format!("{start}s to {end}s is not a range of the source")));
+    }
+    if let Some(duration) = duration.filter(|duration| end > *duration) {
+        return Err(AppError::InvalidClip(format!("{end}s is past the end of the source at {duration}s")));
+    }
+    if let Some(crop) = &request.crop {
+        crop.check().map_err(AppError::InvalidClip)?;
+    }
+    Ok(())
 }
 
 #[derive(Serialize, Deserialize, Debug, Object)]
@@ -190,6 +430,40 @@ pub(crate) enum MaterialDetail {
     Image(MaterialImage),
 }
 
+#[derive(Serialize, Deserialize, Debug, Object)]
+pub(crate) struct FacetCount {
+    value: String,
+    count: u64,
+}
+
+impl From<(String, i64)> for FacetCount {
+    fn from((value, count): (String, i64)) -> Self {
+        Self {
+            value,
+            count: count as u64,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Object)]
+pub(crate) struct SearchFacets {
+    tags: Vec<FacetCount>,
+    types: Vec<FacetCount>,
+    creators: Vec<FacetCount>,
+    /// upload month formatted as `YYYY-MM`
+    months: Vec<FacetCount>,
+}
+
+#[derive(Serialize, Debug, Object)]
+pub(crate) struct SearchResult {
+    #[serde(flatten)]
+    #[oai(flatten)]
+    page: PageResult<MaterialDetail>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[oai(skip_serializing_if_is_none)]
+    facets: Option<SearchFacets>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Object)]
 pub(crate) struct MaterialImage {
     id: Id,
@@ -198,84 +472,530 @@ pub(crate) struct MaterialImage {
     description: String,
 }
 
+/// Outcome of [`MaterialsService::import_file`].
+pub(crate) enum Imported {
+    New(Id),
+    /// a material with the same content already exists
+    Duplicate(Id),
+}
+
+/// Name, description and tags of a material created from a file.
+pub(crate) struct MaterialInfo {
+    pub(crate) name: String,
+    pub(crate) description: Option<String>,
+    pub(crate) tags: Vec<String>,
+}
+
 impl MaterialsService {
+    /// Tells open event streams and subscribed webhooks what happened to a material.
+    async fn publish(&self, kind: EventKind, material: MaterialEvent) {
+        if let Some(event) = kind.webhook() {
+            self.webhooks.emit(event, material.clone()).await;
+        }
+        self.events.publish(kind, material, None);
+    }
+
     pub(crate) async fn search(
         &self,
         condition: SearchCondition,
         base_url: BaseUrl,
         claims: Claims,
-    ) -> Result<PageResult<MaterialDetail>> {
-        let result = self.repo.search(&condition, &claims.id).await?;
-        result.transfer(|material| self.transfer(&base_url, material))
+    ) -> Result<SearchResult> {
+        self.workspaces.require(&claims, WorkspaceRole::Viewer).await?;
+        let result = self.repo.search(&condition, &claims.workspace).await?;
+        let facets = if condition.facets.unwrap_or(false) {
+            Some(self.repo.facets(&condition, &claims.workspace).await?)
+        } else {
+            None
+        };
+        let page = result.transfer(|material| self.transfer(&base_url, material))?;
+        Ok(SearchResult { page, facets })
+    }
+
+    /// Number of materials of the active workspace matching each condition, ignoring paging.
+    pub(crate) async fn counts(&self, conditions: &[SearchCondition], claims: &Claims) -> Result<Vec<u64>> {
+        self.workspaces.require(claims, WorkspaceRole::Viewer).await?;
+        self.repo.counts(conditions, &claims.workspace).await
     }
 
+    /// Stores and transcodes the video, holding `_permit` until done. Callers check the
+    /// workspace role.
     pub(crate) async fn upload(
         &self,
         upload: UploadPayload,
+        _permit: OwnedSemaphorePermit,
         tx: Sender<FormatedEvent>,
         claims: Claims,
     ) -> Result<()> {
-        let file_name = upload.file.file_name().unwrap_or("no_name").to_string();
-        let raw_file = upload.file.into_file();
+        let info = MaterialInfo {
+            name: upload.file.file_name().unwrap_or("no_name").to_string(),
+            description: upload.desc,
+            tags: upload.tags.map(|tags| tags.to_vec()).unwrap_or_default(),
+        };
+        let size = upload.file.size();
 
+        self.save_video(upload.file.into_file(), info, Priority::Interactive, &tx, &claims).await?;
+        self.metrics.uploaded(MaterialType::Video, size);
+        Ok(())
+    }
+
+    /// Stores `source` as a video of the active workspace and transcodes it, reporting progress
+    /// through `tx`. Callers check the workspace role.
+    pub(crate) async fn save_video(
+        &self,
+        source: impl AsyncRead + Unpin,
+        info: MaterialInfo,
+        priority: Priority,
+        tx: &Sender<FormatedEvent>,
+        claims: &Claims,
+    ) -> Result<Id> {
         let id = Id::new_uuid();
+        let saved = self.storage.save(&claims.workspace, &id, source).await?;
+        self.create_video(id, saved, info, priority, tx, claims).await
+    }
 
-        match self.storage.save(&id, raw_file).await? {
+    /// Transcodes the video just stored as `id` and creates its material.
+    async fn create_video(
+        &self,
+        id: Id,
+        saved: SavedId,
+        info: MaterialInfo,
+        priority: Priority,
+        tx: &Sender<FormatedEvent>,
+        claims: &Claims,
+    ) -> Result<Id> {
+        let workspace = claims.workspace.as_str();
+        let file_name = info.name.as_str();
+
+        match saved {
             SavedId::Existed => {
                 warn!("file {file_name} is existed! return id {id}!");
-                tx.send(VideoUploadEvent::existed(&id).into()).await?;
+                self.report(tx, VideoUploadEvent::existed(&id)).await;
             }
-            SavedId::New => {
+            SavedId::New { checksum } => {
                 info!("new file {file_name} with id {id}");
-                tx.send(VideoUploadEvent::wip(&id, 15).into()).await?;
 
-                let raw = self.storage.raw_file(&id).await?;
-                let thumbnail_assert = self.storage.assert_file(&id, "thumbnail.jpeg").await?;
+                let raw = self.storage.raw_file(workspace, &id).await?;
+                let size = tokio::fs::metadata(&raw).await?.len();
 
-                let raw_thumbnail = raw.clone();
-                let ffmpeg = self.ffmpeg;
-                spawn_blocking(move || -> Result<()> {
-                    ffmpeg.thumbnail(&raw_thumbnail, &thumbnail_assert)?;
-                    Ok(())
-                })
-                    .await??;
+                let material = Material::new_video(
+                    id.to_string(),
+                    info.name,
+                    info.description,
+                    claims.id.clone(),
+                    claims.workspace.clone(),
+                )
+                    .with_media(Some(size), None)
+                    .with_checksum(checksum);
+
+                // in flight before the row exists, so whoever sees it `processing` can attach
+                // to or cancel it
+                let cancel = self.begin(tx, &claims.workspace, VideoUploadEvent::wip(&id, 15)).await;
+                if let Err(e) = self.repo.save(&material, Some(&info.tags)).await {
+                    self.progress.report(&VideoUploadEvent::failed(&id).into());
+                    return Err(e);
+                }
+                self.publish(EventKind::Created, material.event()).await;
 
-                info!("save thumbnail: {file_name} with id {id}");
-                tx.send(VideoUploadEvent::wip(&id, 25).into()).await?;
+                self.process(&material, &raw, priority, tx, cancel).await?;
+            }
+        }
 
-                let slice_raw = raw.clone();
-                let mut rx = ffmpeg.slice2(&slice_raw, slice_raw.parent().expect("not here")).await?;
+        Ok(id)
+    }
 
-                let mut progress = 26;
+    /// Passes a processing event to the uploader, if still listening, and to clients attached to
+    /// the progress of the material. Processing goes on when the uploader leaves.
+    async fn report(&self, tx: &Sender<FormatedEvent>, event: VideoUploadEvent<'_>) {
+        let event = FormatedEvent::from(event);
+        self.progress.report(&event);
+        let _ = tx.send(event).await;
+    }
 
-                while let Some(event) = rx.recv().await {
-                    match event {
-                        SliceEvent::Ok => {
-                            info!("save slice: {file_name} with id {id}");
-                            tx.send(VideoUploadEvent::wip(&id, 75).into()).await?;
-                        }
-                        SliceEvent::Wip(e) => {
-                            debug!("ffmpeg {e:?}");
-                            tx.send(VideoUploadEvent::wip(&id, progress).into()).await?;
-                            if progress < 75 {
-                                progress += 1;
-                            }
+    /// Like [`report`](Self::report) for the first event of processing a material of `workspace`,
+    /// returning what cancels it.
+    async fn begin(&self, tx: &Sender<FormatedEvent>, workspace: &str, event: VideoUploadEvent<'_>) -> Cancel {
+        let event = FormatedEvent::from(event);
+        // before the material is `processing`, so it is never taken for interrupted
+        if let Err(e) = self.repo.claim(&event.id, self.instance.id()).await {
+            warn!("recording the process of {} failed: {e}", event.id);
+        }
+        let cancel = self.progress.begin(&event, workspace);
+        let _ = tx.send(event).await;
+        cancel
+    }
+
+    /// Cancels the processing of a material, if any, and waits until it stopped.
+    async fn stop(&self, id: &Id) -> bool {
+        let Some(mut rx) = self.progress.cancel(id) else {
+            return false;
+        };
+        while !matches!(rx.recv().await, Err(RecvError::Closed)) {}
+        true
+    }
+
+    /// Whether `id` is a material of `workspace`, or a clip for it still being cut, which has
+    /// a directory there but no row yet.
+    async fn owns(&self, workspace: &str, id: &Id) -> Result<bool> {
+        if self.repo.exists(workspace, id).await? {
+            return Ok(true);
+        }
+        Ok(self.progress.is_in_flight(id) && self.storage.exists(workspace, id).await?)
+    }
+
+    /// Stops the transcode of a video of the active workspace, which is left `cancelled`.
+    pub(crate) async fn cancel(&self, id: Id, claims: Claims, client: &ClientInfo) -> Result<()> {
+        self.workspaces.require(&claims, WorkspaceRole::Editor).await?;
+        if !self.owns(&claims.workspace, &id).await? {
+            return Err(AppError::MaterialNotFound(id.to_string()));
+        }
+        if !self.stop(&id).await {
+            return Err(AppError::MaterialNotProcessing(id.to_string()));
+        }
+
+        let entry = AuditEntry::by(&claims, AuditAction::MaterialCancel, id.to_string())
+            .details(json!({ "workspace": claims.workspace }));
+        self.audit.record(entry, client).await;
+        Ok(())
+    }
+
+    /// Follows the processing of a material of the active workspace through `tx`: the latest
+    /// event first, then the following ones. A material not in flight gets its final state.
+    pub(crate) async fn attach_progress(&self, id: Id, claims: Claims, tx: Sender<FormatedEvent>) -> Result<()> {
+        self.workspaces.require(&claims, WorkspaceRole::Viewer).await?;
+        // in flight first, like a clip still being cut, which has no row yet
+        let (latest, rx) = match self.progress.attach(&id, &claims.workspace) {
+            Some((latest, rx)) => (latest, Some(rx)),
+            None => {
+                let material = self.repo.get(&claims.workspace, &id).await?;
+                if material.state as u16 != STATE_PROCESSING {
+                    (final_event(&id, material.state), None)
+                } else {
+                    match self.repo.owner(&id).await? {
+                        Some(owner) if self.instance.is_alive(&owner) => {
+                            spawn(follow_elsewhere(self.repo, self.instance, id, owner, tx));
+                            return Ok(());
                         }
-                        SliceEvent::Err(error) => {
-                            return Err(error.into());
+                        // its process stopped and left it to the next startup
+                        _ => {
+                            self.repo.fail_processing(&id).await?;
+                            (VideoUploadEvent::failed(&id).into(), None)
                         }
                     }
                 }
+            }
+        };
+
+        spawn(async move {
+            if tx.send(latest).await.is_err() {
+                return;
+            }
+            let Some(mut rx) = rx else { return };
+            loop {
+                let event = match rx.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return,
+                };
+                if tx.send(event).await.is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Transcodes the stored video of `material` once a worker is free, leaving it `ok`, `failed`
+    /// or `cancelled`. Callers [`begin`](Self::begin) before the material is `processing`.
+    async fn process(
+        &self,
+        material: &Material,
+        raw: &Path,
+        priority: Priority,
+        tx: &Sender<FormatedEvent>,
+        cancel: Cancel,
+    ) -> Result<()> {
+        let id = Id(material.id.clone());
+        let result = match self.wait_turn(material, &id, priority, tx, &cancel).await {
+            Ok(_slot) => match self.transcode(material, &id, raw, tx, &cancel).await {
+                Ok(duration) => self.repo.update_processed(&id, duration).await,
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => {
+                self.publish(EventKind::Ready, material.event()).await;
+                self.report(tx, VideoUploadEvent::ok(&id)).await;
+                Ok(())
+            }
+            Err(_) if cancel.is_cancelled() => {
+                info!("transcode of {id} cancelled");
+                // the final event comes last: whoever cancelled waits for it before e.g. deleting
+                if let Err(e) = self.storage.clean_outputs(&material.workspace_id, &id).await {
+                    warn!("failed to clean outputs of {id}: {e}");
+                }
+                let updated = self.repo.update_state(&id, STATE_CANCELLED).await;
+                self.publish(EventKind::Cancelled, material.event().with_error("cancelled")).await;
+                self.report(tx, VideoUploadEvent::cancelled(&id)).await;
+                updated?;
+                Err(AppError::TranscodeCancelled)
+            }
+            Err(e) => {
+                // closes attached progress streams, the uploader learns from the closed stream
+                self.progress.report(&VideoUploadEvent::failed(&id).into());
+                self.repo.update_state(&id, STATE_FAILED).await?;
+                self.publish(EventKind::Failed, material.event().with_error(&e)).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Waits for a worker of the [`Scheduler`], reporting the position in the queue meanwhile.
+    async fn wait_turn(
+        &self,
+        material: &Material,
+        id: &Id,
+        priority: Priority,
+        tx: &Sender<FormatedEvent>,
+        cancel: &Cancel,
+    ) -> Result<Slot> {
+        let mut queued = self.scheduler.enqueue(&material.creator, priority);
+        let mut waited = false;
+        loop {
+            let turn = tokio::select! {
+                turn = queued.turn() => turn,
+                _ = cancel.cancelled() => return Err(AppError::TranscodeCancelled),
+            };
+            match turn {
+                Turn::Waiting(position) => {
+                    waited = true;
+                    self.report(tx, VideoUploadEvent::queued(id, position)).await;
+                }
+                Turn::Started(slot) => {
+                    if waited {
+                        self.report(tx, VideoUploadEvent::wip(id, 15)).await;
+                    }
+                    return Ok(slot);
+                }
+            }
+        }
+    }
+
+    /// Generates the thumbnail and the hls slices next to `raw`, returning the duration.
+    async fn transcode(
+        &self,
+        material: &Material,
+        id: &Id,
+        raw: &Path,
+        tx: &Sender<FormatedEvent>,
+        cancel: &Cancel,
+    ) -> Result<f64> {
+        let thumbnail_assert = self
+            .storage
+            .assert_file(&material.workspace_id, id, "thumbnail.jpeg")
+            .await?;
+
+        let raw_thumbnail = raw.to_path_buf();
+        let ffmpeg = self.ffmpeg;
+        let duration = spawn_blocking(move || -> Result<f64> {
+            let duration = ffmpeg.duration(&raw_thumbnail)?;
+            ffmpeg.thumbnail(&raw_thumbnail, &thumbnail_assert)?;
+            Ok(duration)
+        })
+            .await??;
+        if cancel.is_cancelled() {
+            return Err(AppError::TranscodeCancelled);
+        }
+
+        info!("save thumbnail of {id}");
+        self.report(tx, VideoUploadEvent::wip(id, 25)).await;
+        self.events.publish(EventKind::Progress, material.event(), Some(25));
+
+        let mut rx = ffmpeg
+            .slice2(raw, raw.parent().expect("not here"), self.metrics, cancel.clone())
+            .await?;
+
+        let mut progress = 26;
+        let mut sliced = false;
+
+        while let Some(event) = rx.recv().await {
+            match event {
+                SliceEvent::Ok => {
+                    sliced = true;
+                    info!("save slice of {id}");
+                    self.report(tx, VideoUploadEvent::wip(id, 75)).await;
+                    self.events.publish(EventKind::Progress, material.event(), Some(75));
+                }
+                SliceEvent::Wip(e) => {
+                    debug!("ffmpeg {e:?}");
+                    self.report(tx, VideoUploadEvent::wip(id, progress)).await;
+                    // streams only hear about changes, the uploader about every ffmpeg line
+                    if progress < 75 {
+                        self.events.publish(EventKind::Progress, material.event(), Some(progress));
+                        progress += 1;
+                    }
+                }
+                SliceEvent::Err(error) => {
+                    return Err(error.into());
+                }
+            }
+        }
+        if !sliced {
+            return Err(anyhow::anyhow!("slicing of {id} stopped without result").into());
+        }
+
+        Ok(duration)
+    }
+
+    /// Transcodes a stored video again, e.g. after a failed transcode.
+    pub(crate) async fn reprocess(&self, id: &Id, tx: &Sender<FormatedEvent>) -> Result<()> {
+        let material = self
+            .repo
+            .find(id)
+            .await?
+            .ok_or_else(|| AppError::MaterialNotFound(id.to_string()))?;
+        if material.r#type as u16 != TYPE_VIDEO {
+            return Err(WrongMaterialType(material.r#type as u16));
+        }
 
-                let materials =
-                    Material::new_video(id.to_string(), file_name, upload.desc, claims.id);
-                let tags = upload.tags.as_ref().map(|tags| tags.as_slice());
-                self.repo.save(&materials, tags).await?;
+        if material.state as u16 == STATE_PROCESSING {
+            let owner = self.repo.owner(id).await?;
+            if self.progress.is_in_flight(id) || owner.is_some_and(|owner| self.instance.is_alive(&owner)) {
+                return Err(AppError::MaterialProcessing(id.to_string()));
+            }
+        }
+
+        let raw = self.storage.raw_file(&material.workspace_id, id).await?;
+        let cancel = self.begin(tx, &material.workspace_id, VideoUploadEvent::wip(id, 15)).await;
+        if let Err(e) = self.repo.update_state(id, STATE_PROCESSING).await {
+            self.progress.report(&VideoUploadEvent::failed(id).into());
+            return Err(e);
+        }
+        self.process(&material, &raw, Priority::Bulk, tx, cancel).await
+    }
 
-                tx.send(VideoUploadEvent::ok(&id).into()).await?;
+    /// The video of the active workspace to cut a clip from, see [`clip`](Self::clip).
+    pub(crate) async fn clip_source(&self, id: &Id, request: &ClipRequest, claims: &Claims) -> Result<Material> {
+        self.workspaces.require(claims, WorkspaceRole::Editor).await?;
+        let source = self.repo.get(&claims.workspace, id).await?;
+        if source.r#type as u16 != TYPE_VIDEO {
+            return Err(WrongMaterialType(source.r#type as u16));
+        }
+        if source.state as u16 != STATE_OK {
+            return Err(AppError::InvalidClip(format!("source `{id}` is not ready")));
+        }
+        check_clip(request, source.duration)?;
+        if let Some(crop) = request.crop {
+            let raw = self.storage.raw_file(&source.workspace_id, id).await?;
+            let ffmpeg = self.ffmpeg;
+            let (width, height) = spawn_blocking(move || ffmpeg.dimensions(raw)).await??;
+            crop.check_within(width, height).map_err(AppError::CropOutOfBounds)?;
+        }
+        Ok(source)
+    }
+
+    /// Cuts a clip of `source` into a new video of the active workspace, transcoded like an
+    /// upload and reporting progress through `tx`, holding `_permit` until done.
+    pub(crate) async fn clip(
+        &self,
+        source: Material,
+        request: ClipRequest,
+        _permit: OwnedSemaphorePermit,
+        tx: Sender<FormatedEvent>,
+        claims: Claims,
+    ) {
+        let id = Id::new_uuid();
+        match self.cut(&id, &source, request, &tx, &claims).await {
+            // reported already
+            Ok(()) | Err(AppError::TranscodeCancelled) => {}
+            Err(e) => {
+                error!("clip {id} of {} failed: {e}", source.id);
+                let _ = tx.send(VideoUploadEvent::failed(&id).into()).await;
             }
         }
+    }
 
+    /// Cuts the clip `id`, in flight from the start so it can be cancelled or deleted meanwhile.
+    /// A clip failing before it became a material leaves nothing behind, one failing after was
+    /// failed or cancelled by its processing, like an upload.
+    async fn cut(
+        &self,
+        id: &Id,
+        source: &Material,
+        request: ClipRequest,
+        tx: &Sender<FormatedEvent>,
+        claims: &Claims,
+    ) -> Result<()> {
+        let workspace = claims.workspace.as_str();
+        let cancel = self.begin(tx, workspace, VideoUploadEvent::wip(id, 0)).await;
+        let result = self.cut_clip(id, source, request, tx, claims, &cancel).await;
+
+        if result.is_err() && !matches!(self.repo.exists(workspace, id).await, Ok(true)) {
+            if let Err(e) = self.storage.delete(workspace, id).await {
+                warn!("failed to remove clip {id}: {e}");
+            }
+            // no material, only its source
+            if let Err(e) = self.repo.delete(workspace, id).await {
+                warn!("failed to remove the source of clip {id}: {e}");
+            }
+            match result {
+                Err(AppError::TranscodeCancelled) => self.report(tx, VideoUploadEvent::cancelled(id)).await,
+                // the uploader hears of it from `clip`
+                _ => self.progress.report(&VideoUploadEvent::failed(id).into()),
+            }
+        }
+        result
+    }
+
+    async fn cut_clip(
+        &self,
+        id: &Id,
+        source: &Material,
+        request: ClipRequest,
+        tx: &Sender<FormatedEvent>,
+        claims: &Claims,
+        cancel: &Cancel,
+    ) -> Result<()> {
+        let workspace = claims.workspace.as_str();
+        let input = self.storage.raw_file(&source.workspace_id, &Id(source.id.clone())).await?;
+        let output = self.storage.new_raw(workspace, id)?;
+
+        let mut queued = self.scheduler.enqueue(&claims.id, Priority::Interactive);
+        let slot = loop {
+            let turn = tokio::select! {
+                turn = queued.turn() => turn,
+                _ = cancel.cancelled() => return Err(AppError::TranscodeCancelled),
+            };
+            match turn {
+                Turn::Waiting(position) => self.report(tx, VideoUploadEvent::queued(id, position)).await,
+                Turn::Started(slot) => break slot,
+            }
+        };
+        self.report(tx, VideoUploadEvent::wip(id, 5)).await;
+
+        let ffmpeg = self.ffmpeg;
+        let (start, end, crop) = (request.start, request.end, request.crop);
+        let target = output.clone();
+        let stop = cancel.clone();
+        let cut = spawn_blocking(move || ffmpeg.clip(&input, &target, start, end, crop, stop)).await;
+        drop(slot);
+        cut??;
+
+        let checksum = checksum(&output).await?;
+        self.repo.save_source(id, &source.id, &request).await?;
+
+        let source_name = source.name.as_deref().unwrap_or("clip");
+        let info = MaterialInfo {
+            name: request
+                .name
+                .unwrap_or_else(|| format!("{source_name} [{start}s-{end}s]")),
+            description: request.desc,
+            tags: request.tags.unwrap_or_default(),
+        };
+        let saved = SavedId::New { checksum };
+        // in flight already, so the processing keeps the cancel of the cut
+        self.create_video(id.clone(), saved, info, Priority::Interactive, tx, claims).await?;
         Ok(())
     }
 
@@ -285,75 +1005,249 @@ impl MaterialsService {
         base_url: BaseUrl,
         claims: Claims,
     ) -> Result<Vec<MaterialImage>> {
+        self.workspaces.require(&claims, WorkspaceRole::Editor).await?;
+
         let mut details = Vec::with_capacity(upload.files.capacity());
+        let tags = upload.tags.map(|tags| tags.to_vec()).unwrap_or_default();
 
         for file in upload.files {
-            let file_name = file.file_name().unwrap_or("no_name").to_string();
-            let raw_file = file.into_file();
-            let id = Id::new_uuid();
-
-            let detail = match self.storage.save(&id, raw_file).await? {
-                SavedId::Existed => {
-                    warn!("file {file_name} is existed! return id {id}!");
-                    let material = self.repo.get(&id).await?;
-                    self.transfer_image(&base_url, material)
+            let info = MaterialInfo {
+                name: file.file_name().unwrap_or("no_name").to_string(),
+                description: upload.desc.clone(),
+                tags: tags.clone(),
+            };
+            let size = file.size();
+
+            let material = self.save_image(file.into_file(), info, &claims).await?;
+            self.metrics.uploaded(MaterialType::Image, size);
+            details.push(self.transfer_image(&base_url, material)?);
+        }
+
+        Ok(details)
+    }
+
+    /// Stores `source` as an image of the active workspace. Callers check the workspace role.
+    pub(crate) async fn save_image(
+        &self,
+        source: impl AsyncRead + Unpin,
+        info: MaterialInfo,
+        claims: &Claims,
+    ) -> Result<Material> {
+        let id = Id::new_uuid();
+        let saved = self.storage.save(&claims.workspace, &id, source).await?;
+        self.create_image(id, saved, info, claims).await
+    }
+
+    async fn create_image(
+        &self,
+        id: Id,
+        saved: SavedId,
+        info: MaterialInfo,
+        claims: &Claims,
+    ) -> Result<Material> {
+        let workspace = claims.workspace.as_str();
+        let file_name = info.name.as_str();
+
+        match saved {
+            SavedId::Existed => {
+                warn!("file {file_name} is existed! return id {id}!");
+                self.repo.get(workspace, &id).await
+            }
+            SavedId::New { checksum } => {
+                info!("new image file {file_name} with id {id}");
+                let raw = self.storage.raw_file(workspace, &id).await?;
+                let size = tokio::fs::metadata(&raw).await?.len();
+                let material = Material::new_image(
+                    id.to_string(),
+                    info.name,
+                    info.description,
+                    claims.id.clone(),
+                    claims.workspace.clone(),
+                )
+                    .with_media(Some(size), None)
+                    .with_checksum(checksum);
+                self.repo.save(&material, Some(&info.tags)).await?;
+                // nothing to process, ready right away
+                self.publish(EventKind::Created, material.event()).await;
+                self.publish(EventKind::Ready, material.event()).await;
+                Ok(material)
+            }
+        }
+    }
+
+    /// Creates a material with `id` from a file already on the server, unless the active workspace
+    /// holds one with the same content. Callers check the workspace role.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn import_file(
+        &self,
+        id: Id,
+        path: &Path,
+        kind: MaterialType,
+        info: MaterialInfo,
+        link: bool,
+        priority: Priority,
+        tx: &Sender<FormatedEvent>,
+        claims: &Claims,
+    ) -> Result<Imported> {
+        let workspace = claims.workspace.as_str();
+        let checksum = checksum(path).await?;
+        if let Some(id) = self.repo.find_by_checksum(workspace, &checksum).await? {
+            return Ok(Imported::Duplicate(Id(id)));
+        }
+
+        let saved = if link {
+            self.storage.link(workspace, &id, path, checksum).await?
+        } else {
+            self.storage.save(workspace, &id, File::open(path).await?).await?
+        };
+
+        let id = match kind {
+            MaterialType::Video => self.create_video(id, saved, info, priority, tx, claims).await?,
+            MaterialType::Image => Id(self.create_image(id, saved, info, claims).await?.id),
+        };
+        Ok(Imported::New(id))
+    }
+
+    /// Removes stored files of materials that no longer exist. Directories modified within
+    /// `min_age` are kept, they may belong to uploads still being transcoded.
+    pub(crate) async fn storage_gc(&self, min_age: std::time::Duration, dry_run: bool) -> Result<Vec<PathBuf>> {
+        let known: HashSet<String> = self.repo.ids().await?.into_iter().collect();
+
+        let mut removed = Vec::new();
+        for dir in self.storage.material_dirs().await? {
+            if known.contains(&*dir.id) || dir.age < min_age {
+                continue;
+            }
+            if !dry_run {
+                tokio::fs::remove_dir_all(&dir.path).await?;
+            }
+            removed.push(dir.path);
+        }
+        Ok(removed)
+    }
+
+    /// Fills in size and duration of materials stored before they were recorded, from their raw
+    /// files. Returns the ids filled in, materials whose file can't be read are logged and skipped.
+    pub(crate) async fn backfill_media(&self) -> Result<Vec<String>> {
+        let mut filled = Vec::new();
+        for material in self.repo.missing_media().await? {
+            let id = Id(material.id.clone());
+            let raw = match self.storage.raw_file(&material.workspace_id, &id).await {
+                Ok(raw) => raw,
+                Err(e) => {
+                    warn!("skip backfill of {id}: {e}");
+                    continue;
                 }
-                SavedId::New => {
-                    info!("new image file {file_name} with id {id}");
-                    let material = Material::new_image(id.to_string(), file_name, upload.desc.clone(), claims.id.clone());
-                    let tags = upload.tags.as_ref().map(|tags| tags.as_slice());
-                    self.repo.save(&material, tags).await?;
-                    self.transfer_image(&base_url, material)
+            };
+            let size = match material.size {
+                Some(size) => size,
+                None => match tokio::fs::metadata(&raw).await {
+                    Ok(metadata) => metadata.len() as i64,
+                    Err(e) => {
+                        warn!("skip backfill of {id}: {e}");
+                        continue;
+                    }
+                },
+            };
+            let duration = match material.duration {
+                None if material.r#type as u16 == TYPE_VIDEO => {
+                    let ffmpeg = self.ffmpeg;
+                    match spawn_blocking(move || ffmpeg.duration(raw)).await? {
+                        Ok(duration) => Some(duration),
+                        Err(e) => {
+                            warn!("no duration of {id}: {e}");
+                            None
+                        }
+                    }
                 }
-            }?;
-            details.push(detail);
+                duration => duration,
+            };
+            self.repo.update_media(&id, size, duration).await?;
+            filled.push(material.id);
         }
+        Ok(filled)
+    }
+
+    pub(crate) async fn detail(
+        &self,
+        id: Id,
+        base_url: BaseUrl,
+        claims: Claims,
+    ) -> Result<MaterialDetail> {
+        self.workspaces.require(&claims, WorkspaceRole::Viewer).await?;
+        if !self.storage.exists(&claims.workspace, &id).await? {
+            return Err(AppError::MaterialNotFound(id.to_string()));
+        }
+
+        let material = self.repo.get(&claims.workspace, &id).await?;
+
+        let mut detail = self.transfer(&base_url, material)?;
+        if let MaterialDetail::Video(detail) = &mut detail {
+            detail.video.source = self.repo.source(&id).await?;
+        }
+        Ok(detail)
+    }
+
+    pub(crate) async fn exists(&self, id: &Id, claims: &Claims) -> Result<bool> {
+        self.workspaces.require(claims, WorkspaceRole::Viewer).await?;
+        self.repo.exists(&claims.workspace, id).await
+    }
 
-        Ok(details)
+    /// Path of a file of a material, e.g. `thumbnail.jpeg` or `720p/slice.m3u8`, for the members
+    /// of `workspace`, whichever workspace is active in their token.
+    pub(crate) async fn file(&self, workspace: &str, id: &Id, name: &str, claims: &Claims) -> Result<PathBuf> {
+        self.workspaces.require_role(workspace, &claims.id, WorkspaceRole::Viewer).await?;
+        self.existing_file(workspace, id, name).await
     }
 
-    pub(crate) async fn detail(
-        &self,
-        id: Id,
-        base_url: BaseUrl,
-        _claims: Claims,
-    ) -> Result<MaterialDetail> {
-        if !self.storage.exists(&id).await? {
-            return Err(AppError::MaterialNotFound(id.to_string()));
+    /// Like [`Self::file`], for a url signed by the storage instead of a member.
+    pub(crate) async fn signed_file(&self, workspace: &str, id: &Id, name: &str, token: &str) -> Result<PathBuf> {
+        if !self.storage.verify_token(token, workspace, id) {
+            return Err(AppError::InvalidFileToken);
         }
+        self.existing_file(workspace, id, name).await
+    }
 
-        let material = self.repo.get(&id).await?;
-
-        self.transfer(&base_url, material)
+    async fn existing_file(&self, workspace: &str, id: &Id, name: &str) -> Result<PathBuf> {
+        if !self.repo.exists(workspace, id).await? {
+            return Err(AppError::MaterialNotFound(id.to_string()));
+        }
+        self.storage.assert_file(workspace, id, name).await
     }
 
     fn transfer_video(&self, base_url: &BaseUrl, material: Material) -> Result<MaterialVideoDetail> {
         let id = Id(material.id);
+        let workspace = material.workspace_id.as_str();
         let slices = VideoSlices {
-            slice: self.storage.url(base_url, &id, "slice.m3u8")?.to_string(),
+            slice: self
+                .storage
+                .url(base_url, workspace, &id, "slice.m3u8")?
+                .to_string(),
             slice720p: self
                 .storage
-                .url(base_url, &id, "720p/slice.m3u8")?
+                .url(base_url, workspace, &id, "720p/slice.m3u8")?
                 .to_string(),
             slice1080p: self
                 .storage
-                .url(base_url, &id, "1080p/slice.m3u8")?
+                .url(base_url, workspace, &id, "1080p/slice.m3u8")?
                 .to_string(),
         };
 
-        let raw = self.storage.url(base_url, &id, "raw")?.to_string();
+        let raw = self.storage.url(base_url, workspace, &id, "raw")?.to_string();
 
         let thumbnail = self
             .storage
-            .url(base_url, &id, "thumbnail.jpeg")?
+            .url(base_url, workspace, &id, "thumbnail.jpeg")?
             .to_string();
 
         let video = MaterialVideo {
             id,
             name: material.name.unwrap_or("".to_string()),
+            state: MaterialState::from_value(material.state as u16).unwrap_or(MaterialState::Ok),
             raw,
             thumbnail,
             description: material.description.unwrap_or("".to_string()),
+            source: None,
         };
 
         let detail = MaterialVideoDetail { slices, video };
@@ -362,7 +1256,10 @@ impl MaterialsService {
 
     fn transfer_image(&self, base_url: &BaseUrl, material: Material) -> Result<MaterialImage> {
         let id = Id(material.id);
-        let raw = self.storage.url(base_url, &id, "raw")?.to_string();
+        let raw = self
+            .storage
+            .url(base_url, &material.workspace_id, &id, "raw")?
+            .to_string();
 
         let detail = MaterialImage {
             id,
@@ -391,32 +1288,248 @@ impl MaterialsService {
         Ok(detail)
     }
 
-    pub(crate) async fn update(&self, id: Id, _claims: Claims, request: MaterialPatchRequest) -> Result<()> {
-        self.repo.update_name(&id, &request.name).await?;
+    pub(crate) async fn update(
+        &self,
+        id: Id,
+        claims: Claims,
+        request: MaterialPatchRequest,
+        client: &ClientInfo,
+    ) -> Result<()> {
+        self.workspaces.require(&claims, WorkspaceRole::Editor).await?;
+        self.repo.update_name(&claims.workspace, &id, &request.name).await?;
+        let material = self.repo.get(&claims.workspace, &id).await?;
+        self.publish(EventKind::Updated, material.event()).await;
+
+        let entry = AuditEntry::by(&claims, AuditAction::MaterialUpdate, id.to_string())
+            .details(json!({ "workspace": claims.workspace, "name": request.name }));
+        self.audit.record(entry, client).await;
         Ok(())
     }
 
-    pub(crate) async fn delete(&self, id: Id, _claims: Claims) -> Result<()> {
-        if !self.storage.exists(&id).await? {
+    pub(crate) async fn delete(&self, id: Id, claims: Claims, client: &ClientInfo) -> Result<()> {
+        self.workspaces.require(&claims, WorkspaceRole::Editor).await?;
+        let workspace = claims.workspace.as_str();
+        if !self.owns(workspace, &id).await? || !self.storage.exists(workspace, &id).await? {
             return Err(AppError::MaterialNotFound(id.to_string()));
         }
-        self.storage.delete(&id).await?;
+        self.stop(&id).await;
+        self.storage.delete(workspace, &id).await?;
+
+        self.repo.delete(workspace, &id).await?;
+        self.publish(EventKind::Deleted, MaterialEvent::deleted(id.to_string(), workspace)).await;
 
-        self.repo.delete(&id).await?;
+        let entry = AuditEntry::by(&claims, AuditAction::MaterialDelete, id.to_string())
+            .details(json!({ "workspace": workspace }));
+        self.audit.record(entry, client).await;
         Ok(())
     }
 
-    pub(crate) async fn batch_delete(&self, ids: Vec<Id>, _claims: Claims) -> Result<()> {
+    pub(crate) async fn batch_delete(
+        &self,
+        ids: Vec<Id>,
+        claims: Claims,
+        client: &ClientInfo,
+    ) -> Result<()> {
+        self.workspaces.require(&claims, WorkspaceRole::Editor).await?;
+        let workspace = claims.workspace.as_str();
         for id in ids.iter() {
-            if self.storage.exists(id).await? {
-                self.storage.delete(id).await?;
+            if self.owns(workspace, id).await? && self.storage.exists(workspace, id).await? {
+                self.stop(id).await;
+                self.storage.delete(workspace, id).await?;
+            }
+        }
+        let deleted = self.repo.delete_by_ids(workspace, &ids).await?;
+        for id in deleted {
+            self.publish(EventKind::Deleted, MaterialEvent::deleted(id, workspace)).await;
+        }
+
+        let ids: Vec<String> = ids.iter().map(ToString::to_string).collect();
+        let entry = AuditEntry::by(&claims, AuditAction::MaterialBatchDelete, workspace)
+            .details(json!({ "ids": ids }));
+        self.audit.record(entry, client).await;
+        Ok(())
+    }
+}
+
+const FACET_LIMIT: u32 = 50;
+
+enum FilterValue {
+    Text(String),
+    Int(i64),
+    Time(NaiveDateTime),
+}
+
+/// WHERE clause of a material search, shared by the page, count and facet queries.
+struct SearchFilter {
+    sql: String,
+    values: Vec<FilterValue>,
+}
+
+impl SearchFilter {
+    fn new(condition: &SearchCondition, workspace: &str) -> Self {
+        let mut sql = " WHERE workspace_id = ?".to_string();
+        let mut values = vec![FilterValue::Text(workspace.to_string())];
+
+        if let Some(ref tags) = condition.tags {
+            if !tags.is_empty() {
+                sql.push_str(" AND id IN (SELECT material_id FROM material_tags WHERE tag IN (");
+                for tag in tags {
+                    values.push(FilterValue::Text(tag.clone()));
+                    sql.push_str("?,");
+                }
+                sql.pop();
+                sql.push_str("))");
+            }
+        }
+
+        if let Some(ref query) = condition.query {
+            sql.push_str(" AND (name LIKE ? or description LIKE ?)");
+            values.push(FilterValue::Text(format!("%{query}%")));
+            values.push(FilterValue::Text(format!("%{query}%")));
+        }
+
+        if let Some(ref material_type) = condition.r#type {
+            sql.push_str(" AND type = ?");
+            values.push(FilterValue::Int(material_type.value() as i64));
+        }
+
+        if let Some(created_from) = condition.created_from {
+            sql.push_str(" AND created_at >= ?");
+            values.push(FilterValue::Time(created_from));
+        }
+
+        if let Some(created_to) = condition.created_to {
+            sql.push_str(" AND created_at < ?");
+            values.push(FilterValue::Time(created_to));
+        }
+
+        Self { sql, values }
+    }
+
+    fn args(&self) -> Result<SqliteArguments<'static>> {
+        let mut args = SqliteArguments::default();
+        self.add_to(&mut args)?;
+        Ok(args)
+    }
+
+    fn add_to(&self, args: &mut SqliteArguments<'static>) -> Result<()> {
+        for value in &self.values {
+            match value {
+                FilterValue::Text(value) => args.add(value.clone())?,
+                FilterValue::Int(value) => args.add(*value)?,
+                FilterValue::Time(value) => args.add(*value)?,
             }
         }
-        self.repo.delete_by_ids(&ids).await?;
         Ok(())
     }
 }
 
+/// Query counting the matches of each condition in one scan of the workspace, a column per
+/// condition.
+fn counts_query(conditions: &[SearchCondition], workspace: &str) -> Result<(String, SqliteArguments<'static>)> {
+    let mut columns = Vec::with_capacity(conditions.len());
+    let mut args = SqliteArguments::default();
+    for condition in conditions {
+        let filter = SearchFilter::new(condition, workspace);
+        let sql_case = filter.sql.strip_prefix(" WHERE ").unwrap_or(&filter.sql);
+        columns.push(format!("COALESCE(SUM(CASE WHEN {sql_case} THEN 1 ELSE 0 END), 0)"));
+        filter.add_to(&mut args)?;
+    }
+    args.add(workspace.to_string())?;
+    let sql = format!("SELECT {} FROM materials WHERE workspace_id = ?", columns.join(", "));
+    Ok((sql, args))
+}
+
+/// How often a client following a material processed elsewhere learns its state.
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Final event of a material that is not `processing`.
+fn final_event(id: &Id, state: i64) -> FormatedEvent {
+    match state as u16 {
+        STATE_OK => VideoUploadEvent::ok(id).into(),
+        STATE_CANCELLED => VideoUploadEvent::cancelled(id).into(),
+        _ => VideoUploadEvent::failed(id).into(),
+    }
+}
+
+/// Follows a material processed by process `owner` through `tx`. Its progress is unknown here,
+/// only its final state, which comes from the row once `owner` is done, or is `failed` if it
+/// stops first.
+async fn follow_elsewhere(
+    repo: &'static MaterialsRepo,
+    instance: &'static Instance,
+    id: Id,
+    owner: String,
+    tx: Sender<FormatedEvent>,
+) {
+    if tx.send(VideoUploadEvent::wip(&id, 0).into()).await.is_err() {
+        return;
+    }
+    while !tx.is_closed() {
+        sleep(FOLLOW_INTERVAL).await;
+        let event = match repo.find(&id).await {
+            Ok(Some(material)) if material.state as u16 != STATE_PROCESSING => final_event(&id, material.state),
+            Ok(Some(_)) if instance.is_alive(&owner) => continue,
+            Ok(Some(_)) => match repo.fail_processing(&id).await {
+                Ok(()) => VideoUploadEvent::failed(&id).into(),
+                Err(e) => {
+                    warn!("failing interrupted {id} failed: {e}");
+                    return;
+                }
+            },
+            // deleted meanwhile
+            Ok(None) => VideoUploadEvent::cancelled(&id).into(),
+            Err(e) => {
+                warn!("following {id} failed: {e}");
+                return;
+            }
+        };
+        let _ = tx.send(event).await;
+        return;
+    }
+}
+
+/// Marks the videos left `processing` by stopped processes as failed, returning how many, see
+/// [`Instance`]. Videos of running processes, like the server next to a command, are left alone.
+pub(crate) async fn fail_interrupted(db: &SqlitePool, instance: &Instance) -> Result<u64> {
+    let processing: Vec<(String, Option<String>)> = sqlx::query_as(
+        r#"
+        SELECT m.id, p.instance
+        FROM materials m LEFT JOIN material_processing p ON p.material_id = m.id
+        WHERE m.state = ?
+        "#,
+    )
+        .bind(STATE_PROCESSING as i64)
+        .fetch_all(db)
+        .await?;
+
+    let mut failed = 0;
+    for (id, owner) in processing {
+        if owner.is_some_and(|owner| instance.is_alive(&owner)) {
+            continue;
+        }
+        failed += sqlx::query("UPDATE materials SET state = ? WHERE id = ? AND state = ?")
+            .bind(STATE_FAILED as i64)
+            .bind(&id)
+            .bind(STATE_PROCESSING as i64)
+            .execute(db)
+            .await?
+            .rows_affected();
+    }
+
+    let owners: Vec<String> = sqlx::query_scalar("SELECT DISTINCT instance FROM material_processing")
+        .fetch_all(db)
+        .await?;
+    for owner in owners.iter().filter(|owner| !instance.is_alive(owner)) {
+        sqlx::query("DELETE FROM material_processing WHERE instance = ?")
+            .bind(owner)
+            .execute(db)
+            .await?;
+    }
+    instance.forget_stopped();
+    Ok(failed)
+}
+
 #[derive(Bean)]
 pub struct MaterialsRepo {
     #[inject(bean = Db)]
@@ -430,9 +1543,16 @@ pub(crate) struct Material {
     raw_name: Option<String>,
     description: Option<String>,
     creator: String,
+    workspace_id: String,
     state: i64,
     r#type: i64,
+    size: Option<i64>,
+    duration: Option<f64>,
     created_at: NaiveDateTime,
+    #[sqlx(default)]
+    #[serde(skip)]
+    #[oai(skip)]
+    checksum: Option<String>,
 }
 
 impl Material {
@@ -441,6 +1561,7 @@ impl Material {
         name: String,
         description: Option<String>,
         creator: String,
+        workspace_id: String,
     ) -> Self {
         Self {
             id,
@@ -448,9 +1569,13 @@ impl Material {
             raw_name: Some(name),
             description,
             creator,
-            state: STATE_OK as i64,
+            workspace_id,
+            state: STATE_PROCESSING as i64,
             r#type: TYPE_VIDEO as i64,
+            size: None,
+            duration: None,
             created_at: Utc::now().naive_utc(),
+            checksum: None,
         }
     }
     pub(crate) fn new_image(
@@ -458,6 +1583,7 @@ impl Material {
         name: String,
         description: Option<String>,
         creator: String,
+        workspace_id: String,
     ) -> Self {
         Self {
             id,
@@ -465,9 +1591,47 @@ impl Material {
             raw_name: Some(name),
             description,
             creator,
+            workspace_id,
             state: STATE_OK as i64,
             r#type: TYPE_IMAGE as i64,
+            size: None,
+            duration: None,
             created_at: Utc::now().naive_utc(),
+            checksum: None,
+        }
+    }
+
+    pub(crate) fn with_checksum(mut self, checksum: String) -> Self {
+        self.checksum = Some(checksum);
+        self
+    }
+
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn event(&self) -> MaterialEvent {
+        MaterialEvent {
+            id: self.id.clone(),
+            workspace_id: self.workspace_id.clone(),
+            r#type: MaterialType::from_value(self.r#type as u16),
+            name: self.name.clone(),
+            error: None,
+        }
+    }
+
+    pub(crate) fn with_media(mut self, size: Option<u64>, duration: Option<f64>) -> Self {
+        self.size = size.map(|size| size as i64);
+        self.duration = duration;
+        self
+    }
+
+    fn sort_key(&self, field: SortField) -> CursorKey {
+        match field {
+            SortField::CreatedAt => CursorKey::CreatedAt(self.created_at),
+            SortField::Name => CursorKey::Name(self.name.clone()),
+            SortField::Duration => CursorKey::Duration(self.duration),
+            SortField::Size => CursorKey::Size(self.size),
         }
     }
 }
@@ -476,68 +1640,140 @@ impl MaterialsRepo {
     async fn search(
         &self,
         condition: &SearchCondition,
-        creator: impl AsRef<str>,
+        workspace: impl AsRef<str>,
     ) -> Result<PageResult<Material>> {
-        let mut sql_select_args = sqlx::sqlite::SqliteArguments::default();
-        let mut sql_count_args = sqlx::sqlite::SqliteArguments::default();
+        let filter = SearchFilter::new(condition, workspace.as_ref());
 
         let sql_select =
-            "SELECT id, name, raw_name, description, creator, state, type, created_at FROM materials";
+            "SELECT id, name, raw_name, description, creator, workspace_id, state, type, size, duration, created_at FROM materials";
 
-        let sql_count = "SELECT COUNT(*) FROM materials";
+        let total = self.count(condition, workspace.as_ref()).await?;
 
-        let mut sql_where = " WHERE creator = ?".to_string();
+        let mut sql_where = filter.sql.clone();
+        let mut sql_select_args = filter.args()?;
 
-        sql_select_args.add(creator.as_ref())?;
-        sql_count_args.add(creator.as_ref())?;
+        let cursor = condition.cursor.as_deref().map(Cursor::decode).transpose()?;
 
-        if let Some(ref tags) = condition.tags {
-            if !tags.is_empty() {
-                sql_where
-                    .push_str(" AND id IN (SELECT material_id FROM material_tags WHERE tag IN (");
-                for tag in tags {
-                    sql_select_args.add(tag)?;
-                    sql_count_args.add(tag)?;
-                    sql_where.push_str("?,");
-                }
-                sql_where.pop();
-                sql_where.push_str("))");
+        let (sort, direction) = match cursor {
+            Some(ref cursor) => (cursor.field(), cursor.direction()),
+            None => (
+                condition.sort.unwrap_or_default(),
+                condition.direction.unwrap_or_default(),
+            ),
+        };
+
+        let sql_limit = if let Some(ref cursor) = cursor {
+            cursor.push_where(&mut sql_where, &mut sql_select_args)?;
+            sql_select_args.add(condition.page.limit())?;
+            " LIMIT ?"
+        } else {
+            sql_select_args.add(condition.page.limit())?;
+            sql_select_args.add(condition.page.offset())?;
+            " LIMIT ? OFFSET ?"
+        };
+
+        // id breaks ties so that the order, and therefore the cursor, is stable
+        let sql_order = format!(
+            " ORDER BY {column} {direction}, id {direction}",
+            column = sort.column(),
+            direction = direction.value()
+        );
+
+        let records: Vec<Material> = query_as_with(
+            &format!("{sql_select}{sql_where}{sql_order}{sql_limit}"),
+            sql_select_args,
+        )
+            .fetch_all(self.db)
+            .await?;
+
+        let next_cursor = match records.last() {
+            Some(last) if records.len() as i64 == condition.page.limit() => {
+                Some(Cursor::new(last.sort_key(sort), last.id.clone(), direction).encode()?)
             }
-        }
+            _ => None,
+        };
 
-        if let Some(ref query) = condition.query {
-            sql_where.push_str(" AND (name LIKE ? or description LIKE ?)");
-            sql_select_args.add(format!("%{query}%"))?;
-            sql_select_args.add(format!("%{query}%"))?;
-            sql_count_args.add(format!("%{query}%"))?;
-            sql_count_args.add(format!("%{query}%"))?;
-        }
+        Ok(PageResult::new(&condition.page, total, records).with_next_cursor(next_cursor))
+    }
 
-        if let Some(ref material_type) = condition.r#type {
-            sql_where.push_str(" AND type = ?");
-            sql_select_args.add(material_type.value())?;
-            sql_count_args.add(material_type.value())?;
-        }
+    async fn count(&self, condition: &SearchCondition, workspace: &str) -> Result<u64> {
+        let filter = SearchFilter::new(condition, workspace);
 
-        let total: u64 = query_scalar_with(&format!("{sql_count}{sql_where}"), sql_count_args)
+        let total: u64 = query_scalar_with(
+            &format!("SELECT COUNT(*) FROM materials{}", filter.sql),
+            filter.args()?,
+        )
             .fetch_one(self.db)
             .await?;
 
-        let sql_order = " ORDER BY created_at DESC";
+        Ok(total)
+    }
 
-        let sql_limit = " LIMIT ? OFFSET ?";
+    async fn counts(&self, conditions: &[SearchCondition], workspace: &str) -> Result<Vec<u64>> {
+        if conditions.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (sql, args) = counts_query(conditions, workspace)?;
+        let row = query_with(&sql, args).fetch_one(self.db).await?;
+        (0..conditions.len())
+            .map(|index| Ok(row.try_get::<i64, _>(index)? as u64))
+            .collect()
+    }
 
-        sql_select_args.add(condition.page.limit())?;
-        sql_select_args.add(condition.page.offset())?;
+    /// Counts per tag, type, creator and upload month over the same matches as [`Self::search`].
+    async fn facets(&self, condition: &SearchCondition, workspace: &str) -> Result<SearchFacets> {
+        let filter = SearchFilter::new(condition, workspace);
+        let sql_where = filter.sql.as_str();
+
+        let tags: Vec<(String, i64)> = query_as_with(
+            &format!(
+                "SELECT tag, COUNT(*) FROM material_tags WHERE material_id IN (SELECT id FROM materials{sql_where}) \
+                 GROUP BY tag ORDER BY COUNT(*) DESC, tag LIMIT {FACET_LIMIT}"
+            ),
+            filter.args()?,
+        )
+            .fetch_all(self.db)
+            .await?;
 
-        let records: Vec<Material> = query_as_with(
-            &format!("{sql_select}{sql_where}{sql_order}{sql_limit}"),
-            sql_select_args,
+        let types: Vec<(i64, i64)> = query_as_with(
+            &format!("SELECT type, COUNT(*) FROM materials{sql_where} GROUP BY type ORDER BY type"),
+            filter.args()?,
+        )
+            .fetch_all(self.db)
+            .await?;
+
+        let creators: Vec<(String, i64)> = query_as_with(
+            &format!(
+                "SELECT creator, COUNT(*) FROM materials{sql_where} \
+                 GROUP BY creator ORDER BY COUNT(*) DESC, creator LIMIT {FACET_LIMIT}"
+            ),
+            filter.args()?,
+        )
+            .fetch_all(self.db)
+            .await?;
+
+        let months: Vec<(String, i64)> = query_as_with(
+            &format!(
+                "SELECT strftime('%Y-%m', created_at) AS month, COUNT(*) FROM materials{sql_where} \
+                 GROUP BY month ORDER BY month DESC"
+            ),
+            filter.args()?,
         )
             .fetch_all(self.db)
             .await?;
 
-        Ok(PageResult::new(&condition.page, total, records))
+        Ok(SearchFacets {
+            tags: tags.into_iter().map(FacetCount::from).collect(),
+            types: types
+                .into_iter()
+                .filter_map(|(value, count)| {
+                    MaterialType::from_value(value as u16)
+                        .map(|material_type| FacetCount::from((material_type.name().to_string(), count)))
+                })
+                .collect(),
+            creators: creators.into_iter().map(FacetCount::from).collect(),
+            months: months.into_iter().map(FacetCount::from).collect(),
+        })
     }
 
     async fn save(&self, materials: &Material, tags: Option<&[String]>) -> Result<()> {
@@ -545,17 +1781,21 @@ impl MaterialsRepo {
 
         let result = sqlx::query!(
             r#"
-            INSERT INTO materials (id, name, raw_name, description, creator, state, type, created_at)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            INSERT INTO materials (id, name, raw_name, description, creator, workspace_id, state, type, size, duration, created_at, checksum)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             "#,
             materials.id,
             materials.name,
             materials.raw_name,
             materials.description,
             materials.creator,
+            materials.workspace_id,
             materials.state,
             materials.r#type,
-            materials.created_at
+            materials.size,
+            materials.duration,
+            materials.created_at,
+            materials.checksum
         )
             .execute(&mut *tx)
             .await?;
@@ -585,45 +1825,229 @@ impl MaterialsRepo {
         Ok(())
     }
 
-    async fn get(&self, id: &Id) -> Result<Material> {
+    async fn get(&self, workspace: &str, id: &Id) -> Result<Material> {
         let materials = sqlx::query_as(
             r#"
-            SELECT id, name, raw_name, description, creator, state, type, created_at
+            SELECT id, name, raw_name, description, creator, workspace_id, state, type, size, duration, created_at
+            FROM materials
+            WHERE id = ? AND workspace_id = ?
+            "#,
+        )
+            .bind(id.as_ref())
+            .bind(workspace)
+            .fetch_optional(self.db)
+            .await?;
+
+        materials.ok_or_else(|| AppError::MaterialNotFound(id.to_string()))
+    }
+
+    /// The material with `id` in whatever workspace it belongs to.
+    async fn find(&self, id: &Id) -> Result<Option<Material>> {
+        let material = sqlx::query_as(
+            r#"
+            SELECT id, name, raw_name, description, creator, workspace_id, state, type, size, duration, created_at
             FROM materials
             WHERE id = ?
             "#,
         )
             .bind(id.as_ref())
-            .fetch_one(self.db)
+            .fetch_optional(self.db)
+            .await?;
+        Ok(material)
+    }
+
+    /// Records what the clip `id` was cut from.
+    async fn save_source(&self, id: &Id, source_id: &str, request: &ClipRequest) -> Result<()> {
+        let id_str = id.deref();
+        let crop = request.crop.as_ref();
+        let crop_x = crop.map(|crop| crop.x);
+        let crop_y = crop.map(|crop| crop.y);
+        let crop_width = crop.map(|crop| crop.width);
+        let crop_height = crop.map(|crop| crop.height);
+        let created_at = Utc::now().naive_utc();
+        sqlx::query!(
+            r#"
+            INSERT INTO material_sources (material_id, source_id, start_seconds, end_seconds, crop_x, crop_y, crop_width, crop_height, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+            id_str,
+            source_id,
+            request.start,
+            request.end,
+            crop_x,
+            crop_y,
+            crop_width,
+            crop_height,
+            created_at
+        )
+            .execute(self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn source(&self, id: &Id) -> Result<Option<ClipSource>> {
+        let row: Option<ClipSourceRow> = sqlx::query_as(
+            r#"
+            SELECT source_id, start_seconds, end_seconds, crop_x, crop_y, crop_width, crop_height
+            FROM material_sources
+            WHERE material_id = ?
+            "#,
+        )
+            .bind(id.as_ref())
+            .fetch_optional(self.db)
+            .await?;
+        Ok(row.map(ClipSource::from))
+    }
+
+    async fn find_by_checksum(&self, workspace: &str, checksum: &str) -> Result<Option<String>> {
+        let id = sqlx::query_scalar("SELECT id FROM materials WHERE workspace_id = ? AND checksum = ? LIMIT 1")
+            .bind(workspace)
+            .bind(checksum)
+            .fetch_optional(self.db)
+            .await?;
+        Ok(id)
+    }
+
+    async fn ids(&self) -> Result<Vec<String>> {
+        let ids = sqlx::query_scalar("SELECT id FROM materials")
+            .fetch_all(self.db)
             .await?;
+        Ok(ids)
+    }
 
+    /// Materials without a size, or videos without a duration.
+    async fn missing_media(&self) -> Result<Vec<Material>> {
+        let materials = sqlx::query_as(
+            r#"
+            SELECT id, name, raw_name, description, creator, workspace_id, state, type, size, duration, created_at
+            FROM materials
+            WHERE size IS NULL OR (type = ? AND duration IS NULL)
+            "#,
+        )
+            .bind(TYPE_VIDEO as i64)
+            .fetch_all(self.db)
+            .await?;
         Ok(materials)
     }
 
-    async fn update_name(&self, id: &Id, name: &str) -> Result<()> {
+    async fn update_media(&self, id: &Id, size: i64, duration: Option<f64>) -> Result<()> {
+        let id_str = id.deref();
+        sqlx::query!(
+            "UPDATE materials SET size = ?, duration = ? WHERE id = ?",
+            size,
+            duration,
+            id_str
+        )
+            .execute(self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Marks a transcoded video `ok`.
+    async fn update_processed(&self, id: &Id, duration: f64) -> Result<()> {
         let id_str = id.deref();
+        let state = STATE_OK as i64;
+        sqlx::query!(
+            "UPDATE materials SET state = ?, duration = ? WHERE id = ?",
+            state,
+            duration,
+            id_str
+        )
+            .execute(self.db)
+            .await?;
+        Ok(())
+    }
 
+    async fn update_state(&self, id: &Id, state: u16) -> Result<()> {
+        let id_str = id.deref();
+        let state = state as i64;
         sqlx::query!(
+            "UPDATE materials SET state = ? WHERE id = ?",
+            state,
+            id_str
+        )
+            .execute(self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Records that process `instance` processes the material `id`.
+    async fn claim(&self, id: &str, instance: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO material_processing (material_id, instance) VALUES (?, ?)
+            ON CONFLICT (material_id) DO UPDATE SET instance = excluded.instance
+            "#,
+        )
+            .bind(id)
+            .bind(instance)
+            .execute(self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// The process that processes, or last processed, the material `id`.
+    async fn owner(&self, id: &Id) -> Result<Option<String>> {
+        let owner = sqlx::query_scalar("SELECT instance FROM material_processing WHERE material_id = ?")
+            .bind(id.as_ref())
+            .fetch_optional(self.db)
+            .await?;
+        Ok(owner)
+    }
+
+    /// Fails a material still `processing` after its process stopped.
+    async fn fail_processing(&self, id: &Id) -> Result<()> {
+        sqlx::query("UPDATE materials SET state = ? WHERE id = ? AND state = ?")
+            .bind(STATE_FAILED as i64)
+            .bind(id.as_ref())
+            .bind(STATE_PROCESSING as i64)
+            .execute(self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn exists(&self, workspace: &str, id: &Id) -> Result<bool> {
+        let count: i64 = sqlx::query_scalar(
+            "SELECT COUNT(1) FROM materials WHERE id = ? AND workspace_id = ?",
+        )
+            .bind(id.as_ref())
+            .bind(workspace)
+            .fetch_one(self.db)
+            .await?;
+
+        Ok(count > 0)
+    }
+
+    async fn update_name(&self, workspace: &str, id: &Id, name: &str) -> Result<()> {
+        let id_str = id.deref();
+
+        let result = sqlx::query!(
             r#"
-            UPDATE materials SET name = ? WHERE id = ?
+            UPDATE materials SET name = ? WHERE id = ? AND workspace_id = ?
             "#,
             name,
-            id_str
+            id_str,
+            workspace
         ).execute(self.db).await?;
 
+        if result.rows_affected() == 0 {
+            return Err(AppError::MaterialNotFound(id.to_string()));
+        }
+
         Ok(())
     }
 
-    async fn delete(&self, id: &Id) -> Result<()> {
+    async fn delete(&self, workspace: &str, id: &Id) -> Result<()> {
         let mut tx = self.db.begin().await?;
 
         let id_str = id.deref();
 
         sqlx::query!(
             r#"
-            DELETE FROM materials WHERE id = ?
+            DELETE FROM materials WHERE id = ? AND workspace_id = ?
             "#,
-            id_str
+            id_str,
+            workspace
         )
             .execute(&mut *tx)
             .await?;
@@ -637,25 +2061,58 @@ impl MaterialsRepo {
             .execute(&mut *tx)
             .await?;
 
+        sqlx::query!(
+            r#"
+            DELETE FROM material_sources WHERE material_id = ?
+            "#,
+            id_str
+        )
+            .execute(&mut *tx)
+            .await?;
+
         tx.commit().await?;
 
         Ok(())
     }
 
-    async fn delete_by_ids(&self, ids: &[Id]) -> Result<()> {
+    /// Deletes the materials of `ids` belonging to `workspace`, returning their ids.
+    async fn delete_by_ids(&self, workspace: &str, ids: &[Id]) -> Result<Vec<String>> {
         let mut tx = self.db.begin().await?;
 
-        QueryBuilder::new("DELETE FROM materials WHERE id IN")
+        // only ids of this workspace, so tags of other workspaces' materials stay untouched
+        let ids: Vec<String> = QueryBuilder::new("SELECT id FROM materials WHERE workspace_id = ")
+            .push_bind(workspace)
+            .push(" AND id IN")
             .push_tuples(ids.iter(), |mut b, id| {
                 b.push_bind(id.deref());
             })
+            .build_query_scalar()
+            .fetch_all(&mut *tx)
+            .await?;
+
+        if ids.is_empty() {
+            return Ok(ids);
+        }
+
+        QueryBuilder::new("DELETE FROM materials WHERE id IN")
+            .push_tuples(ids.iter(), |mut b, id| {
+                b.push_bind(id.as_str());
+            })
             .build()
             .execute(&mut *tx)
             .await?;
 
         QueryBuilder::new("DELETE FROM material_tags WHERE material_id IN")
             .push_tuples(ids.iter(), |mut b, id| {
-                b.push_bind(id.deref());
+                b.push_bind(id.as_str());
+            })
+            .build()
+            .execute(&mut *tx)
+            .await?;
+
+        QueryBuilder::new("DELETE FROM material_sources WHERE material_id IN")
+            .push_tuples(ids.iter(), |mut b, id| {
+                b.push_bind(id.as_str());
             })
             .build()
             .execute(&mut *tx)
@@ -663,6 +2120,6 @@ impl MaterialsRepo {
 
         tx.commit().await?;
 
-        Ok(())
+        Ok(ids)
     }
 }
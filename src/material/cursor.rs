@@ -0,0 +1,146 @@
+use base64ct::{Base64UrlUnpadded, Encoding};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteArguments, Arguments};
+
+use crate::{
+    common::{AppError, Result},
+    material::{SortDirection, SortField},
+};
+
+/// Value of the sort field of the last record of a page, `None` for a NULL column.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum CursorKey {
+    CreatedAt(NaiveDateTime),
+    Name(Option<String>),
+    Duration(Option<f64>),
+    Size(Option<i64>),
+}
+
+impl CursorKey {
+    pub(crate) fn field(&self) -> SortField {
+        match self {
+            CursorKey::CreatedAt(_) => SortField::CreatedAt,
+            CursorKey::Name(_) => SortField::Name,
+            CursorKey::Duration(_) => SortField::Duration,
+            CursorKey::Size(_) => SortField::Size,
+        }
+    }
+
+    fn is_null(&self) -> bool {
+        match self {
+            CursorKey::CreatedAt(_) => false,
+            CursorKey::Name(value) => value.is_none(),
+            CursorKey::Duration(value) => value.is_none(),
+            CursorKey::Size(value) => value.is_none(),
+        }
+    }
+
+    fn add_to(&self, args: &mut SqliteArguments<'_>) -> Result<()> {
+        match self {
+            CursorKey::CreatedAt(value) => args.add(*value)?,
+            CursorKey::Name(value) => args.add(value.clone())?,
+            CursorKey::Duration(value) => args.add(*value)?,
+            CursorKey::Size(value) => args.add(*value)?,
+        }
+        Ok(())
+    }
+}
+
+/// Opaque keyset position: `(sort key, id)` of the last record seen.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub(crate) struct Cursor {
+    key: CursorKey,
+    id: String,
+    direction: SortDirection,
+}
+
+impl Cursor {
+    pub(crate) fn new(key: CursorKey, id: String, direction: SortDirection) -> Self {
+        Self { key, id, direction }
+    }
+
+    pub(crate) fn field(&self) -> SortField {
+        self.key.field()
+    }
+
+    pub(crate) fn direction(&self) -> SortDirection {
+        self.direction
+    }
+
+    pub(crate) fn encode(&self) -> Result<String> {
+        let json = serde_json::to_vec(self).map_err(|e| AppError::Other(e.into()))?;
+        Ok(Base64UrlUnpadded::encode_string(&json))
+    }
+
+    pub(crate) fn decode(value: &str) -> Result<Self> {
+        let json = Base64UrlUnpadded::decode_vec(value)
+            .map_err(|_| AppError::InvalidCursor(value.to_string()))?;
+        serde_json::from_slice(&json).map_err(|_| AppError::InvalidCursor(value.to_string()))
+    }
+
+    /// Appends the keyset condition, e.g. ` AND (created_at < ? OR (created_at = ? AND id < ?) ...)`.
+    /// NULLs sort first ascending and last descending, as SQLite orders them.
+    pub(crate) fn push_where(&self, sql_where: &mut String, args: &mut SqliteArguments<'_>) -> Result<()> {
+        let column = self.field().column();
+        let condition = match (self.direction, self.key.is_null()) {
+            (SortDirection::Asc, false) => format!("({column} > ? OR ({column} = ? AND id > ?))"),
+            (SortDirection::Desc, false) => {
+                format!("({column} < ? OR ({column} = ? AND id < ?) OR {column} IS NULL)")
+            }
+            (SortDirection::Asc, true) => format!("(({column} IS NULL AND id > ?) OR {column} IS NOT NULL)"),
+            (SortDirection::Desc, true) => format!("({column} IS NULL AND id < ?)"),
+        };
+        sql_where.push_str(" AND ");
+        sql_where.push_str(&condition);
+        if !self.key.is_null() {
+            self.key.add_to(args)?;
+            self.key.add_to(args)?;
+        }
+        args.add(self.id.clone())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cursor_round_trip() -> anyhow::Result<()> {
+        let created_at = NaiveDateTime::parse_from_str("2024-10-17 08:30:00.123", "%F %T%.f")?;
+        let cursor = Cursor::new(
+            CursorKey::CreatedAt(created_at),
+            "abc".to_string(),
+            SortDirection::Desc,
+        );
+
+        let decoded = Cursor::decode(&cursor.encode()?)?;
+
+        assert_eq!(decoded, cursor);
+        assert_eq!(decoded.field(), SortField::CreatedAt);
+        Ok(())
+    }
+
+    #[test]
+    fn test_null_key() -> anyhow::Result<()> {
+        let cursor = Cursor::new(CursorKey::Size(None), "abc".to_string(), SortDirection::Desc);
+        let mut sql_where = String::new();
+        let mut args = SqliteArguments::default();
+        cursor.push_where(&mut sql_where, &mut args)?;
+
+        // descending, NULLs come last: only the NULL ones after `abc` are left
+        assert_eq!(sql_where, " AND (size IS NULL AND id < ?)");
+        assert_eq!(Cursor::decode(&cursor.encode()?)?, cursor);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_cursor() {
+        assert!(matches!(
+            Cursor::decode("not a cursor"),
+            Err(AppError::InvalidCursor(_))
+        ));
+    }
+}
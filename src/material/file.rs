@@ -0,0 +1,182 @@
+use std::{io::ErrorKind, path::Path as FsPath};
+
+use ioc::{mvc, Bean, OpenApi};
+use poem::{Body, Request};
+use poem_openapi::{
+    param::{Path, Query},
+    payload::Binary,
+    ApiResponse,
+};
+use tokio::fs::{read_to_string, File};
+
+use crate::{
+    auth::apikey::{self, API_KEY_PREFIX},
+    common::{AppError, Result},
+    ffmpeg::slice::RENDITIONS,
+    material::{biz::MaterialsService, storage::Id},
+    metrics::metered,
+};
+
+#[derive(ApiResponse)]
+pub(crate) enum FileResponse {
+    #[oai(status = 200)]
+    Ok(Binary<Body>, #[oai(header = "Content-Type")] String),
+}
+
+/// Content type of a material file by its name, the raw file is whatever was uploaded.
+fn content_type(name: &str) -> &'static str {
+    match FsPath::new(name).extension().and_then(|ext| ext.to_str()) {
+        Some("m3u8") => "application/vnd.apple.mpegurl",
+        Some("ts") => "video/mp2t",
+        Some("mp4") => "video/mp4",
+        Some("jpeg" | "jpg") => "image/jpeg",
+        Some("png") => "image/png",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Files are generated by the server, so anything hidden or outside a material is refused.
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(AppError::MaterialNotFound(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Adds `token` to the uris of a playlist, players drop the query of the playlist url when
+/// resolving the relative uris of renditions and segments.
+fn with_token(playlist: &str, token: &str) -> String {
+    let query = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("token", token)
+        .finish();
+    playlist
+        .lines()
+        .map(|line| match line.trim() {
+            uri if uri.is_empty() || uri.starts_with('#') => line.to_string(),
+            _ => format!("{line}?{query}"),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        + "\n"
+}
+
+/// Serves the files of materials, like their urls in search results, to workspace members or
+/// to anyone with a signed url, as `<img>` and video players can't send the `auth` header.
+#[derive(Bean)]
+pub(crate) struct FileMvc {
+    #[inject(bean)]
+    materials_svc: &'static MaterialsService,
+}
+
+impl FileMvc {
+    async fn send(
+        &self,
+        workspace: &str,
+        id: &Id,
+        name: &str,
+        token: Option<String>,
+        req: &Request,
+    ) -> Result<FileResponse> {
+        let path = match &token {
+            Some(token) => self.materials_svc.signed_file(workspace, id, name, token).await?,
+            None => {
+                let claims = apikey::authenticate(req).await?;
+                // api keys are issued for one workspace, sessions open every workspace of the user
+                let api_key = req.header("auth").is_some_and(|key| key.starts_with(API_KEY_PREFIX));
+                if api_key && workspace != claims.workspace {
+                    return Err(AppError::WorkspaceForbidden(workspace.to_string()));
+                }
+                self.materials_svc.file(workspace, id, name, &claims).await?
+            }
+        };
+        let content_type = content_type(name).to_string();
+        if let (Some(token), true) = (&token, name.ends_with(".m3u8")) {
+            let playlist = match read_to_string(&path).await {
+                Ok(playlist) => playlist,
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    return Err(AppError::MaterialNotFound(format!("{id}/{name}")))
+                }
+                Err(e) => return Err(e.into()),
+            };
+            return Ok(FileResponse::Ok(
+                Binary(Body::from_string(with_token(&playlist, token))),
+                content_type,
+            ));
+        }
+        let file = match File::open(&path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Err(AppError::MaterialNotFound(format!("{id}/{name}")))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(FileResponse::Ok(
+            Binary(Body::from_async_read(file)),
+            content_type,
+        ))
+    }
+}
+
+#[mvc]
+#[OpenApi(prefix_path = "/api/v1")]
+impl FileMvc {
+    /// Raw file or thumbnail of a material, or the playlist of its slices
+    #[oai(path = "/files/:workspace/:id/:name", method = "get", transform = "metered")]
+    async fn file(
+        &self,
+        workspace: Path<String>,
+        id: Path<Id>,
+        name: Path<String>,
+        /// signed by the server in the urls of materials, instead of the `auth` header
+        token: Query<Option<String>>,
+        req: &Request,
+    ) -> Result<FileResponse> {
+        check_name(&name)?;
+        self.send(&workspace, &id, &name, token.0, req).await
+    }
+
+    /// Playlist or segment of one rendition of a video
+    #[oai(path = "/files/:workspace/:id/:rendition/:name", method = "get", transform = "metered")]
+    async fn rendition(
+        &self,
+        workspace: Path<String>,
+        id: Path<Id>,
+        rendition: Path<String>,
+        name: Path<String>,
+        /// signed by the server in the urls of materials, instead of the `auth` header
+        token: Query<Option<String>>,
+        req: &Request,
+    ) -> Result<FileResponse> {
+        if !RENDITIONS.contains(&rendition.as_str()) {
+            return Err(AppError::MaterialNotFound(rendition.0));
+        }
+        check_name(&name)?;
+        let name = format!("{}/{}", rendition.0, name.0);
+        self.send(&workspace, &id, &name, token.0, req).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_name() {
+        assert!(check_name("thumbnail.jpeg").is_ok());
+        assert!(check_name("raw").is_ok());
+        assert!(check_name(".downloads").is_err());
+        assert!(check_name("..").is_err());
+        assert!(check_name("a/b").is_err());
+        assert_eq!(content_type("slice.m3u8"), "application/vnd.apple.mpegurl");
+        assert_eq!(content_type("raw"), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_with_token() {
+        let playlist = "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=2800000\n  720p/slice.m3u8\n\n#EXT-X-ENDLIST\n";
+        assert_eq!(
+            with_token(playlist, "17.a+b"),
+            "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=2800000\n  720p/slice.m3u8?token=17.a%2Bb\n\n#EXT-X-ENDLIST\n"
+        );
+    }
+}
@@ -0,0 +1,420 @@
+//! Bulk import of files already on the server, e.g. when migrating from a NAS. The source is a
+//! directory, walked recursively, or a CSV manifest. Every outcome is appended to a JSONL report;
+//! running the same import again skips what the report lists as done and retries failures.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use base64ct::{Base64UrlUnpadded, Encoding};
+use fs2::FileExt;
+use ioc::Bean;
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncReadExt, AsyncWriteExt},
+    spawn,
+    sync::mpsc::{channel, Sender},
+};
+use tracing::{error, info};
+
+use crate::{
+    auth::{account::AccountService, jwt::Claims},
+    common::{AppError, FormatedEvent, Result},
+    ffmpeg::scheduler::Priority,
+    material::{
+        biz::{Imported, MaterialInfo, MaterialsService},
+        mvc::ImportRequest,
+        storage::Id,
+        MaterialType,
+    },
+    workspace::{biz::WorkspaceService, WorkspaceRole},
+};
+
+const VIDEO_EXTENSIONS: [&str; 7] = ["mp4", "mov", "mkv", "webm", "avi", "m4v", "flv"];
+const IMAGE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "gif", "webp", "bmp"];
+
+/// Separates the tags in the `tags` column of a manifest.
+const TAG_SEPARATOR: char = ';';
+
+/// Material type of a file by its extension, `None` for files that can't be imported.
+pub(crate) fn material_type(path: &Path) -> Option<MaterialType> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    if VIDEO_EXTENSIONS.contains(&extension.as_str()) {
+        Some(MaterialType::Video)
+    } else if IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+        Some(MaterialType::Image)
+    } else {
+        None
+    }
+}
+
+/// A file to import with the material it becomes.
+pub(crate) struct ImportFile {
+    pub(crate) path: PathBuf,
+    pub(crate) info: MaterialInfo,
+}
+
+impl ImportFile {
+    fn named_after(path: PathBuf) -> Self {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        Self {
+            path,
+            info: MaterialInfo {
+                name,
+                description: None,
+                tags: Vec::new(),
+            },
+        }
+    }
+}
+
+/// Row of a manifest, `path` is relative to the manifest. Tags are separated by `;`.
+#[derive(Deserialize, Debug)]
+struct ManifestRow {
+    path: PathBuf,
+    name: Option<String>,
+    description: Option<String>,
+    tags: Option<String>,
+}
+
+/// Files of `source`: the importable files under a directory, or the rows of a `.csv` manifest.
+pub(crate) async fn files(source: &Path) -> Result<Vec<ImportFile>> {
+    if fs::metadata(source).await?.is_dir() {
+        let mut files = Vec::new();
+        let mut dirs = vec![source.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = fs::read_dir(dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+                if entry.file_type().await?.is_dir() {
+                    dirs.push(path);
+                } else if material_type(&path).is_some() {
+                    files.push(path);
+                }
+            }
+        }
+        // a predictable order makes an interrupted import easy to follow in the report
+        files.sort();
+        Ok(files.into_iter().map(ImportFile::named_after).collect())
+    } else {
+        let content = fs::read(source).await?;
+        let base = source.parent().unwrap_or(Path::new("."));
+        parse_manifest(&content, base)
+    }
+}
+
+fn parse_manifest(content: &[u8], base: &Path) -> Result<Vec<ImportFile>> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(content);
+    let mut files = Vec::new();
+    for row in reader.deserialize() {
+        let row: ManifestRow = row.map_err(|e| anyhow::anyhow!("invalid manifest: {e}"))?;
+        let mut file = ImportFile::named_after(base.join(row.path));
+        if let Some(name) = row.name.filter(|name| !name.is_empty()) {
+            file.info.name = name;
+        }
+        file.info.description = row.description.filter(|description| !description.is_empty());
+        file.info.tags = row
+            .tags
+            .iter()
+            .flat_map(|tags| tags.split(TAG_SEPARATOR))
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(ToString::to_string)
+            .collect();
+        files.push(file);
+    }
+    Ok(files)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Enum)]
+#[serde(rename_all = "lowercase")]
+#[oai(rename_all = "lowercase")]
+pub(crate) enum ImportStatus {
+    Imported,
+    /// the workspace already holds a material with the same content
+    Duplicate,
+    Failed,
+}
+
+/// Outcome of one file, a line of the report.
+#[derive(Serialize, Deserialize, Debug, Object)]
+pub(crate) struct ImportRecord {
+    pub(crate) path: String,
+    pub(crate) status: ImportStatus,
+    /// the new material, or the existing one for duplicates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[oai(skip_serializing_if_is_none)]
+    pub(crate) id: Option<Id>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[oai(skip_serializing_if_is_none)]
+    pub(crate) error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default, Object)]
+pub(crate) struct ImportSummary {
+    pub(crate) imported: u64,
+    pub(crate) duplicates: u64,
+    pub(crate) failed: u64,
+    /// done by an earlier run of the same report
+    pub(crate) skipped: u64,
+}
+
+/// Paths a report lists as done. Failed ones are not, they are retried.
+fn done(content: &str) -> HashSet<String> {
+    content
+        .lines()
+        // the last line may be cut off by a crash
+        .filter_map(|line| serde_json::from_str::<ImportRecord>(line).ok())
+        .filter(|record| record.status != ImportStatus::Failed)
+        .map(|record| record.path)
+        .collect()
+}
+
+/// The report of an import, locked so a second import of it, by this or another process, fails
+/// instead of interleaving its records. Unlocked when dropped.
+pub(crate) struct Report {
+    writer: File,
+    done: HashSet<String>,
+}
+
+impl Report {
+    pub(crate) async fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).await?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .await?
+            .into_std()
+            .await;
+        if let Err(e) = file.try_lock_exclusive() {
+            return Err(match e.kind() == fs2::lock_contended_error().kind() {
+                true => AppError::ImportRunning(path.display().to_string()),
+                false => e.into(),
+            });
+        }
+
+        let mut writer = File::from_std(file);
+        let mut content = String::new();
+        writer.read_to_string(&mut content).await?;
+        if !content.is_empty() && !content.ends_with('\n') {
+            // end the line cut off by a crash so the next record starts on its own
+            writer.write_all(b"\n").await?;
+        }
+        Ok(Self {
+            writer,
+            done: done(&content),
+        })
+    }
+}
+
+pub(crate) struct ImportOptions {
+    /// hard link the files into the storage instead of copying them
+    pub(crate) link: bool,
+    /// added to the tags of every file
+    pub(crate) tags: Vec<String>,
+}
+
+/// Imports `files` into the active workspace of `claims`, appending to `report` and sending each
+/// record to `records`. Callers check the workspace role.
+pub(crate) async fn import(
+    materials: &MaterialsService,
+    files: Vec<ImportFile>,
+    report: Report,
+    options: &ImportOptions,
+    claims: &Claims,
+    records: &Sender<ImportRecord>,
+) -> Result<ImportSummary> {
+    let Report { mut writer, done } = report;
+
+    // transcode progress is of no interest here
+    let (tx, mut rx) = channel::<FormatedEvent>(64);
+    spawn(async move { while rx.recv().await.is_some() {} });
+
+    let mut summary = ImportSummary::default();
+    for mut file in files {
+        let path = file.path.to_string_lossy().to_string();
+        if done.contains(&path) {
+            summary.skipped += 1;
+            continue;
+        }
+
+        file.info.tags.extend(options.tags.iter().cloned());
+        let result = match material_type(&file.path) {
+            Some(kind) => {
+                let id = Id::new_uuid();
+                materials
+                    .import_file(id, &file.path, kind, file.info, options.link, Priority::Bulk, &tx, claims)
+                    .await
+            }
+            None => Err(anyhow::anyhow!("unsupported file type").into()),
+        };
+
+        let (status, id, error) = match result {
+            Ok(Imported::New(id)) => (ImportStatus::Imported, Some(id), None),
+            Ok(Imported::Duplicate(id)) => (ImportStatus::Duplicate, Some(id), None),
+            Err(e) => (ImportStatus::Failed, None, Some(e.to_string())),
+        };
+        match status {
+            ImportStatus::Imported => summary.imported += 1,
+            ImportStatus::Duplicate => summary.duplicates += 1,
+            ImportStatus::Failed => summary.failed += 1,
+        }
+
+        let record = ImportRecord { path, status, id, error };
+        let mut line = serde_json::to_string(&record).map_err(anyhow::Error::from)?;
+        line.push('\n');
+        writer.write_all(line.as_bytes()).await?;
+        writer.flush().await?;
+        // the import goes on when nobody listens anymore, the report has everything
+        let _ = records.send(record).await;
+    }
+
+    Ok(summary)
+}
+
+/// Imports through the api, limited to files under `import.roots`.
+#[derive(Bean)]
+pub(crate) struct ImportService {
+    #[inject(bean)]
+    materials: &'static MaterialsService,
+    #[inject(bean)]
+    accounts: &'static AccountService,
+    #[inject(bean)]
+    workspaces: &'static WorkspaceService,
+    /// directories the api may import from, nothing when empty
+    #[inject(config = "import.roots")]
+    roots: Vec<PathBuf>,
+    #[inject(config = "import.report-dir")]
+    report_dir: PathBuf,
+}
+
+impl ImportService {
+    /// Fails unless `path` is under one of the roots, returning it canonicalized.
+    async fn allowed(&self, path: &Path) -> Result<PathBuf> {
+        let forbidden = || AppError::ImportSourceForbidden(path.display().to_string());
+        let path = fs::canonicalize(path).await.map_err(|_| forbidden())?;
+        for root in &self.roots {
+            if let Ok(root) = fs::canonicalize(root).await {
+                if path.starts_with(&root) {
+                    return Ok(path);
+                }
+            }
+        }
+        Err(forbidden())
+    }
+
+    /// Starts importing `request.source` into the active workspace in the background, sending
+    /// every record to `records`. The report is kept per workspace and source, so posting the
+    /// same source again resumes the import.
+    pub(crate) async fn start(
+        &'static self,
+        request: ImportRequest,
+        claims: Claims,
+        records: Sender<ImportRecord>,
+    ) -> Result<()> {
+        if !self.accounts.is_admin(&claims) {
+            return Err(AppError::AdminRequired);
+        }
+        self.workspaces.require(&claims, WorkspaceRole::Editor).await?;
+
+        let source = self.allowed(Path::new(&request.source)).await?;
+        let files = files(&source).await?;
+        // rows of a manifest may point anywhere
+        for file in &files {
+            if fs::try_exists(&file.path).await? {
+                self.allowed(&file.path).await?;
+            }
+        }
+
+        let key = format!("{}\n{}", claims.workspace, source.display());
+        let report = self
+            .report_dir
+            .join(format!("{}.jsonl", Base64UrlUnpadded::encode_string(&Sha256::digest(key.as_bytes()))));
+        // before answering, so a running import of the source is a conflict
+        let report = Report::open(&report).await?;
+        let options = ImportOptions {
+            link: request.link.unwrap_or(false),
+            tags: request.tags.unwrap_or_default(),
+        };
+
+        spawn(async move {
+            let result = import(self.materials, files, report, &options, &claims, &records).await;
+            match result {
+                Ok(summary) => info!("import of {} done: {summary:?}", source.display()),
+                Err(e) => error!("import of {} failed: {e}", source.display()),
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_material_type() {
+        assert_eq!(material_type(Path::new("a/clip.MP4")), Some(MaterialType::Video));
+        assert_eq!(material_type(Path::new("cover.jpeg")), Some(MaterialType::Image));
+        assert_eq!(material_type(Path::new("notes.txt")), None);
+        assert_eq!(material_type(Path::new("README")), None);
+    }
+
+    #[test]
+    fn test_parse_manifest() -> anyhow::Result<()> {
+        let manifest = b"path,name,description,tags
+videos/a.mp4,Opening,first take,intro; 2019
+b.png,,,
+";
+        let files = parse_manifest(manifest, Path::new("/nas"))?;
+        assert_eq!(files.len(), 2);
+
+        assert_eq!(files[0].path, Path::new("/nas/videos/a.mp4"));
+        assert_eq!(files[0].info.name, "Opening");
+        assert_eq!(files[0].info.description.as_deref(), Some("first take"));
+        assert_eq!(files[0].info.tags, vec!["intro", "2019"]);
+
+        assert_eq!(files[1].info.name, "b.png");
+        assert_eq!(files[1].info.description, None);
+        assert!(files[1].info.tags.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_done() {
+        let report = r#"{"path":"a.mp4","status":"imported","id":"1"}
+{"path":"b.mp4","status":"failed","error":"boom"}
+{"path":"c.mp4","status":"duplicate","id":"2"}
+{"path":"d.mp4","sta"#;
+
+        let done = done(report);
+        assert_eq!(done, HashSet::from(["a.mp4".to_string(), "c.mp4".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn test_report_locked() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("phi-import-{}.jsonl", Id::new_uuid()));
+        fs::write(&path, r#"{"path":"a.mp4","status":"imported","id":"1"}"#).await?;
+
+        let report = Report::open(&path).await?;
+        assert!(report.done.contains("a.mp4"));
+        assert!(matches!(Report::open(&path).await, Err(AppError::ImportRunning(_))));
+        drop(report);
+        assert!(Report::open(&path).await.is_ok());
+        // the line cut off was ended
+        assert!(fs::read_to_string(&path).await?.ends_with('\n'));
+        fs::remove_file(&path).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+//! The process a video is processed by. Every process locks a file of its own under the
+//! storage directory for as long as it runs, and `material_processing` records which process
+//! began processing a material. A `processing` material whose process holds no lock anymore
+//! was interrupted; one whose process still does is processing elsewhere, e.g. in
+//! `phi material reprocess` next to the server.
+
+use std::{
+    fs::{create_dir_all, read_dir, remove_file, File, OpenOptions},
+    io::{self, ErrorKind},
+    path::PathBuf,
+};
+
+use fs2::FileExt;
+use ioc::{bean, BeanSpec, InitContext};
+use tokio::runtime::Builder;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{db, material::biz::fail_interrupted};
+
+/// Directory of the lock files, under the storage directory.
+const DIR: &str = ".instances";
+
+pub(crate) struct Instance {
+    id: String,
+    dir: PathBuf,
+    /// locked until the process exits
+    _lock: File,
+}
+
+#[bean]
+impl BeanSpec for Instance {
+    type Bean = Self;
+
+    fn build(ctx: &mut impl InitContext) -> ioc::Result<Self::Bean> {
+        let database_url = ctx.get_config::<String>("db.url")?;
+        let instance = Self::new(ctx.get_config::<PathBuf>("storage.dir")?)?;
+
+        // beans are built before the server's runtime exists, like in `Db`
+        let failed = Builder::new_current_thread()
+            .enable_time()
+            .build()?
+            .block_on(async {
+                let pool = db::init(&database_url, 1).await?;
+                let failed = fail_interrupted(&pool, &instance).await;
+                pool.close().await;
+                failed.map_err(anyhow::Error::from)
+            })?;
+        if failed > 0 {
+            warn!("{failed} videos were left processing by stopped processes, marked failed");
+        }
+        Ok(instance)
+    }
+}
+
+impl Instance {
+    /// Locks a new file under `storage_dir`.
+    pub(crate) fn new(storage_dir: PathBuf) -> io::Result<Self> {
+        let dir = storage_dir.join(DIR);
+        create_dir_all(&dir)?;
+        let id = Uuid::new_v4().to_string();
+        let lock = File::create(dir.join(format!("{id}.lock")))?;
+        lock.try_lock_exclusive()?;
+        Ok(Self { id, dir, _lock: lock })
+    }
+
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Whether the process `id` still runs. Processes of another storage directory are unknown,
+    /// so they count as stopped.
+    pub(crate) fn is_alive(&self, id: &str) -> bool {
+        if id == self.id {
+            return true;
+        }
+        let file = match OpenOptions::new().write(true).open(self.dir.join(format!("{id}.lock"))) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return false,
+            Err(e) => {
+                warn!("checking process {id} failed: {e}");
+                return true;
+            }
+        };
+        match file.try_lock_exclusive() {
+            Ok(()) => {
+                let _ = file.unlock();
+                false
+            }
+            Err(_) => true,
+        }
+    }
+
+    /// Removes the lock files of stopped processes, once nothing refers to them anymore.
+    pub(crate) fn forget_stopped(&self) {
+        let Ok(entries) = read_dir(&self.dir) else { return };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let Some(id) = name.to_str().and_then(|name| name.strip_suffix(".lock")) else {
+                continue;
+            };
+            if !self.is_alive(id) {
+                let _ = remove_file(entry.path());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_alive() {
+        let dir = std::env::temp_dir().join(format!("phi-instances-{}", Uuid::new_v4()));
+        let first = Instance::new(dir.clone()).unwrap();
+        let second = Instance::new(dir.clone()).unwrap();
+        assert!(first.is_alive(second.id()));
+        assert!(second.is_alive(first.id()));
+        assert!(!first.is_alive("unknown"));
+
+        let stopped = second.id().to_string();
+        drop(second);
+        assert!(!first.is_alive(&stopped));
+        first.forget_stopped();
+        assert!(!dir.join(DIR).join(format!("{stopped}.lock")).exists());
+        assert!(dir.join(DIR).join(format!("{}.lock", first.id())).exists());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -2,13 +2,19 @@ use poem_openapi::Enum;
 use serde::{Deserialize, Serialize};
 
 pub mod biz;
+pub mod cursor;
+pub mod file;
+pub mod import;
+pub mod instance;
 pub mod mvc;
+pub mod progress;
+pub mod remote;
 pub mod storage;
 
 pub const TYPE_VIDEO: u16 = 1;
 pub const TYPE_IMAGE: u16 = 2;
 
-#[derive(Serialize, Deserialize, Debug, Enum)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Enum)]
 pub enum MaterialType {
     Video,
     Image,
@@ -21,6 +27,100 @@ impl MaterialType {
             MaterialType::Image => TYPE_IMAGE,
         }
     }
+
+    pub(crate) fn from_value(value: u16) -> Option<Self> {
+        match value {
+            TYPE_VIDEO => Some(MaterialType::Video),
+            TYPE_IMAGE => Some(MaterialType::Image),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            MaterialType::Video => "Video",
+            MaterialType::Image => "Image",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, Enum)]
+#[serde(rename_all = "snake_case")]
+#[oai(rename_all = "snake_case")]
+pub enum SortField {
+    #[default]
+    CreatedAt,
+    Name,
+    Duration,
+    Size,
+}
+
+impl SortField {
+    /// column ordered by, left bare so the `(workspace_id, <column>)` indexes serve the order;
+    /// see [`crate::material::cursor::Cursor::push_where`] for NULLs
+    pub(crate) fn column(&self) -> &'static str {
+        match self {
+            SortField::CreatedAt => "created_at",
+            SortField::Name => "name",
+            SortField::Duration => "duration",
+            SortField::Size => "size",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, Enum)]
+#[serde(rename_all = "lowercase")]
+#[oai(rename_all = "lowercase")]
+pub enum SortDirection {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl SortDirection {
+    pub(crate) fn value(&self) -> &'static str {
+        match self {
+            SortDirection::Asc => "ASC",
+            SortDirection::Desc => "DESC",
+        }
+    }
 }
 
 pub const STATE_OK: u16 = 0;
+/// stored, the thumbnail and slices are being generated
+pub const STATE_PROCESSING: u16 = 1;
+/// transcoding failed, the raw file is kept for a reprocess
+pub const STATE_FAILED: u16 = 2;
+/// transcoding was cancelled, the raw file is kept for a reprocess
+pub const STATE_CANCELLED: u16 = 3;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Enum)]
+#[serde(rename_all = "lowercase")]
+#[oai(rename_all = "lowercase")]
+pub enum MaterialState {
+    Ok,
+    Processing,
+    Failed,
+    Cancelled,
+}
+
+impl MaterialState {
+    pub(crate) fn value(&self) -> u16 {
+        match self {
+            MaterialState::Ok => STATE_OK,
+            MaterialState::Processing => STATE_PROCESSING,
+            MaterialState::Failed => STATE_FAILED,
+            MaterialState::Cancelled => STATE_CANCELLED,
+        }
+    }
+
+    pub(crate) fn from_value(value: u16) -> Option<Self> {
+        match value {
+            STATE_OK => Some(MaterialState::Ok),
+            STATE_PROCESSING => Some(MaterialState::Processing),
+            STATE_FAILED => Some(MaterialState::Failed),
+            STATE_CANCELLED => Some(MaterialState::Cancelled),
+            _ => None,
+        }
+    }
+}
@@ -1,17 +1,27 @@
 use crate::material::biz::MaterialImage;
 use crate::{
     auth::apikey::JwtAuth,
-    common::{FormatedEvent, Page, PageResult, Response, Result},
+    common::{FormatedEvent, Page, Response, Result},
+    ffmpeg::clip::Crop,
     material::{
         biz::{
             MaterialDetail,
             MaterialsService,
+            SearchResult,
         },
-        storage::{Id, LocalStorage, Storage},
-        MaterialType,
+        import::{ImportRecord, ImportService},
+        remote::RemoteImportService,
+        storage::Id,
+        MaterialType, SortDirection, SortField,
     },
-    util::poem::BaseUrl,
+    metrics::metered,
+    util::{
+        limit::{RateLimit, Search, TranscodePermit, Upload},
+        poem::{BaseUrl, ClientInfo},
+    },
+    workspace::{biz::WorkspaceService, WorkspaceRole},
 };
+use chrono::NaiveDateTime;
 use ioc::{mvc, Bean, OpenApi};
 use poem::web::Field;
 use poem_openapi::{
@@ -77,6 +87,18 @@ pub(crate) struct SearchCondition {
     pub(crate) page: Page,
     pub(crate) query: Option<String>,
     pub(crate) r#type: Option<MaterialType>,
+    /// field to order by, defaults to `created_at`
+    pub(crate) sort: Option<SortField>,
+    /// defaults to `desc`
+    pub(crate) direction: Option<SortDirection>,
+    /// `next_cursor` of the previous result; when set, `page` is ignored and the sort of the cursor is used
+    pub(crate) cursor: Option<String>,
+    /// only materials created at or after this time
+    pub(crate) created_from: Option<NaiveDateTime>,
+    /// only materials created before this time
+    pub(crate) created_to: Option<NaiveDateTime>,
+    /// also return counts per tag, type, creator and upload month
+    pub(crate) facets: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Object)]
@@ -89,24 +111,65 @@ pub(crate) struct MaterialPatchRequest {
     pub(crate) name: String,
 }
 
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub(crate) struct ImportRequest {
+    /// directory or CSV manifest on the server, under one of `import.roots`
+    pub(crate) source: String,
+    /// hard link the files into the storage instead of copying them, defaults to `false`
+    pub(crate) link: Option<bool>,
+    /// added to the tags of every file
+    pub(crate) tags: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub(crate) struct RemoteImportRequest {
+    /// http or https url of the file
+    pub(crate) url: String,
+    /// detected from the `Content-Type` of the download, then from the url, when missing
+    pub(crate) r#type: Option<MaterialType>,
+    /// defaults to the file name of the url
+    pub(crate) name: Option<String>,
+    pub(crate) desc: Option<String>,
+    pub(crate) tags: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub(crate) struct ClipRequest {
+    /// seconds into the source where the clip starts
+    pub(crate) start: f64,
+    /// seconds into the source where the clip ends
+    pub(crate) end: f64,
+    /// area of the frame to keep, the whole frame when missing
+    pub(crate) crop: Option<Crop>,
+    /// defaults to the name of the source and the range
+    pub(crate) name: Option<String>,
+    pub(crate) desc: Option<String>,
+    pub(crate) tags: Option<Vec<String>>,
+}
+
 #[derive(Bean)]
 pub(crate) struct MaterialMvc {
-    #[inject(bean)]
-    storage: &'static LocalStorage,
     #[inject(bean)]
     materials_svc: &'static MaterialsService,
+    #[inject(bean)]
+    imports: &'static ImportService,
+    #[inject(bean)]
+    remote: &'static RemoteImportService,
+    #[inject(bean)]
+    workspaces: &'static WorkspaceService,
 }
 
 #[mvc]
 #[OpenApi(prefix_path = "/api/v1")]
 impl MaterialMvc {
-    #[oai(path = "/materials:search", method = "post")]
+    #[oai(path = "/materials:search", method = "post", transform = "metered")]
     async fn search(
         &self,
+        auth: JwtAuth,
+        _limit: RateLimit<Search>,
         condition: Json<SearchCondition>,
         base_url: BaseUrl,
-        auth: JwtAuth,
-    ) -> Result<Response<PageResult<MaterialDetail>>> {
+    ) -> Result<Response<SearchResult>> {
         info!("{:?}", condition);
 
         let result = self
@@ -117,16 +180,16 @@ impl MaterialMvc {
         Ok(Response::ok(result))
     }
 
-    #[oai(path = "/materials/:id", method = "head")]
-    async fn exists(&self, id: Path<Id>, _auth: JwtAuth) -> Result<Response<bool>> {
-        if self.storage.exists(&id).await? {
+    #[oai(path = "/materials/:id", method = "head", transform = "metered")]
+    async fn exists(&self, id: Path<Id>, auth: JwtAuth) -> Result<Response<bool>> {
+        if self.materials_svc.exists(&id, &auth).await? {
             Ok(Response::ok(true))
         } else {
             Ok(Response::not_found())
         }
     }
 
-    #[oai(path = "/materials/:id", method = "get")]
+    #[oai(path = "/materials/:id", method = "get", transform = "metered")]
     async fn detail(
         &self,
         id: Path<Id>,
@@ -140,45 +203,133 @@ impl MaterialMvc {
         Ok(Response::ok(detail))
     }
 
-    #[oai(path = "/materials/:id", method = "patch")]
-    async fn update(&self, id: Path<Id>, auth: JwtAuth, request: Json<MaterialPatchRequest>) -> Result<Response<String>> {
-        self.materials_svc.update(id.0, auth.into(), request.0).await?;
+    /// Progress of a video being processed, like the stream of its upload: the latest event
+    /// first, then the following ones until `ok`, `failed` or `cancelled`
+    #[oai(path = "/materials/:id/progress", method = "get", transform = "metered")]
+    async fn progress(&self, id: Path<Id>, auth: JwtAuth) -> Result<EventStream<ReceiverStream<FormatedEvent>>> {
+        let (tx, rx) = channel(32);
+
+        self.materials_svc.attach_progress(id.0, auth.into(), tx).await?;
+
+        Ok(EventStream::new(ReceiverStream::new(rx)))
+    }
+
+    /// Stop the transcode of a video, which is left `cancelled` once stopped
+    #[oai(path = "/materials/:id/cancel", method = "post", transform = "metered")]
+    async fn cancel(&self, id: Path<Id>, auth: JwtAuth, client: ClientInfo) -> Result<Response<String>> {
+        self.materials_svc.cancel(id.0, auth.into(), &client).await?;
         Ok(Response::ok("ok".to_string()))
     }
 
-    #[oai(path = "/materials/:id", method = "delete")]
-    async fn delete(&self, id: Path<Id>, auth: JwtAuth) -> Result<Response<String>> {
-        self.materials_svc.delete(id.0, auth.into()).await?;
+    #[oai(path = "/materials/:id", method = "patch", transform = "metered")]
+    async fn update(
+        &self,
+        id: Path<Id>,
+        auth: JwtAuth,
+        request: Json<MaterialPatchRequest>,
+        client: ClientInfo,
+    ) -> Result<Response<String>> {
+        self.materials_svc.update(id.0, auth.into(), request.0, &client).await?;
         Ok(Response::ok("ok".to_string()))
     }
 
-    #[oai(path = "/materials/batch_delete", method = "post")]
-    async fn batch_delete(&self, request: Json<BatchDeleteRequest>, auth: JwtAuth) -> Result<Response<String>> {
-        self.materials_svc.batch_delete(request.0.ids, auth.into()).await?;
+    #[oai(path = "/materials/:id", method = "delete", transform = "metered")]
+    async fn delete(&self, id: Path<Id>, auth: JwtAuth, client: ClientInfo) -> Result<Response<String>> {
+        self.materials_svc.delete(id.0, auth.into(), &client).await?;
         Ok(Response::ok("ok".to_string()))
     }
 
-    /// Upload  video file
-    #[oai(path = "/materials/video", method = "post")]
+    #[oai(path = "/materials/batch_delete", method = "post", transform = "metered")]
+    async fn batch_delete(
+        &self,
+        request: Json<BatchDeleteRequest>,
+        auth: JwtAuth,
+        client: ClientInfo,
+    ) -> Result<Response<String>> {
+        self.materials_svc.batch_delete(request.0.ids, auth.into(), &client).await?;
+        Ok(Response::ok("ok".to_string()))
+    }
+
+    /// Upload  video file. The transcode may first wait for a worker, reported as `queued` events
+    /// with the `position` in the queue
+    #[oai(path = "/materials/video", method = "post", transform = "metered")]
     async fn upload(
         &self,
+        auth: JwtAuth,
+        _limit: RateLimit<Upload>,
+        permit: TranscodePermit,
         upload: UploadPayload,
+    ) -> Result<EventStream<ReceiverStream<FormatedEvent>>> {
+        let claims = auth.into_inner();
+        // checked before detaching, the task has nobody to answer with the error
+        self.workspaces.require(&claims, WorkspaceRole::Editor).await?;
+
+        let (tx, rx) = channel(32);
+
+        let _detached = spawn(self.materials_svc.upload(upload, permit.0, tx, claims));
+
+        Ok(EventStream::new(ReceiverStream::new(rx)))
+    }
+
+    /// Cut a clip of a video into a new material, reporting progress like a video upload
+    #[oai(path = "/materials/:id/clip", method = "post", transform = "metered")]
+    async fn clip(
+        &self,
+        id: Path<Id>,
+        auth: JwtAuth,
+        _limit: RateLimit<Upload>,
+        permit: TranscodePermit,
+        request: Json<ClipRequest>,
+    ) -> Result<EventStream<ReceiverStream<FormatedEvent>>> {
+        let claims = auth.into_inner();
+        let source = self.materials_svc.clip_source(&id, &request.0, &claims).await?;
+
+        let (tx, rx) = channel(32);
+
+        let _detached = spawn(self.materials_svc.clip(source, request.0, permit.0, tx, claims));
+
+        Ok(EventStream::new(ReceiverStream::new(rx)))
+    }
+
+    /// Download a video or image file from a url, reporting progress like a video upload
+    #[oai(path = "/materials/remote", method = "post", transform = "metered")]
+    async fn remote(
+        &self,
         auth: JwtAuth,
-    ) -> EventStream<ReceiverStream<FormatedEvent>> {
+        _limit: RateLimit<Upload>,
+        permit: TranscodePermit,
+        request: Json<RemoteImportRequest>,
+    ) -> Result<EventStream<ReceiverStream<FormatedEvent>>> {
         let (tx, rx) = channel(32);
 
-        let _detached = spawn(self.materials_svc.upload(upload, tx, auth.into()));
+        self.remote.start(request.0, permit.0, tx, auth.into()).await?;
 
-        EventStream::new(ReceiverStream::new(rx))
+        Ok(EventStream::new(ReceiverStream::new(rx)))
+    }
+
+    /// Import files already on the server, admin only. Streams a record per file; posting the
+    /// same source again resumes the import.
+    #[oai(path = "/materials/import", method = "post", transform = "metered")]
+    async fn import(
+        &self,
+        request: Json<ImportRequest>,
+        auth: JwtAuth,
+    ) -> Result<EventStream<ReceiverStream<ImportRecord>>> {
+        let (tx, rx) = channel(32);
+
+        self.imports.start(request.0, auth.into(), tx).await?;
+
+        Ok(EventStream::new(ReceiverStream::new(rx)))
     }
 
     /// Upload image file
-    #[oai(path = "/materials/image", method = "post")]
+    #[oai(path = "/materials/image", method = "post", transform = "metered")]
     async fn upload_image(
         &self,
-        upload: ImagesUploadPayload,
         base_url: BaseUrl,
         auth: JwtAuth,
+        _limit: RateLimit<Upload>,
+        upload: ImagesUploadPayload,
     ) -> Result<Response<Vec<MaterialImage>>> {
         let detail = self.materials_svc.upload_image(upload, base_url, auth.into()).await?;
         Ok(Response::ok(detail))
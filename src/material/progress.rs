@@ -0,0 +1,161 @@
+//! Progress of the videos being processed, shared so any client can attach to it, not only the
+//! uploader holding the stream of the upload request, and a way to cancel the processing.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use ioc::{bean, BeanSpec, InitContext};
+use tokio::sync::broadcast;
+
+use crate::{common::FormatedEvent, ffmpeg::slice::Cancel};
+
+/// Events buffered per attached client, a slower one skips progress events.
+const CAPACITY: usize = 16;
+
+struct InFlight {
+    /// workspace of the material, only its members attach
+    workspace: String,
+    latest: FormatedEvent,
+    sender: broadcast::Sender<FormatedEvent>,
+    cancel: Cancel,
+}
+
+/// Whether nothing follows `event`.
+fn is_final(event: &FormatedEvent) -> bool {
+    !matches!(event.state.as_str(), "queued" | "wip")
+}
+
+pub(crate) struct ProgressRegistry {
+    in_flight: Mutex<HashMap<String, InFlight>>,
+}
+
+#[bean]
+impl BeanSpec for ProgressRegistry {
+    type Bean = Self;
+
+    fn build(_: &mut impl InitContext) -> ioc::Result<Self::Bean> {
+        Ok(Self::new())
+    }
+}
+
+impl ProgressRegistry {
+    pub(crate) fn new() -> Self {
+        Self {
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records the event of a material in flight and passes it to attached clients. The
+    /// material is forgotten after its final event, which closes their streams.
+    pub(crate) fn report(&self, event: &FormatedEvent) {
+        let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        if is_final(event) {
+            if let Some(entry) = in_flight.remove(&event.id) {
+                let _ = entry.sender.send(event.clone());
+            }
+            return;
+        }
+
+        if let Some(entry) = in_flight.get_mut(&event.id) {
+            entry.latest = event.clone();
+            // an error only means nobody is attached
+            let _ = entry.sender.send(event.clone());
+        }
+    }
+
+    /// Puts a material of `workspace` in flight with the first event of its processing,
+    /// returning what cancels it.
+    pub(crate) fn begin(&self, event: &FormatedEvent, workspace: &str) -> Cancel {
+        let cancel = {
+            let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+            let entry = in_flight.entry(event.id.clone()).or_insert_with(|| InFlight {
+                workspace: workspace.to_string(),
+                latest: event.clone(),
+                sender: broadcast::channel(CAPACITY).0,
+                cancel: Cancel::default(),
+            });
+            entry.cancel.clone()
+        };
+        self.report(event);
+        cancel
+    }
+
+    /// Cancels the processing of a material in flight. The returned events end with the final
+    /// one, once the processing stopped.
+    pub(crate) fn cancel(&self, id: &str) -> Option<broadcast::Receiver<FormatedEvent>> {
+        let in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        in_flight.get(id).map(|entry| {
+            entry.cancel.cancel();
+            entry.sender.subscribe()
+        })
+    }
+
+    pub(crate) fn is_in_flight(&self, id: &str) -> bool {
+        let in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        in_flight.contains_key(id)
+    }
+
+    /// The latest event of a material of `workspace` in flight and the events following it.
+    pub(crate) fn attach(
+        &self,
+        id: &str,
+        workspace: &str,
+    ) -> Option<(FormatedEvent, broadcast::Receiver<FormatedEvent>)> {
+        let in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        in_flight
+            .get(id)
+            .filter(|entry| entry.workspace == workspace)
+            .map(|entry| (entry.latest.clone(), entry.sender.subscribe()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::material::{biz::VideoUploadEvent, storage::Id};
+
+    #[test]
+    fn test_attach() {
+        let registry = ProgressRegistry::new();
+        let id = Id("m".to_string());
+        assert!(registry.attach(&id, "ws").is_none());
+
+        // only begun materials are in flight
+        registry.report(&VideoUploadEvent::wip(&id, 5).into());
+        assert!(registry.attach(&id, "ws").is_none());
+
+        registry.begin(&VideoUploadEvent::wip(&id, 15).into(), "ws");
+        registry.report(&VideoUploadEvent::wip(&id, 25).into());
+        assert!(registry.attach(&id, "other").is_none());
+        let (latest, mut rx) = registry.attach(&id, "ws").unwrap();
+        assert_eq!(latest.progress, 25);
+
+        registry.report(&VideoUploadEvent::wip(&id, 26).into());
+        registry.report(&VideoUploadEvent::ok(&id).into());
+        assert_eq!(rx.try_recv().unwrap().progress, 26);
+        assert_eq!(rx.try_recv().unwrap().state, "ok");
+        assert!(matches!(rx.try_recv(), Err(broadcast::error::TryRecvError::Closed)));
+        assert!(registry.attach(&id, "ws").is_none());
+
+        // a duplicate upload is final right away and never in flight
+        registry.report(&VideoUploadEvent::existed(&id).into());
+        assert!(registry.attach(&id, "ws").is_none());
+    }
+
+    #[test]
+    fn test_cancel() {
+        let registry = ProgressRegistry::new();
+        let id = Id("m".to_string());
+        assert!(registry.cancel(&id).is_none());
+
+        let cancel = registry.begin(&VideoUploadEvent::wip(&id, 15).into(), "ws");
+        assert!(!cancel.is_cancelled());
+        assert!(registry.is_in_flight(&id));
+        let mut rx = registry.cancel(&id).unwrap();
+        assert!(cancel.is_cancelled());
+
+        registry.report(&VideoUploadEvent::cancelled(&id).into());
+        assert_eq!(rx.try_recv().unwrap().state, "cancelled");
+        assert!(matches!(rx.try_recv(), Err(broadcast::error::TryRecvError::Closed)));
+        assert!(!registry.is_in_flight(&id));
+    }
+}
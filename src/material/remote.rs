@@ -0,0 +1,285 @@
+//! Materials created from a file url: the file is downloaded next to the storage, then ingested
+//! like an import, see [`MaterialsService::import_file`].
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use ioc::{bean, Bean, BeanSpec, InitContext};
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use tokio::{
+    fs::{self, File},
+    io::AsyncWriteExt,
+    spawn,
+    sync::{mpsc::Sender, OwnedSemaphorePermit},
+};
+use tracing::{error, info};
+use url::Url;
+
+use crate::{
+    auth::jwt::Claims,
+    client::{check_user_url, user_url_client},
+    common::{AppError, FormatedEvent, Result},
+    ffmpeg::scheduler::Priority,
+    material::{
+        biz::{Imported, MaterialInfo, MaterialsService, VideoUploadEvent},
+        import::material_type,
+        mvc::RemoteImportRequest,
+        storage::Id,
+        MaterialType,
+    },
+    workspace::{biz::WorkspaceService, WorkspaceRole},
+};
+
+/// Share of the progress taken by the download, an upload reports 15 once the file is stored.
+const DOWNLOAD_PROGRESS: u64 = 15;
+
+pub(crate) struct DownloadLimits {
+    pub(crate) max_bytes: u64,
+    /// of the whole download, not only of connecting
+    pub(crate) timeout: Duration,
+}
+
+#[derive(Debug)]
+pub(crate) struct Downloaded {
+    pub(crate) size: u64,
+    pub(crate) content_type: Option<String>,
+}
+
+/// Downloads `url` into `target`, calling `progress` with the bytes so far and the expected total.
+pub(crate) async fn download(
+    client: &reqwest::Client,
+    url: &Url,
+    target: &Path,
+    limits: &DownloadLimits,
+    mut progress: impl FnMut(u64, Option<u64>),
+) -> Result<Downloaded> {
+    let mut response = client
+        .get(url.clone())
+        .header(ACCEPT, "*/*")
+        .timeout(limits.timeout)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let total = response.content_length();
+    if total.is_some_and(|total| total > limits.max_bytes) {
+        return Err(AppError::DownloadTooLarge(limits.max_bytes));
+    }
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(ToString::to_string);
+
+    let mut file = File::create(target).await?;
+    let mut size = 0;
+    while let Some(chunk) = response.chunk().await? {
+        size += chunk.len() as u64;
+        if size > limits.max_bytes {
+            return Err(AppError::DownloadTooLarge(limits.max_bytes));
+        }
+        file.write_all(&chunk).await?;
+        progress(size, total);
+    }
+    file.flush().await?;
+
+    Ok(Downloaded { size, content_type })
+}
+
+/// Material type by the `Content-Type` of the download, then by the extension of the url.
+fn detect_type(content_type: Option<&str>, url: &Url) -> Option<MaterialType> {
+    match content_type.and_then(|content_type| content_type.split('/').next()) {
+        Some("video") => Some(MaterialType::Video),
+        Some("image") => Some(MaterialType::Image),
+        _ => material_type(Path::new(url.path())),
+    }
+}
+
+/// Last segment of the url path, the name of the material when the request has none.
+fn file_name(url: &Url) -> Option<String> {
+    url.path_segments()?
+        .next_back()
+        .filter(|name| !name.is_empty())
+        .map(ToString::to_string)
+}
+
+/// Client of the downloads: it connects to public addresses only, on every redirect too,
+/// unless `remote-import.allow-private-networks`.
+pub(crate) struct RemoteClient(reqwest::Client);
+
+#[bean]
+impl BeanSpec for RemoteClient {
+    type Bean = Self;
+
+    fn build(ctx: &mut impl InitContext) -> ioc::Result<Self::Bean> {
+        let allow_private_networks = ctx.get_config::<bool>("remote-import.allow-private-networks")?;
+        Ok(Self(user_url_client(allow_private_networks, true)?))
+    }
+}
+
+#[derive(Bean)]
+pub(crate) struct RemoteImportService {
+    #[inject(bean)]
+    materials: &'static MaterialsService,
+    #[inject(bean)]
+    workspaces: &'static WorkspaceService,
+    #[inject(bean)]
+    client: &'static RemoteClient,
+    /// downloads land in a hidden directory of the storage, so they can be hard linked
+    #[inject(config = "storage.dir")]
+    storage_dir: PathBuf,
+    #[inject(config = "remote-import.max-bytes")]
+    max_bytes: u64,
+    #[inject(config = "remote-import.timeout-seconds")]
+    timeout_seconds: u64,
+    /// allow urls of loopback and private addresses, only for trusted users or tests
+    #[inject(config = "remote-import.allow-private-networks")]
+    allow_private_networks: bool,
+}
+
+impl RemoteImportService {
+    /// Checks the request, then downloads and ingests the file in the background, reporting
+    /// progress through `tx` like an upload. The transcode slot `permit` is held until done.
+    pub(crate) async fn start(
+        &'static self,
+        request: RemoteImportRequest,
+        permit: OwnedSemaphorePermit,
+        tx: Sender<FormatedEvent>,
+        claims: Claims,
+    ) -> Result<()> {
+        self.workspaces.require(&claims, WorkspaceRole::Editor).await?;
+        let url = check_user_url(&request.url, self.allow_private_networks).await?;
+
+        spawn(async move {
+            let _permit = permit;
+            let id = Id::new_uuid();
+            if let Err(e) = self.ingest(&id, &url, request, &tx, &claims).await {
+                error!("import of {url} failed: {e}");
+                let _ = tx.send(VideoUploadEvent::failed(&id).into()).await;
+            }
+        });
+        Ok(())
+    }
+
+    async fn ingest(
+        &self,
+        id: &Id,
+        url: &Url,
+        request: RemoteImportRequest,
+        tx: &Sender<FormatedEvent>,
+        claims: &Claims,
+    ) -> Result<()> {
+        let downloads = self.storage_dir.join(".downloads");
+        fs::create_dir_all(&downloads).await?;
+        let target = downloads.join(id.as_ref());
+
+        let result = self.fetch_and_import(id, url, request, &target, tx, claims).await;
+
+        // the material holds a hard link or a copy by now
+        if fs::try_exists(&target).await? {
+            fs::remove_file(&target).await?;
+        }
+        result
+    }
+
+    async fn fetch_and_import(
+        &self,
+        id: &Id,
+        url: &Url,
+        request: RemoteImportRequest,
+        target: &Path,
+        tx: &Sender<FormatedEvent>,
+        claims: &Claims,
+    ) -> Result<()> {
+        let limits = DownloadLimits {
+            max_bytes: self.max_bytes,
+            timeout: Duration::from_secs(self.timeout_seconds),
+        };
+        let progress = |size: u64, total: Option<u64>| {
+            if let Some(total) = total.filter(|total| *total > 0) {
+                let progress = (size * DOWNLOAD_PROGRESS / total) as u16;
+                // a slow listener must not slow down the download
+                let _ = tx.try_send(VideoUploadEvent::wip(id, progress).into());
+            }
+        };
+        let downloaded = download(&self.client.0, url, target, &limits, progress).await?;
+        info!("downloaded {url}, {} bytes", downloaded.size);
+
+        let kind = request
+            .r#type
+            .or_else(|| detect_type(downloaded.content_type.as_deref(), url))
+            .ok_or_else(|| AppError::InvalidRemoteUrl(format!("{url}: unknown material type")))?;
+        let info = MaterialInfo {
+            name: request.name.or_else(|| file_name(url)).unwrap_or("no_name".to_string()),
+            description: request.desc,
+            tags: request.tags.unwrap_or_default(),
+        };
+
+        let imported = self
+            .materials
+            .import_file(id.clone(), target, kind, info, true, Priority::Interactive, tx, claims)
+            .await?;
+        match imported {
+            Imported::Duplicate(existing) => tx.send(VideoUploadEvent::existed(&existing).into()).await?,
+            // videos report `ok` once transcoded
+            Imported::New(id) if kind == MaterialType::Image => tx.send(VideoUploadEvent::ok(&id).into()).await?,
+            Imported::New(_) => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::mock;
+    use poem::{get, handler, http::header, Response, Route};
+
+    #[test]
+    fn test_detect_type() -> anyhow::Result<()> {
+        let url = Url::parse("https://cdn.example.com/clips/intro.MP4?sig=1")?;
+        assert_eq!(detect_type(Some("image/png"), &url), Some(MaterialType::Image));
+        assert_eq!(detect_type(Some("application/octet-stream"), &url), Some(MaterialType::Video));
+        assert_eq!(file_name(&url).as_deref(), Some("intro.MP4"));
+        assert_eq!(file_name(&Url::parse("https://example.com/")?), None);
+        Ok(())
+    }
+
+    #[handler]
+    fn clip() -> Response {
+        Response::builder()
+            .header(header::CONTENT_TYPE, "video/mp4")
+            .body(vec![7u8; 4096])
+    }
+
+    #[tokio::test]
+    async fn test_download() -> anyhow::Result<()> {
+        let base_url = mock::serve(Route::new().at("/clip.mp4", get(clip))).await?;
+        let url = Url::parse(&format!("{base_url}/clip.mp4"))?;
+        let client = reqwest::Client::new();
+        let target = std::env::temp_dir().join(format!("phi-download-{}", Id::new_uuid()));
+
+        let limits = DownloadLimits {
+            max_bytes: 1024 * 1024,
+            timeout: Duration::from_secs(5),
+        };
+        let mut last = (0, None);
+        let downloaded = download(&client, &url, &target, &limits, |size, total| last = (size, total)).await?;
+        assert_eq!(downloaded.size, 4096);
+        assert_eq!(downloaded.content_type.as_deref(), Some("video/mp4"));
+        assert_eq!(last, (4096, Some(4096)));
+        assert_eq!(fs::read(&target).await?, vec![7u8; 4096]);
+
+        let limits = DownloadLimits {
+            max_bytes: 1024,
+            timeout: Duration::from_secs(5),
+        };
+        let result = download(&client, &url, &target, &limits, |_, _| {}).await;
+        assert!(matches!(result, Err(AppError::DownloadTooLarge(1024))));
+
+        fs::remove_file(&target).await?;
+        Ok(())
+    }
+}
@@ -1,7 +1,11 @@
 use anyhow::anyhow;
-use ioc::Bean;
+use base64ct::{Base64UrlUnpadded, Encoding};
+use chrono::Utc;
+use ioc::{bean, Bean, BeanSpec, InitContext};
 use poem_openapi::NewType;
+use ring::hmac;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
     fmt::{Display, Formatter},
     fs::{create_dir_all, remove_file as std_remove_file},
@@ -10,10 +14,11 @@ use std::{
         Path,
         PathBuf,
     },
+    time::Duration,
 };
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::{
-    fs::{remove_file, try_exists, File as TokioFile},
+    fs::{hard_link, read_dir, remove_file, try_exists, File as TokioFile},
     io::AsyncRead,
 };
 use tokio::fs::remove_dir_all;
@@ -21,6 +26,7 @@ use tracing::{error, warn};
 use url::Url;
 use uuid::Uuid;
 
+use crate::auth::state;
 use crate::common::{AppError, Result};
 use crate::util::poem::BaseUrl;
 
@@ -55,29 +61,112 @@ impl Deref for Id {
 
 pub(crate) enum SavedId {
     Existed,
-    New,
+    New {
+        /// see [`checksum`]
+        checksum: String,
+    },
+}
+
+/// Sha256 of the content, base64url encoded.
+fn encode_checksum(hasher: Sha256) -> String {
+    Base64UrlUnpadded::encode_string(&hasher.finalize())
+}
+
+/// Checksum of a file, comparable to the one of [`SavedId::New`].
+pub(crate) async fn checksum(path: &Path) -> Result<String> {
+    let mut file = TokioFile::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut cache = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut cache).await? {
+            0 => break,
+            n => hasher.update(&cache[..n]),
+        }
+    }
+    Ok(encode_checksum(hasher))
 }
 
+/// Storage of material files, scoped by the workspace owning the material.
 pub(crate) trait Storage {
-    async fn exists(&self, id: &Id) -> Result<bool>;
+    async fn exists(&self, workspace: &str, id: &Id) -> Result<bool>;
+
+    async fn delete(&self, workspace: &str, id: &Id) -> Result<()>;
+
+    async fn raw_file(&self, workspace: &str, id: &Id) -> Result<PathBuf>;
+
+    async fn assert_file(&self, workspace: &str, id: &Id, path: impl AsRef<Path>) -> Result<PathBuf>;
+
+    async fn save(&self, workspace: &str, id: &Id, source: impl AsyncRead + Unpin) -> Result<SavedId>;
+
+    fn url(&self, base_url: &BaseUrl, workspace: &str, id: &Id, path: impl AsRef<str>) -> Result<Url>;
+}
+
+/// Signs file urls, so they work without the `auth` header, e.g. as `<img src>` or in a video
+/// player fetching playlists and segments. A token opens every file of one material until it
+/// expires.
+pub(crate) struct UrlSigner {
+    key: hmac::Key,
+    expire_secs: i64,
+}
+
+#[bean]
+impl BeanSpec for UrlSigner {
+    type Bean = Self;
 
-    async fn delete(&self, id: &Id) -> Result<()>;
+    fn build(ctx: &mut impl InitContext) -> ioc::Result<Self::Bean> {
+        let secret = ctx.get_config::<String>("storage.url-secret")?;
+        let expire_secs = ctx.get_config::<i64>("storage.url-expire-seconds")?;
+        Ok(Self::new(&secret, expire_secs))
+    }
+}
+
+impl UrlSigner {
+    /// An empty `secret` is generated, urls then stop working when the server restarts.
+    pub(crate) fn new(secret: &str, expire_secs: i64) -> Self {
+        let secret = match secret.is_empty() {
+            true => state::random_token(),
+            false => secret.to_string(),
+        };
+        Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+            expire_secs,
+        }
+    }
 
-    async fn raw_file(&self, id: &Id) -> Result<PathBuf>;
+    fn message(workspace: &str, id: &str, expires: i64) -> String {
+        format!("{workspace}/{id}/{expires}")
+    }
 
-    async fn assert_file(&self, id: &Id, path: impl AsRef<Path>) -> Result<PathBuf>;
+    fn sign_until(&self, workspace: &str, id: &str, expires: i64) -> String {
+        let signature = hmac::sign(&self.key, Self::message(workspace, id, expires).as_bytes());
+        format!("{expires}.{}", Base64UrlUnpadded::encode_string(signature.as_ref()))
+    }
 
-    async fn save(&self, id: &Id, source: impl AsyncRead + Unpin) -> Result<SavedId>;
+    /// Token for the files of material `id` of `workspace`.
+    pub(crate) fn sign(&self, workspace: &str, id: &str) -> String {
+        self.sign_until(workspace, id, Utc::now().timestamp() + self.expire_secs)
+    }
 
-    fn url(&self, base_url: &BaseUrl, id: &Id, path: impl AsRef<str>) -> Result<Url>;
+    pub(crate) fn verify(&self, token: &str, workspace: &str, id: &str) -> bool {
+        let Some((expires, signature)) = token.split_once('.') else {
+            return false;
+        };
+        let (Ok(expires), Ok(signature)) = (expires.parse::<i64>(), Base64UrlUnpadded::decode_vec(signature)) else {
+            return false;
+        };
+        expires >= Utc::now().timestamp()
+            && hmac::verify(&self.key, Self::message(workspace, id, expires).as_bytes(), &signature).is_ok()
+    }
 }
 
 #[derive(Bean)]
 pub(crate) struct LocalStorage {
-    #[inject(config = "web.static.mapping.storage.dir")]
+    #[inject(config = "storage.dir")]
     dir: PathBuf,
-    #[inject(config = "web.static.mapping.storage.path")]
+    #[inject(config = "storage.path")]
     uri_path: String,
+    #[inject(bean)]
+    signer: &'static UrlSigner,
 }
 
 struct TmpFile {
@@ -91,13 +180,16 @@ impl TmpFile {
         Ok(Self { target, path })
     }
 
-    async fn copy_from(mut self, mut source: impl AsyncRead + Unpin) -> Result<()> {
+    /// Copies `source` into the file, returning its checksum.
+    async fn copy_from(mut self, mut source: impl AsyncRead + Unpin) -> Result<String> {
         let mut cache = [0; 512];
+        let mut hasher = Sha256::new();
 
         loop {
             match source.read(&mut cache).await? {
                 0 => break,
                 n => {
+                    hasher.update(&cache[..n]);
                     self.target.write_all(&cache[..n]).await?;
                 }
             };
@@ -105,7 +197,7 @@ impl TmpFile {
 
         // skip file clean in `Drop::drop`
         std::mem::forget(self);
-        Ok(())
+        Ok(encode_checksum(hasher))
     }
 }
 
@@ -118,20 +210,115 @@ impl Drop for TmpFile {
     }
 }
 
+/// Directory holding the files of one material.
+pub(crate) struct MaterialDir {
+    pub(crate) id: Id,
+    pub(crate) path: PathBuf,
+    /// time since the directory was last modified
+    pub(crate) age: Duration,
+}
+
 impl LocalStorage {
-    fn path(&self, id: &Id) -> PathBuf {
-        self.dir.join(&id.0)
+    /// Whether `token` of a url from [`Storage::url`] opens the files of material `id`.
+    pub(crate) fn verify_token(&self, token: &str, workspace: &str, id: &Id) -> bool {
+        self.signer.verify(token, workspace, id.as_ref())
+    }
+
+    /// Every material directory, legacy ones directly under the root and the ones of workspaces.
+    pub(crate) async fn material_dirs(&self) -> Result<Vec<MaterialDir>> {
+        let mut dirs = Vec::new();
+        let mut roots = vec![(self.dir.clone(), true)];
+        while let Some((root, top)) = roots.pop() {
+            let mut entries = read_dir(&root).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                let name = entry.file_name().to_string_lossy().to_string();
+                if name.starts_with('.') || !entry.file_type().await?.is_dir() {
+                    continue;
+                }
+                // a workspace directory holds material directories, a material one its `raw`
+                if top && !try_exists(path.join("raw")).await? {
+                    roots.push((path, false));
+                    continue;
+                }
+                let age = entry
+                    .metadata()
+                    .await?
+                    .modified()?
+                    .elapsed()
+                    .unwrap_or_default();
+                dirs.push(MaterialDir { id: Id(name), path, age });
+            }
+        }
+        Ok(dirs)
+    }
+
+    /// Stores `source` by hard linking it instead of copying, falling back to a copy when it is on
+    /// another file system. `checksum` of `source` is computed by the caller to find duplicates.
+    pub(crate) async fn link(&self, workspace: &str, id: &Id, source: &Path, checksum: String) -> Result<SavedId> {
+        let mut target = self.path(workspace, id);
+        target.push("raw");
+
+        if try_exists(&target).await? {
+            return Ok(SavedId::Existed);
+        }
+        if let Some(parent) = target.parent() {
+            create_dir_all(parent)?;
+        }
+        match hard_link(source, &target).await {
+            Ok(()) => Ok(SavedId::New { checksum }),
+            Err(e) => {
+                warn!("hard link {} failed, copying instead: {e}", source.display());
+                self.save(workspace, id, TokioFile::open(source).await?).await
+            }
+        }
+    }
+
+    /// Where a raw file produced on the server goes, e.g. a clip cut from another material.
+    pub(crate) fn new_raw(&self, workspace: &str, id: &Id) -> Result<PathBuf> {
+        let dir = self.path(workspace, id);
+        create_dir_all(&dir)?;
+        Ok(dir.join("raw"))
+    }
+
+    /// Removes whatever was generated next to the raw file of a material, e.g. partial slices.
+    pub(crate) async fn clean_outputs(&self, workspace: &str, id: &Id) -> Result<()> {
+        let mut entries = read_dir(self.path(workspace, id)).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_name() == "raw" {
+                continue;
+            }
+            if entry.file_type().await?.is_dir() {
+                remove_dir_all(entry.path()).await?;
+            } else {
+                remove_file(entry.path()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// materials saved before workspaces existed live directly under the storage root
+    fn is_legacy(&self, id: &Id) -> bool {
+        self.dir.join(&id.0).is_dir()
+    }
+
+    fn path(&self, workspace: &str, id: &Id) -> PathBuf {
+        if self.is_legacy(id) {
+            self.dir.join(&id.0)
+        } else {
+            self.dir.join(workspace).join(&id.0)
+        }
     }
 }
 
 impl Storage for LocalStorage {
-    async fn exists(&self, id: &Id) -> Result<bool> {
-        let path = self.path(id);
+    async fn exists(&self, workspace: &str, id: &Id) -> Result<bool> {
+        let path = self.path(workspace, id);
         Ok(try_exists(path).await?)
     }
 
-    async fn delete(&self, id: &Id) -> Result<()> {
-        let path = self.path(id);
+    async fn delete(&self, workspace: &str, id: &Id) -> Result<()> {
+        let path = self.path(workspace, id);
         if try_exists(&path).await? {
             if path.is_dir() {
                 Ok(remove_dir_all(&path).await?)
@@ -144,28 +331,28 @@ impl Storage for LocalStorage {
         }
     }
 
-    async fn raw_file(&self, id: &Id) -> Result<PathBuf> {
-        if !self.exists(id).await? {
+    async fn raw_file(&self, workspace: &str, id: &Id) -> Result<PathBuf> {
+        if !self.exists(workspace, id).await? {
             return Err(AppError::MaterialNotFound(id.to_string()));
         }
 
-        let mut buf = self.path(id);
+        let mut buf = self.path(workspace, id);
         buf.push("raw");
         Ok(buf)
     }
 
-    async fn assert_file(&self, id: &Id, path: impl AsRef<Path>) -> Result<PathBuf> {
-        if !self.exists(id).await? {
+    async fn assert_file(&self, workspace: &str, id: &Id, path: impl AsRef<Path>) -> Result<PathBuf> {
+        if !self.exists(workspace, id).await? {
             return Err(AppError::MaterialNotFound(id.to_string()));
         }
 
-        let mut buf = self.path(id);
+        let mut buf = self.path(workspace, id);
         buf.push(path);
         Ok(buf)
     }
 
-    async fn save(&self, id: &Id, source: impl AsyncRead + Unpin) -> Result<SavedId> {
-        let mut target = self.path(&id);
+    async fn save(&self, workspace: &str, id: &Id, source: impl AsyncRead + Unpin) -> Result<SavedId> {
+        let mut target = self.path(workspace, id);
         target.push("raw");
 
         if try_exists(&target).await? {
@@ -174,20 +361,44 @@ impl Storage for LocalStorage {
             if let Some(parent) = target.parent() {
                 create_dir_all(parent)?;
             }
-            TmpFile::new(target)
+            let checksum = TmpFile::new(target)
                 .await?
                 .copy_from(source)
                 .await?;
-            Ok(SavedId::New)
+            Ok(SavedId::New { checksum })
         }
     }
 
-    fn url(&self, base_url: &BaseUrl, id: &Id, path: impl AsRef<str>) -> Result<Url> {
+    /// Served by [`crate::material::file::FileMvc`], which finds legacy materials by their id.
+    fn url(&self, base_url: &BaseUrl, workspace: &str, id: &Id, path: impl AsRef<str>) -> Result<Url> {
         let mut url = base_url.join(self.uri_path.as_str())?;
-        url.path_segments_mut()
-            .map_err(|_| AppError::Other(anyhow!("invalid base url:{}", self.uri_path)))?
-            .push(id.as_ref())
-            .push(path.as_ref());
+        {
+            let mut segments = url
+                .path_segments_mut()
+                .map_err(|_| AppError::Other(anyhow!("invalid base url:{}", self.uri_path)))?;
+            segments.push(workspace).push(id.as_ref());
+            segments.extend(path.as_ref().split('/'));
+        }
+        url.query_pairs_mut()
+            .append_pair("token", &self.signer.sign(workspace, id.as_ref()));
         Ok(url)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_url_signer() {
+        let signer = UrlSigner::new("secret", 60);
+        let token = signer.sign("ws", "id");
+        assert!(signer.verify(&token, "ws", "id"));
+        assert!(!signer.verify(&token, "ws", "other"));
+        assert!(!signer.verify(&token, "other", "id"));
+        assert!(!UrlSigner::new("another", 60).verify(&token, "ws", "id"));
+        let expired = signer.sign_until("ws", "id", Utc::now().timestamp() - 1);
+        assert!(!signer.verify(&expired, "ws", "id"));
+        assert!(!signer.verify("garbage", "ws", "id"));
+    }
+}
@@ -0,0 +1,313 @@
+use std::time::{Duration, Instant};
+
+use ioc::{bean, mvc, Bean, BeanSpec, InitContext};
+use poem::{route::PathPattern, Endpoint, EndpointExt, IntoResponse, Request};
+use poem_openapi::payload::PlainText;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use sqlx::SqlitePool;
+
+use crate::{
+    auth::state,
+    common::{AppError, Result},
+    db::Db,
+    ffmpeg::scheduler::Scheduler,
+    material::MaterialType,
+};
+
+/// Prometheus metrics of the server, exported at `/metrics`.
+pub(crate) struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    upload_bytes: IntCounterVec,
+    transcodes_active: IntGauge,
+    transcodes_queued: IntGauge,
+    transcode_duration: Histogram,
+    transcode_failures: IntCounter,
+    transcode_cancellations: IntCounter,
+    db_connections: IntGaugeVec,
+    storage_bytes: IntGaugeVec,
+    materials: IntGaugeVec,
+}
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(
+    registry: &Registry,
+    metric: prometheus::Result<T>,
+) -> prometheus::Result<T> {
+    let metric = metric?;
+    registry.register(Box::new(metric.clone()))?;
+    Ok(metric)
+}
+
+impl Metrics {
+    pub(crate) fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("phi".to_string()), None)?;
+        let transcode_buckets = vec![1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0];
+
+        Ok(Self {
+            http_requests: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("http_requests_total", "http requests by route and status"),
+                    &["method", "route", "status"],
+                ),
+            )?,
+            http_duration: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new("http_request_duration_seconds", "http request latency"),
+                    &["method", "route"],
+                ),
+            )?,
+            upload_bytes: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("upload_bytes_total", "bytes of uploaded material files"),
+                    &["type"],
+                ),
+            )?,
+            transcodes_active: register(
+                &registry,
+                IntGauge::new("ffmpeg_jobs_active", "transcodes running"),
+            )?,
+            transcodes_queued: register(
+                &registry,
+                IntGauge::new(
+                    "ffmpeg_jobs_queued",
+                    "transcodes waiting for a worker",
+                ),
+            )?,
+            // a transcode produces every rendition in one ffmpeg run, so it is measured as a whole
+            transcode_duration: register(
+                &registry,
+                Histogram::with_opts(
+                    HistogramOpts::new("transcode_duration_seconds", "successful transcode duration")
+                        .buckets(transcode_buckets),
+                ),
+            )?,
+            transcode_failures: register(
+                &registry,
+                IntCounter::new("transcode_failures_total", "failed transcodes"),
+            )?,
+            transcode_cancellations: register(
+                &registry,
+                IntCounter::new("transcode_cancellations_total", "transcodes cancelled by users"),
+            )?,
+            db_connections: register(
+                &registry,
+                IntGaugeVec::new(
+                    Opts::new("db_pool_connections", "sqlite pool connections"),
+                    &["state"],
+                ),
+            )?,
+            storage_bytes: register(
+                &registry,
+                IntGaugeVec::new(
+                    Opts::new("storage_bytes", "size of stored raw material files"),
+                    &["type"],
+                ),
+            )?,
+            materials: register(
+                &registry,
+                IntGaugeVec::new(Opts::new("materials", "stored materials"), &["type"]),
+            )?,
+            registry,
+        })
+    }
+
+    fn observe_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        self.http_requests
+            .with_label_values(&[method, route, &status.to_string()])
+            .inc();
+        self.http_duration
+            .with_label_values(&[method, route])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub(crate) fn uploaded(&self, kind: MaterialType, bytes: usize) {
+        self.upload_bytes
+            .with_label_values(&[&kind.name().to_lowercase()])
+            .inc_by(bytes as u64);
+    }
+
+    /// Counts a running transcode until the returned guard is dropped.
+    pub(crate) fn transcode_started(&self) -> TranscodeGuard<'_> {
+        self.transcodes_active.inc();
+        TranscodeGuard {
+            metrics: self,
+            started: Instant::now(),
+        }
+    }
+}
+
+/// A running transcode, see [`Metrics::transcode_started`].
+pub(crate) struct TranscodeGuard<'a> {
+    metrics: &'a Metrics,
+    started: Instant,
+}
+
+impl TranscodeGuard<'_> {
+    /// Records the outcome, a cancelled transcode is neither a success nor a failure.
+    pub(crate) fn finish<T>(self, result: &Result<T>) {
+        match result {
+            Ok(_) => self
+                .metrics
+                .transcode_duration
+                .observe(self.started.elapsed().as_secs_f64()),
+            Err(AppError::TranscodeCancelled) => self.metrics.transcode_cancellations.inc(),
+            Err(_) => self.metrics.transcode_failures.inc(),
+        }
+    }
+}
+
+impl Drop for TranscodeGuard<'_> {
+    fn drop(&mut self) {
+        self.metrics.transcodes_active.dec();
+    }
+}
+
+#[bean]
+impl BeanSpec for Metrics {
+    type Bean = Self;
+
+    fn build(_: &mut impl InitContext) -> ioc::Result<Self::Bean> {
+        Metrics::new().map_err(|err| ioc::IocError::Other(err.into()))
+    }
+}
+
+/// Operation transform recording request counts and latencies, labelled by the route pattern
+/// so ids in paths don't blow up cardinality. Use as `#[oai(..., transform = "metered")]`.
+pub(crate) fn metered<E: Endpoint + 'static>(ep: E) -> impl Endpoint {
+    ep.around(|ep, req: Request| async move {
+        let route = req
+            .data::<PathPattern>()
+            .map(|pattern| pattern.0.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        let method = req.method().to_string();
+        let started = Instant::now();
+
+        let result = ep.call(req).await.map(IntoResponse::into_response);
+
+        let status = match &result {
+            Ok(response) => response.status(),
+            Err(err) => err.status(),
+        };
+        Metrics::get().observe_request(&method, &route, status.as_u16(), started.elapsed());
+        result
+    })
+}
+
+#[derive(sqlx::FromRow)]
+struct StorageRow {
+    #[sqlx(rename = "type")]
+    kind: i64,
+    count: i64,
+    bytes: i64,
+}
+
+#[derive(Bean)]
+pub(crate) struct MetricsMvc {
+    #[inject(bean)]
+    metrics: &'static Metrics,
+    #[inject(bean = Db)]
+    db: &'static SqlitePool,
+    #[inject(bean)]
+    scheduler: &'static Scheduler,
+    /// bearer token required to scrape, open when empty
+    #[inject(config = "metrics.token")]
+    token: String,
+}
+
+impl MetricsMvc {
+    fn authorize(&self, req: &Request) -> Result<()> {
+        if self.token.is_empty() {
+            return Ok(());
+        }
+        let presented = req
+            .header("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        // compare digests so the check doesn't leak the token through timing
+        if state::token_hash(presented) == state::token_hash(&self.token) {
+            Ok(())
+        } else {
+            Err(AppError::InvalidMetricsToken)
+        }
+    }
+
+    /// Gauges sampled at scrape time.
+    async fn sample(&self) -> Result<()> {
+        let metrics = self.metrics;
+
+        let size = self.db.size() as i64;
+        let idle = self.db.num_idle() as i64;
+        let max = self.db.options().get_max_connections() as i64;
+        metrics.db_connections.with_label_values(&["active"]).set(size - idle);
+        metrics.db_connections.with_label_values(&["idle"]).set(idle);
+        metrics.db_connections.with_label_values(&["max"]).set(max);
+
+        metrics.transcodes_queued.set(self.scheduler.queued() as i64);
+
+        let rows: Vec<StorageRow> = sqlx::query_as(
+            "SELECT type, COUNT(*) AS count, COALESCE(SUM(size), 0) AS bytes FROM materials GROUP BY type",
+        )
+            .fetch_all(self.db)
+            .await?;
+        for row in rows {
+            let Some(kind) = MaterialType::from_value(row.kind as u16) else {
+                continue;
+            };
+            let kind = kind.name().to_lowercase();
+            metrics.materials.with_label_values(&[&kind]).set(row.count);
+            metrics.storage_bytes.with_label_values(&[&kind]).set(row.bytes);
+        }
+        Ok(())
+    }
+}
+
+#[mvc]
+impl MetricsMvc {
+    /// Prometheus text exposition, requires `Authorization: Bearer <metrics.token>` when set
+    #[oai(path = "/metrics", method = "get", hidden)]
+    async fn metrics(&self, req: &Request) -> Result<PlainText<String>> {
+        self.authorize(req)?;
+        self.sample().await?;
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.metrics.registry.gather(), &mut buffer)
+            .map_err(anyhow::Error::from)?;
+        Ok(PlainText(String::from_utf8_lossy(&buffer).into_owned()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_transcode_guard() {
+        let metrics = Metrics::new().unwrap();
+
+        let guard = metrics.transcode_started();
+        assert_eq!(metrics.transcodes_active.get(), 1);
+        guard.finish::<()>(&Err(AppError::DbError("boom".to_string())));
+        assert_eq!(metrics.transcodes_active.get(), 0);
+        assert_eq!(metrics.transcode_failures.get(), 1);
+
+        metrics.transcode_started().finish::<()>(&Err(AppError::TranscodeCancelled));
+        assert_eq!(metrics.transcode_failures.get(), 1);
+        assert_eq!(metrics.transcode_cancellations.get(), 1);
+
+        metrics.observe_request("GET", "/api/v1/materials/:id", 404, Duration::from_millis(3));
+        let text = {
+            let mut buffer = Vec::new();
+            TextEncoder::new().encode(&metrics.registry.gather(), &mut buffer).unwrap();
+            String::from_utf8(buffer).unwrap()
+        };
+        assert!(text.contains(r#"phi_http_requests_total{method="GET",route="/api/v1/materials/:id",status="404"} 1"#));
+    }
+}
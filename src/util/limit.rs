@@ -0,0 +1,204 @@
+use crate::{
+    auth::{apikey::API_KEY_PREFIX, jwt::JwtService, state},
+    common::AppError,
+    ffmpeg::common::FFmpegUtils,
+};
+use ioc::{bean, BeanSpec, InitContext};
+use poem::{FromRequest, Request, RequestBody};
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::sync::OwnedSemaphorePermit;
+
+/// Entries idle for longer than this are dropped once the table grows past `SWEEP_SIZE`.
+const IDLE: Duration = Duration::from_secs(600);
+const SWEEP_SIZE: usize = 10_000;
+
+/// A named group of endpoints sharing a limit, configured at `rate-limit.<NAME>`.
+pub(crate) trait Bucket: Send {
+    const NAME: &'static str;
+}
+
+pub(crate) enum Login {}
+
+pub(crate) enum Search {}
+
+pub(crate) enum Upload {}
+
+impl Bucket for Login {
+    const NAME: &'static str = "login";
+}
+
+impl Bucket for Search {
+    const NAME: &'static str = "search";
+}
+
+impl Bucket for Upload {
+    const NAME: &'static str = "upload";
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Limit {
+    /// tokens added per second
+    rate: f64,
+    burst: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    at: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: Limit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst,
+            at: now,
+        }
+    }
+
+    /// Takes a token, or returns the seconds until one is available.
+    fn take(&mut self, limit: Limit, now: Instant) -> Result<(), u64> {
+        let elapsed = now.saturating_duration_since(self.at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst);
+        self.at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - self.tokens) / limit.rate).ceil() as u64)
+        }
+    }
+}
+
+/// Token bucket rate limiter keyed by client ip and, when the request carries credentials,
+/// by the caller as well.
+pub(crate) struct RateLimiter {
+    limits: HashMap<&'static str, Limit>,
+    trust_forwarded: bool,
+    buckets: Mutex<HashMap<(&'static str, String), TokenBucket>>,
+}
+
+#[bean]
+impl BeanSpec for RateLimiter {
+    type Bean = Self;
+
+    fn build(ctx: &mut impl InitContext) -> ioc::Result<Self::Bean> {
+        let mut limits = HashMap::new();
+        for name in [Login::NAME, Search::NAME, Upload::NAME] {
+            let per_minute = ctx.get_config::<f64>(&format!("rate-limit.{name}.per-minute"))?;
+            let burst = ctx.get_config::<f64>(&format!("rate-limit.{name}.burst"))?;
+            limits.insert(
+                name,
+                Limit {
+                    rate: per_minute / 60.0,
+                    burst: burst.max(1.0),
+                },
+            );
+        }
+        let trust_forwarded = ctx.get_config::<bool>("rate-limit.trust-forwarded")?;
+
+        Ok(Self {
+            limits,
+            trust_forwarded,
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+impl RateLimiter {
+    /// Address of the client, from `X-Forwarded-For` when `rate-limit.trust-forwarded` is set.
+    pub(crate) fn client_ip(&self, req: &Request) -> Option<IpAddr> {
+        let forwarded = self
+            .trust_forwarded
+            .then(|| req.header("X-Forwarded-For"))
+            .flatten()
+            .and_then(|value| value.split(',').next())
+            .and_then(|ip| ip.trim().parse().ok());
+
+        forwarded.or_else(|| req.remote_addr().as_socket_addr().map(|addr| addr.ip()))
+    }
+
+    /// The caller as named by its credentials, the user id for a valid jwt.
+    fn caller(req: &Request) -> Option<String> {
+        let credential = req.header("auth")?;
+        if credential.starts_with(API_KEY_PREFIX) {
+            return Some(format!("key:{}", state::token_hash(credential)));
+        }
+        let claims = JwtService::get().decode(credential).ok()?;
+        Some(format!("user:{}", claims.id))
+    }
+
+    pub(crate) fn check(&self, bucket: &'static str, req: &Request) -> Result<(), AppError> {
+        let Some(limit) = self.limits.get(bucket).copied() else {
+            return Ok(());
+        };
+
+        let keys = self
+            .client_ip(req)
+            .map(|ip| format!("ip:{ip}"))
+            .into_iter()
+            .chain(Self::caller(req));
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() > SWEEP_SIZE {
+            buckets.retain(|_, state| now.saturating_duration_since(state.at) < IDLE);
+        }
+
+        for key in keys {
+            buckets
+                .entry((bucket, key))
+                .or_insert_with(|| TokenBucket::new(limit, now))
+                .take(limit, now)
+                .map_err(AppError::TooManyRequests)?;
+        }
+        Ok(())
+    }
+}
+
+/// Extractor rejecting the request with 429 once the caller exhausts bucket `B`.
+pub(crate) struct RateLimit<B: Bucket>(PhantomData<B>);
+
+impl<'a, B: Bucket> FromRequest<'a> for RateLimit<B> {
+    async fn from_request(req: &'a Request, _: &mut RequestBody) -> poem::Result<Self> {
+        RateLimiter::get().check(B::NAME, req)?;
+        Ok(Self(PhantomData))
+    }
+}
+
+/// Extractor reserving a transcode slot, see [`FFmpegUtils::reserve_transcode`]. Declared
+/// before the payload, a busy server answers 429 before the upload is read.
+pub(crate) struct TranscodePermit(pub(crate) OwnedSemaphorePermit);
+
+impl<'a> FromRequest<'a> for TranscodePermit {
+    async fn from_request(_: &'a Request, _: &mut RequestBody) -> poem::Result<Self> {
+        Ok(Self(FFmpegUtils::get().reserve_transcode()?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let limit = Limit { rate: 1.0, burst: 2.0 };
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(limit, now);
+
+        assert_eq!(bucket.take(limit, now), Ok(()));
+        assert_eq!(bucket.take(limit, now), Ok(()));
+        assert_eq!(bucket.take(limit, now), Err(1));
+
+        let later = now + Duration::from_millis(1500);
+        assert_eq!(bucket.take(limit, later), Ok(()));
+        assert_eq!(bucket.take(limit, later), Err(1));
+    }
+}
@@ -0,0 +1,22 @@
+use poem::{
+    listener::{Acceptor, Listener, TcpListener},
+    Endpoint, Server,
+};
+
+/// Serves `app` on a random local port, standing in for a remote http service in tests.
+/// Returns the base url like `http://127.0.0.1:12345`.
+pub(crate) async fn serve<E>(app: E) -> anyhow::Result<String>
+where
+    E: Endpoint + 'static,
+{
+    let acceptor = TcpListener::bind("127.0.0.1:0").into_acceptor().await?;
+    let addr = acceptor
+        .local_addr()
+        .first()
+        .and_then(|addr| addr.0.as_socket_addr().cloned())
+        .ok_or_else(|| anyhow::anyhow!("mock server has no socket address"))?;
+
+    tokio::spawn(Server::new_with_acceptor(acceptor).run(app));
+
+    Ok(format!("http://{addr}"))
+}
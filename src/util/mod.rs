@@ -1 +1,4 @@
+#[cfg(test)]
+pub(crate) mod mock;
+pub(crate) mod limit;
 pub(crate) mod poem;
@@ -1,5 +1,6 @@
-use crate::common;
+use crate::{common, util::limit::RateLimiter};
 use http::uri::Scheme;
+use ioc::BeanSpec;
 use poem::{FromRequest, Request, RequestBody};
 use std::str::FromStr;
 use tracing::log::warn;
@@ -41,3 +42,25 @@ impl BaseUrl {
         Ok(Url::parse(&self.base_url)?.join(path)?)
     }
 }
+
+/// Who is on the other end of a request, recorded in the audit log.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ClientInfo {
+    pub(crate) ip: Option<String>,
+    pub(crate) user_agent: Option<String>,
+}
+
+impl ClientInfo {
+    pub(crate) fn of(req: &Request) -> Self {
+        Self {
+            ip: RateLimiter::get().client_ip(req).map(|ip| ip.to_string()),
+            user_agent: req.header("User-Agent").map(ToString::to_string),
+        }
+    }
+}
+
+impl<'a> FromRequest<'a> for ClientInfo {
+    async fn from_request(req: &'a Request, _: &mut RequestBody) -> poem::Result<Self> {
+        Ok(Self::of(req))
+    }
+}
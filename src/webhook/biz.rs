@@ -0,0 +1,718 @@
+use crate::{
+    audit::{
+        biz::{AuditEntry, AuditService},
+        AuditAction,
+    },
+    auth::{jwt::Claims, state},
+    client::{check_user_url, user_url_client},
+    common::{AppError, Page, PageResult, Result},
+    db::{self, Db},
+    util::poem::ClientInfo,
+    webhook::{MaterialEvent, WebhookEvent},
+    workspace::{biz::WorkspaceService, WorkspaceRole},
+};
+use chrono::{NaiveDateTime, Utc};
+use ioc::{bean, Bean, BeanSpec, InitContext};
+use poem_openapi::{Enum, Object};
+use reqwest::header::CONTENT_TYPE;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+use std::{sync::Arc, thread, time::Duration};
+use tokio::{runtime::Builder, spawn, sync::Notify, task::JoinHandle, time::timeout};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Due deliveries are also looked for this often, retries are scheduled without waking the loop.
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+/// Deliveries claimed per round.
+const BATCH_SIZE: i64 = 50;
+/// Longest wait between two attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(6 * 3600);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Enum)]
+#[serde(rename_all = "lowercase")]
+#[oai(rename_all = "lowercase")]
+pub(crate) enum DeliveryStatus {
+    Pending,
+    Delivered,
+    /// gave up after `webhook.max-attempts`
+    Failed,
+}
+
+impl DeliveryStatus {
+    fn value(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+
+    fn from_value(value: &str) -> Self {
+        match value {
+            "delivered" => DeliveryStatus::Delivered,
+            "failed" => DeliveryStatus::Failed,
+            _ => DeliveryStatus::Pending,
+        }
+    }
+}
+
+/// Body posted to the webhook url.
+#[derive(Serialize, Deserialize, Debug)]
+struct Payload<'a> {
+    /// the same for every delivery of the event, including redeliveries
+    id: String,
+    event: WebhookEvent,
+    created_at: NaiveDateTime,
+    data: &'a MaterialEvent,
+}
+
+/// Hex encoded HMAC-SHA256 of `message`.
+fn sign(secret: &str, message: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    hmac::sign(&key, message)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Value of the `X-Phi-Signature` header: the timestamp is signed along with the body, so a
+/// receiver rejecting old timestamps is safe from replays.
+fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    format!("v1={}", sign(secret, format!("{timestamp}.{body}").as_bytes()))
+}
+
+/// Wait before the attempt following `attempts` failed ones, doubling from `base`.
+fn backoff(base: Duration, attempts: u32) -> Duration {
+    base.saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+#[derive(Serialize, Deserialize, Debug, Object)]
+pub(crate) struct WebhookInfo {
+    id: String,
+    workspace_id: String,
+    url: String,
+    events: Vec<WebhookEvent>,
+    created_by: String,
+    created_at: NaiveDateTime,
+}
+
+#[derive(sqlx::FromRow)]
+struct WebhookRow {
+    id: String,
+    workspace_id: String,
+    url: String,
+    events: String,
+    created_by: String,
+    created_at: NaiveDateTime,
+}
+
+impl From<WebhookRow> for WebhookInfo {
+    fn from(row: WebhookRow) -> Self {
+        Self {
+            id: row.id,
+            workspace_id: row.workspace_id,
+            url: row.url,
+            events: WebhookEvent::split(&row.events),
+            created_by: row.created_by,
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Object)]
+pub(crate) struct NewWebhook {
+    #[oai(flatten)]
+    #[serde(flatten)]
+    info: WebhookInfo,
+    /// key of the payload signatures, shown only once
+    secret: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub(crate) struct WebhookRequest {
+    url: String,
+    /// all events when empty
+    #[serde(default)]
+    #[oai(default)]
+    events: Vec<WebhookEvent>,
+    /// generated when absent
+    secret: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Object)]
+pub(crate) struct WebhookDelivery {
+    id: String,
+    webhook_id: String,
+    event: String,
+    payload: Value,
+    status: DeliveryStatus,
+    attempts: u32,
+    /// of the last attempt
+    response_status: Option<u16>,
+    error: Option<String>,
+    next_attempt_at: Option<NaiveDateTime>,
+    created_at: NaiveDateTime,
+    delivered_at: Option<NaiveDateTime>,
+}
+
+#[derive(sqlx::FromRow)]
+struct DeliveryRow {
+    id: String,
+    webhook_id: String,
+    event: String,
+    payload: String,
+    status: String,
+    attempts: i64,
+    response_status: Option<i64>,
+    error: Option<String>,
+    next_attempt_at: NaiveDateTime,
+    created_at: NaiveDateTime,
+    delivered_at: Option<NaiveDateTime>,
+}
+
+impl From<DeliveryRow> for WebhookDelivery {
+    fn from(row: DeliveryRow) -> Self {
+        let status = DeliveryStatus::from_value(&row.status);
+        Self {
+            id: row.id,
+            webhook_id: row.webhook_id,
+            event: row.event,
+            payload: serde_json::from_str(&row.payload).unwrap_or(Value::String(row.payload)),
+            status,
+            attempts: row.attempts as u32,
+            response_status: row.response_status.map(|status| status as u16),
+            error: row.error,
+            next_attempt_at: (status == DeliveryStatus::Pending).then_some(row.next_attempt_at),
+            created_at: row.created_at,
+            delivered_at: row.delivered_at,
+        }
+    }
+}
+
+/// A pending delivery together with where it goes.
+#[derive(sqlx::FromRow)]
+struct DueDelivery {
+    id: String,
+    event: String,
+    payload: String,
+    attempts: i64,
+    next_attempt_at: NaiveDateTime,
+    url: String,
+    secret: String,
+}
+
+/// Connections of the pool the deliveries have for themselves.
+const POOL_SIZE: u32 = 2;
+
+/// Sends pending deliveries. In the server a thread of its own runs the loop from startup on,
+/// so deliveries left over by a restart or a command go out without waiting for a new event.
+/// A command sends what it queued with [`Deliveries::drain`] before exiting.
+#[derive(Clone)]
+pub(crate) struct Deliveries {
+    db: SqlitePool,
+    /// connects to public addresses only, unless `webhook.allow-private-networks`, and never
+    /// follows redirects
+    client: reqwest::Client,
+    notify: Arc<Notify>,
+    max_attempts: u32,
+    /// wait after the first failed attempt, doubled after each further one
+    backoff_seconds: u64,
+    timeout_seconds: u64,
+    /// allow urls of loopback and private addresses, only for trusted admins or tests
+    allow_private_networks: bool,
+}
+
+#[bean]
+impl BeanSpec for Deliveries {
+    type Bean = Self;
+
+    fn build(ctx: &mut impl InitContext) -> ioc::Result<Self::Bean> {
+        let database_url = ctx.get_config::<String>("db.url")?;
+        let allow_private_networks = ctx.get_config::<bool>("webhook.allow-private-networks")?;
+
+        // beans are built before the server's runtime exists, so the loop gets a runtime of its
+        // own, and a pool too as the one of `Db` isn't reachable from here
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        let db = runtime.block_on(db::init(&database_url, POOL_SIZE))?;
+        let deliveries = Self::new(
+            db,
+            user_url_client(allow_private_networks, false)?,
+            ctx.get_config::<u32>("webhook.max-attempts")?,
+            ctx.get_config::<u64>("webhook.backoff-seconds")?,
+            ctx.get_config::<u64>("webhook.timeout-seconds")?,
+            allow_private_networks,
+        );
+
+        let looping = deliveries.clone();
+        thread::Builder::new()
+            .name("webhook-deliveries".to_string())
+            .spawn(move || runtime.block_on(looping.run()))?;
+        Ok(deliveries)
+    }
+}
+
+#[derive(Bean)]
+pub(crate) struct WebhookService {
+    #[inject(bean = Db)]
+    db: &'static SqlitePool,
+    #[inject(bean)]
+    deliveries: &'static Deliveries,
+    #[inject(bean)]
+    workspaces: &'static WorkspaceService,
+    #[inject(bean)]
+    audit: &'static AuditService,
+}
+
+impl WebhookService {
+    /// Subscribes a url to events of the caller's active workspace, workspace admins only.
+    pub(crate) async fn create(
+        &self,
+        request: WebhookRequest,
+        claims: &Claims,
+        client: &ClientInfo,
+    ) -> Result<NewWebhook> {
+        self.workspaces.require(claims, WorkspaceRole::Admin).await?;
+
+        let url = check_user_url(&request.url, self.deliveries.allow_private_networks).await?;
+
+        let events = match request.events.is_empty() {
+            true => WebhookEvent::ALL.to_vec(),
+            false => request.events,
+        };
+        let secret = request.secret.unwrap_or_else(state::random_token);
+        let info = WebhookInfo {
+            id: Uuid::new_v4().to_string(),
+            workspace_id: claims.workspace.clone(),
+            url: url.to_string(),
+            events,
+            created_by: claims.id.clone(),
+            created_at: Utc::now().naive_utc(),
+        };
+        let events = WebhookEvent::join(&info.events);
+
+        sqlx::query!(
+            r#"
+            INSERT INTO webhooks (id, workspace_id, url, secret, events, created_by, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+            info.id,
+            info.workspace_id,
+            info.url,
+            secret,
+            events,
+            info.created_by,
+            info.created_at
+        )
+            .execute(self.db)
+            .await?;
+
+        let entry = AuditEntry::by(claims, AuditAction::WebhookCreate, info.id.clone())
+            .details(json!({ "workspace": info.workspace_id, "url": info.url, "events": events }));
+        self.audit.record(entry, client).await;
+
+        Ok(NewWebhook { info, secret })
+    }
+
+    pub(crate) async fn list(&self, claims: &Claims) -> Result<Vec<WebhookInfo>> {
+        self.workspaces.require(claims, WorkspaceRole::Admin).await?;
+
+        let rows: Vec<WebhookRow> = sqlx::query_as(
+            r#"
+            SELECT id, workspace_id, url, events, created_by, created_at
+            FROM webhooks
+            WHERE workspace_id = ? AND deleted_at IS NULL
+            ORDER BY created_at DESC
+            "#,
+        )
+            .bind(&claims.workspace)
+            .fetch_all(self.db)
+            .await?;
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    /// Stops deliveries to the webhook, its delivery log is kept.
+    pub(crate) async fn delete(&self, id: &str, claims: &Claims, client: &ClientInfo) -> Result<()> {
+        self.workspaces.require(claims, WorkspaceRole::Admin).await?;
+        let now = Utc::now().naive_utc();
+        let mut tx = self.db.begin().await?;
+
+        let result = sqlx::query!(
+            "UPDATE webhooks SET deleted_at = ? WHERE id = ? AND workspace_id = ? AND deleted_at IS NULL",
+            now,
+            id,
+            claims.workspace
+        )
+            .execute(&mut *tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::WebhookNotFound(id.to_string()));
+        }
+
+        sqlx::query!(
+            "UPDATE webhook_deliveries SET status = 'failed', error = 'webhook deleted' WHERE webhook_id = ? AND status = 'pending'",
+            id
+        )
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        let entry = AuditEntry::by(claims, AuditAction::WebhookDelete, id)
+            .details(json!({ "workspace": claims.workspace }));
+        self.audit.record(entry, client).await;
+        Ok(())
+    }
+
+    async fn require_webhook(&self, id: &str, claims: &Claims) -> Result<()> {
+        self.workspaces.require(claims, WorkspaceRole::Admin).await?;
+        let count: i64 = sqlx::query_scalar(
+            "SELECT COUNT(1) FROM webhooks WHERE id = ? AND workspace_id = ? AND deleted_at IS NULL",
+        )
+            .bind(id)
+            .bind(&claims.workspace)
+            .fetch_one(self.db)
+            .await?;
+        match count {
+            0 => Err(AppError::WebhookNotFound(id.to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Delivery log of the webhook, newest first.
+    pub(crate) async fn deliveries(
+        &self,
+        id: &str,
+        page: &Page,
+        claims: &Claims,
+    ) -> Result<PageResult<WebhookDelivery>> {
+        self.require_webhook(id, claims).await?;
+
+        let total: i64 = sqlx::query_scalar("SELECT COUNT(1) FROM webhook_deliveries WHERE webhook_id = ?")
+            .bind(id)
+            .fetch_one(self.db)
+            .await?;
+        let rows: Vec<DeliveryRow> = sqlx::query_as(
+            r#"
+            SELECT id, webhook_id, event, payload, status, attempts, response_status, error,
+                   next_attempt_at, created_at, delivered_at
+            FROM webhook_deliveries
+            WHERE webhook_id = ?
+            ORDER BY created_at DESC
+            LIMIT ? OFFSET ?
+            "#,
+        )
+            .bind(id)
+            .bind(page.limit())
+            .bind(page.offset())
+            .fetch_all(self.db)
+            .await?;
+
+        let records = rows.into_iter().map(Into::into).collect();
+        Ok(PageResult::new(page, total as u64, records))
+    }
+
+    /// Sends the payload of a past delivery again, as a new delivery.
+    pub(crate) async fn redeliver(
+        &self,
+        id: &str,
+        delivery_id: &str,
+        claims: &Claims,
+    ) -> Result<WebhookDelivery> {
+        self.require_webhook(id, claims).await?;
+
+        let original: Option<(String, String)> = sqlx::query_as(
+            "SELECT event, payload FROM webhook_deliveries WHERE id = ? AND webhook_id = ?",
+        )
+            .bind(delivery_id)
+            .bind(id)
+            .fetch_optional(self.db)
+            .await?;
+        let (event, payload) = original.ok_or_else(|| AppError::WebhookNotFound(delivery_id.to_string()))?;
+
+        let now = Utc::now().naive_utc();
+        let delivery = DeliveryRow {
+            id: Uuid::new_v4().to_string(),
+            webhook_id: id.to_string(),
+            event,
+            payload,
+            status: DeliveryStatus::Pending.value().to_string(),
+            attempts: 0,
+            response_status: None,
+            error: None,
+            next_attempt_at: now,
+            created_at: now,
+            delivered_at: None,
+        };
+        sqlx::query!(
+            r#"
+            INSERT INTO webhook_deliveries (id, webhook_id, event, payload, status, attempts, next_attempt_at, created_at)
+            VALUES (?, ?, ?, ?, 'pending', 0, ?, ?)
+            "#,
+            delivery.id,
+            delivery.webhook_id,
+            delivery.event,
+            delivery.payload,
+            delivery.next_attempt_at,
+            delivery.created_at
+        )
+            .execute(self.db)
+            .await?;
+
+        self.deliveries.wake();
+        Ok(delivery.into())
+    }
+
+    /// Queues `event` for every webhook of the material's workspace subscribed to it. A failure
+    /// is only logged, webhooks never fail the operation that raised the event.
+    pub(crate) async fn emit(&self, event: WebhookEvent, material: MaterialEvent) {
+        match self.enqueue(event, &material).await {
+            Ok(0) => {}
+            Ok(_) => self.deliveries.wake(),
+            Err(e) => warn!("queue {} of {} failed: {e}", event.value(), material.id),
+        }
+    }
+
+    async fn enqueue(&self, event: WebhookEvent, material: &MaterialEvent) -> Result<usize> {
+        let webhooks: Vec<(String, String)> = sqlx::query_as(
+            "SELECT id, events FROM webhooks WHERE workspace_id = ? AND deleted_at IS NULL",
+        )
+            .bind(&material.workspace_id)
+            .fetch_all(self.db)
+            .await?;
+        let webhooks: Vec<String> = webhooks
+            .into_iter()
+            .filter(|(_, events)| WebhookEvent::split(events).contains(&event))
+            .map(|(id, _)| id)
+            .collect();
+        if webhooks.is_empty() {
+            return Ok(0);
+        }
+
+        let now = Utc::now().naive_utc();
+        let payload = Payload {
+            id: Uuid::new_v4().to_string(),
+            event,
+            created_at: now,
+            data: material,
+        };
+        let payload = serde_json::to_string(&payload).map_err(anyhow::Error::from)?;
+        let event = event.value();
+
+        let mut tx = self.db.begin().await?;
+        for webhook_id in webhooks.iter() {
+            let id = Uuid::new_v4().to_string();
+            sqlx::query!(
+                r#"
+                INSERT INTO webhook_deliveries (id, webhook_id, event, payload, status, attempts, next_attempt_at, created_at)
+                VALUES (?, ?, ?, ?, 'pending', 0, ?, ?)
+                "#,
+                id,
+                webhook_id,
+                event,
+                payload,
+                now,
+                now
+            )
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(webhooks.len())
+    }
+
+    /// Sends the deliveries queued by a command, see [`Deliveries::drain`].
+    pub(crate) async fn drain(&self) {
+        self.deliveries.drain().await
+    }
+}
+
+impl Deliveries {
+    pub(crate) fn new(
+        db: SqlitePool,
+        client: reqwest::Client,
+        max_attempts: u32,
+        backoff_seconds: u64,
+        timeout_seconds: u64,
+        allow_private_networks: bool,
+    ) -> Self {
+        Self {
+            db,
+            client,
+            notify: Arc::new(Notify::new()),
+            max_attempts,
+            backoff_seconds,
+            timeout_seconds,
+            allow_private_networks,
+        }
+    }
+
+    /// Makes the loop look for due deliveries now.
+    fn wake(&self) {
+        self.notify.notify_one();
+    }
+
+    async fn run(self) {
+        info!("webhook deliveries started");
+        loop {
+            if let Err(e) = self.dispatch_due().await {
+                warn!("webhook deliveries failed: {e}");
+            }
+            let _ = timeout(POLL_INTERVAL, self.notify.notified()).await;
+        }
+    }
+
+    /// Sends what is due until nothing is, waiting for the attempts. Those failing are left to
+    /// the server, their next attempt is only due after the backoff.
+    pub(crate) async fn drain(&self) {
+        loop {
+            let attempts = match self.dispatch_due().await {
+                Ok(attempts) if attempts.is_empty() => return,
+                Ok(attempts) => attempts,
+                Err(e) => {
+                    warn!("webhook deliveries failed: {e}");
+                    return;
+                }
+            };
+            for attempt in attempts {
+                let _ = attempt.await;
+            }
+        }
+    }
+
+    /// Starts an attempt for every due delivery claimed.
+    async fn dispatch_due(&self) -> Result<Vec<JoinHandle<()>>> {
+        let now = Utc::now().naive_utc();
+        let due: Vec<DueDelivery> = sqlx::query_as(
+            r#"
+            SELECT d.id, d.event, d.payload, d.attempts, d.next_attempt_at, w.url, w.secret
+            FROM webhook_deliveries d
+                     JOIN webhooks w ON w.id = d.webhook_id
+            WHERE d.status = 'pending' AND d.next_attempt_at <= ? AND w.deleted_at IS NULL
+            ORDER BY d.next_attempt_at
+            LIMIT ?
+            "#,
+        )
+            .bind(now)
+            .bind(BATCH_SIZE)
+            .fetch_all(&self.db)
+            .await?;
+
+        let mut attempts = Vec::new();
+        for delivery in due {
+            if self.claim(&delivery, now).await? {
+                let deliveries = self.clone();
+                attempts.push(spawn(async move { deliveries.attempt(delivery).await }));
+            }
+        }
+        Ok(attempts)
+    }
+
+    /// Pushes `next_attempt_at` past the attempt, so neither this loop nor the one of another
+    /// process, e.g. an import on the command line, picks the delivery up meanwhile. An attempt
+    /// interrupted by a shutdown is retried once the lease runs out.
+    async fn claim(&self, delivery: &DueDelivery, now: NaiveDateTime) -> Result<bool> {
+        let lease = now + Duration::from_secs(self.timeout_seconds * 2 + 60);
+        let result = sqlx::query!(
+            "UPDATE webhook_deliveries SET next_attempt_at = ? WHERE id = ? AND status = 'pending' AND next_attempt_at = ?",
+            lease,
+            delivery.id,
+            delivery.next_attempt_at
+        )
+            .execute(&self.db)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn attempt(&self, delivery: DueDelivery) {
+        let attempts = delivery.attempts + 1;
+        let (response_status, error) = match self.send(&delivery).await {
+            Ok(status) if (200..300).contains(&status) => (Some(status), None),
+            Ok(status) => (Some(status), Some(format!("responded with {status}"))),
+            Err(e) => (None, Some(e.to_string())),
+        };
+
+        let now = Utc::now().naive_utc();
+        let status = match &error {
+            None => DeliveryStatus::Delivered,
+            Some(_) if attempts >= self.max_attempts as i64 => DeliveryStatus::Failed,
+            Some(_) => DeliveryStatus::Pending,
+        };
+        if let Some(error) = &error {
+            warn!("delivery {} to {} failed, attempt {attempts}: {error}", delivery.id, delivery.url);
+        }
+        let next_attempt_at = now + backoff(Duration::from_secs(self.backoff_seconds), attempts as u32);
+        let delivered_at = (status == DeliveryStatus::Delivered).then_some(now);
+        let status = status.value();
+
+        let result = sqlx::query!(
+            r#"
+            UPDATE webhook_deliveries
+            SET status = ?, attempts = ?, response_status = ?, error = ?, next_attempt_at = ?, delivered_at = ?
+            WHERE id = ?
+            "#,
+            status,
+            attempts,
+            response_status,
+            error,
+            next_attempt_at,
+            delivered_at,
+            delivery.id
+        )
+            .execute(&self.db)
+            .await;
+        if let Err(e) = result {
+            warn!("record delivery {} failed: {e}", delivery.id);
+        }
+    }
+
+    /// Posts the payload, returning the response status. The url is checked again, its host may
+    /// resolve differently than when the webhook was created.
+    async fn send(&self, delivery: &DueDelivery) -> Result<u16> {
+        let url = check_user_url(&delivery.url, self.allow_private_networks).await?;
+        let timestamp = Utc::now().timestamp();
+        let response = self
+            .client
+            .post(url)
+            .header(CONTENT_TYPE, "application/json")
+            .header("X-Phi-Event", &delivery.event)
+            .header("X-Phi-Delivery", &delivery.id)
+            .header("X-Phi-Timestamp", timestamp)
+            .header("X-Phi-Signature", signature(&delivery.secret, timestamp, &delivery.payload))
+            .body(delivery.payload.clone())
+            .timeout(Duration::from_secs(self.timeout_seconds))
+            .send()
+            .await?;
+        Ok(response.status().as_u16())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            signature("Jefe", 1700000000, "{}"),
+            format!("v1={}", sign("Jefe", b"1700000000.{}"))
+        );
+    }
+
+    #[test]
+    fn test_backoff() {
+        let base = Duration::from_secs(30);
+        assert_eq!(backoff(base, 1), Duration::from_secs(30));
+        assert_eq!(backoff(base, 2), Duration::from_secs(60));
+        assert_eq!(backoff(base, 4), Duration::from_secs(240));
+        assert_eq!(backoff(base, 40), MAX_BACKOFF);
+    }
+}
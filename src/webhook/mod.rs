@@ -0,0 +1,105 @@
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+
+use crate::material::MaterialType;
+
+pub mod biz;
+pub mod mvc;
+
+/// Material lifecycle events a webhook subscribes to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Enum)]
+pub enum WebhookEvent {
+    /// stored, videos are still being transcoded
+    #[oai(rename = "material.created")]
+    #[serde(rename = "material.created")]
+    MaterialCreated,
+    /// thumbnail and slices are available
+    #[oai(rename = "material.ready")]
+    #[serde(rename = "material.ready")]
+    MaterialReady,
+    #[oai(rename = "material.failed")]
+    #[serde(rename = "material.failed")]
+    MaterialFailed,
+    #[oai(rename = "material.updated")]
+    #[serde(rename = "material.updated")]
+    MaterialUpdated,
+    #[oai(rename = "material.deleted")]
+    #[serde(rename = "material.deleted")]
+    MaterialDeleted,
+}
+
+impl WebhookEvent {
+    pub(crate) const ALL: [WebhookEvent; 5] = [
+        WebhookEvent::MaterialCreated,
+        WebhookEvent::MaterialReady,
+        WebhookEvent::MaterialFailed,
+        WebhookEvent::MaterialUpdated,
+        WebhookEvent::MaterialDeleted,
+    ];
+
+    pub(crate) fn value(&self) -> &'static str {
+        match self {
+            WebhookEvent::MaterialCreated => "material.created",
+            WebhookEvent::MaterialReady => "material.ready",
+            WebhookEvent::MaterialFailed => "material.failed",
+            WebhookEvent::MaterialUpdated => "material.updated",
+            WebhookEvent::MaterialDeleted => "material.deleted",
+        }
+    }
+
+    fn from_value(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|event| event.value() == value)
+    }
+
+    fn join(events: &[WebhookEvent]) -> String {
+        events.iter().map(WebhookEvent::value).collect::<Vec<_>>().join(",")
+    }
+
+    fn split(events: &str) -> Vec<WebhookEvent> {
+        events.split(',').filter_map(WebhookEvent::from_value).collect()
+    }
+}
+
+/// The material an event is about, `data` of the delivered payload.
+#[derive(Serialize, Deserialize, Debug, Clone, Object)]
+pub(crate) struct MaterialEvent {
+    pub(crate) id: String,
+    pub(crate) workspace_id: String,
+    /// absent for deleted materials
+    pub(crate) r#type: Option<MaterialType>,
+    pub(crate) name: Option<String>,
+    /// why processing failed, only for `material.failed`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[oai(skip_serializing_if_is_none)]
+    pub(crate) error: Option<String>,
+}
+
+impl MaterialEvent {
+    pub(crate) fn deleted(id: impl Into<String>, workspace_id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            workspace_id: workspace_id.into(),
+            r#type: None,
+            name: None,
+            error: None,
+        }
+    }
+
+    pub(crate) fn with_error(mut self, error: impl ToString) -> Self {
+        self.error = Some(error.to_string());
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_events_round_trip() {
+        let events = vec![WebhookEvent::MaterialReady, WebhookEvent::MaterialDeleted];
+        assert_eq!(WebhookEvent::join(&events), "material.ready,material.deleted");
+        assert_eq!(WebhookEvent::split(&WebhookEvent::join(&events)), events);
+        assert_eq!(WebhookEvent::split("material.ready,unknown"), vec![WebhookEvent::MaterialReady]);
+    }
+}
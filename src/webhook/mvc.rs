@@ -0,0 +1,81 @@
+use crate::{
+    auth::apikey::JwtAuth,
+    common::{Page, PageResult, Response, Result},
+    metrics::metered,
+    util::poem::ClientInfo,
+    webhook::biz::{NewWebhook, WebhookDelivery, WebhookInfo, WebhookRequest, WebhookService},
+};
+use ioc::{mvc, Bean, OpenApi};
+use poem_openapi::{
+    param::{Path, Query},
+    payload::Json,
+};
+
+#[derive(Bean)]
+pub(crate) struct WebhookMvc {
+    #[inject(bean)]
+    webhooks: &'static WebhookService,
+}
+
+#[mvc]
+#[OpenApi(prefix_path = "/api/v1")]
+impl WebhookMvc {
+    /// Workspace admins: subscribes a url to material events of the active workspace. Payloads
+    /// are signed in `X-Phi-Signature` as `v1=` and the hex HMAC-SHA256 of
+    /// `{X-Phi-Timestamp}.{body}` keyed with the secret.
+    #[oai(path = "/webhooks", method = "post", transform = "metered")]
+    async fn create(
+        &self,
+        request: Json<WebhookRequest>,
+        auth: JwtAuth,
+        client: ClientInfo,
+    ) -> Result<Response<NewWebhook>> {
+        let webhook = self.webhooks.create(request.0, &auth, &client).await?;
+        Ok(Response::ok(webhook))
+    }
+
+    #[oai(path = "/webhooks", method = "get", transform = "metered")]
+    async fn list(&self, auth: JwtAuth) -> Result<Response<Vec<WebhookInfo>>> {
+        let webhooks = self.webhooks.list(&auth).await?;
+        Ok(Response::ok(webhooks))
+    }
+
+    #[oai(path = "/webhooks/:id", method = "delete", transform = "metered")]
+    async fn delete(&self, id: Path<String>, auth: JwtAuth, client: ClientInfo) -> Result<Response<String>> {
+        self.webhooks.delete(&id, &auth, &client).await?;
+        Ok(Response::ok("ok".to_string()))
+    }
+
+    /// Delivery log of a webhook, newest first.
+    #[oai(path = "/webhooks/:id/deliveries", method = "get", transform = "metered")]
+    async fn deliveries(
+        &self,
+        id: Path<String>,
+        page: Query<Option<u32>>,
+        size: Query<Option<u32>>,
+        auth: JwtAuth,
+    ) -> Result<Response<PageResult<WebhookDelivery>>> {
+        let page = Page {
+            page: page.0.unwrap_or(1).max(1),
+            size: size.0.unwrap_or(20).clamp(1, 200),
+        };
+        let deliveries = self.webhooks.deliveries(&id, &page, &auth).await?;
+        Ok(Response::ok(deliveries))
+    }
+
+    /// Sends the payload of a delivery again, whatever its outcome was.
+    #[oai(
+        path = "/webhooks/:id/deliveries/:delivery_id/redeliver",
+        method = "post",
+        transform = "metered"
+    )]
+    async fn redeliver(
+        &self,
+        id: Path<String>,
+        delivery_id: Path<String>,
+        auth: JwtAuth,
+    ) -> Result<Response<WebhookDelivery>> {
+        let delivery = self.webhooks.redeliver(&id, &delivery_id, &auth).await?;
+        Ok(Response::ok(delivery))
+    }
+}
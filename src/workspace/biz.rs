@@ -0,0 +1,305 @@
+use crate::{
+    auth::{jwt::Claims, user::UserService},
+    common::{AppError, Result},
+    db::Db,
+    workspace::WorkspaceRole,
+};
+use chrono::{NaiveDateTime, Utc};
+use ioc::Bean;
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use uuid::Uuid;
+
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Object)]
+pub(crate) struct Workspace {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) created_by: String,
+    pub(crate) created_at: NaiveDateTime,
+}
+
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Object)]
+pub(crate) struct Membership {
+    workspace_id: String,
+    name: String,
+    role: String,
+    created_at: NaiveDateTime,
+}
+
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Object)]
+pub(crate) struct Member {
+    user_id: String,
+    name: String,
+    role: String,
+    created_at: NaiveDateTime,
+}
+
+#[derive(Bean)]
+pub(crate) struct WorkspaceService {
+    #[inject(bean = Db)]
+    db: &'static SqlitePool,
+    #[inject(bean)]
+    users: &'static UserService,
+}
+
+impl WorkspaceService {
+    /// Creates the personal workspace of a user; the workspace shares the user id.
+    pub(crate) async fn create_personal(&self, user_id: &str, name: &str) -> Result<()> {
+        let now = Utc::now().naive_utc();
+        let owner = WorkspaceRole::Owner.value();
+
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query!(
+            r#"
+            INSERT OR IGNORE INTO workspaces (id, name, created_by, created_at)
+            VALUES (?, ?, ?, ?)
+            "#,
+            user_id,
+            name,
+            user_id,
+            now
+        )
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT OR IGNORE INTO workspace_members (workspace_id, user_id, role, created_at)
+            VALUES (?, ?, ?, ?)
+            "#,
+            user_id,
+            user_id,
+            owner,
+            now
+        )
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn create(&self, name: &str, claims: &Claims) -> Result<Workspace> {
+        let workspace = Workspace {
+            id: Uuid::new_v4().as_simple().to_string(),
+            name: name.to_string(),
+            created_by: claims.id.clone(),
+            created_at: Utc::now().naive_utc(),
+        };
+        let owner = WorkspaceRole::Owner.value();
+
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO workspaces (id, name, created_by, created_at)
+            VALUES (?, ?, ?, ?)
+            "#,
+            workspace.id,
+            workspace.name,
+            workspace.created_by,
+            workspace.created_at
+        )
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO workspace_members (workspace_id, user_id, role, created_at)
+            VALUES (?, ?, ?, ?)
+            "#,
+            workspace.id,
+            workspace.created_by,
+            owner,
+            workspace.created_at
+        )
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(workspace)
+    }
+
+    pub(crate) async fn role(&self, workspace: &str, user_id: &str) -> Result<Option<WorkspaceRole>> {
+        let role: Option<String> = sqlx::query_scalar(
+            "SELECT role FROM workspace_members WHERE workspace_id = ? AND user_id = ?",
+        )
+            .bind(workspace)
+            .bind(user_id)
+            .fetch_optional(self.db)
+            .await?;
+
+        Ok(role.as_deref().and_then(WorkspaceRole::from_value))
+    }
+
+    /// Checks that the user holds at least `required` in `workspace`.
+    pub(crate) async fn require_role(
+        &self,
+        workspace: &str,
+        user_id: &str,
+        required: WorkspaceRole,
+    ) -> Result<WorkspaceRole> {
+        match self.role(workspace, user_id).await? {
+            Some(role) if role.allows(required) => Ok(role),
+            _ => Err(AppError::WorkspaceForbidden(workspace.to_string())),
+        }
+    }
+
+    /// Checks that the caller holds at least `required` in the active workspace of the token.
+    pub(crate) async fn require(&self, claims: &Claims, required: WorkspaceRole) -> Result<WorkspaceRole> {
+        self.require_role(&claims.workspace, &claims.id, required).await
+    }
+
+    /// Picks the workspace a token is issued for: the requested one if the user is a member,
+    /// otherwise the personal workspace, otherwise the oldest membership.
+    pub(crate) async fn resolve(&self, user_id: &str, requested: Option<&str>) -> Result<String> {
+        if let Some(workspace) = requested {
+            self.require_role(workspace, user_id, WorkspaceRole::Viewer).await?;
+            return Ok(workspace.to_string());
+        }
+
+        if self.role(user_id, user_id).await?.is_some() {
+            return Ok(user_id.to_string());
+        }
+
+        let workspace: Option<String> = sqlx::query_scalar(
+            "SELECT workspace_id FROM workspace_members WHERE user_id = ? ORDER BY created_at LIMIT 1",
+        )
+            .bind(user_id)
+            .fetch_optional(self.db)
+            .await?;
+
+        workspace.ok_or_else(|| AppError::WorkspaceForbidden(user_id.to_string()))
+    }
+
+    pub(crate) async fn list(&self, user_id: &str) -> Result<Vec<Membership>> {
+        let memberships = sqlx::query_as(
+            r#"
+            SELECT m.workspace_id, w.name, m.role, m.created_at
+            FROM workspace_members m JOIN workspaces w ON w.id = m.workspace_id
+            WHERE m.user_id = ?
+            ORDER BY m.created_at
+            "#,
+        )
+            .bind(user_id)
+            .fetch_all(self.db)
+            .await?;
+
+        Ok(memberships)
+    }
+
+    pub(crate) async fn members(&self, workspace: &str, claims: &Claims) -> Result<Vec<Member>> {
+        self.require_role(workspace, &claims.id, WorkspaceRole::Viewer).await?;
+
+        let members = sqlx::query_as(
+            r#"
+            SELECT m.user_id, u.name, m.role, m.created_at
+            FROM workspace_members m JOIN users u ON u.id = m.user_id
+            WHERE m.workspace_id = ?
+            ORDER BY m.created_at
+            "#,
+        )
+            .bind(workspace)
+            .fetch_all(self.db)
+            .await?;
+
+        Ok(members)
+    }
+
+    pub(crate) async fn set_member(
+        &self,
+        workspace: &str,
+        user_id: &str,
+        role: WorkspaceRole,
+        claims: &Claims,
+    ) -> Result<()> {
+        let caller = self.require_role(workspace, &claims.id, WorkspaceRole::Admin).await?;
+        let current = self.role(workspace, user_id).await?;
+        // only owners may hand out or take away ownership
+        if (role == WorkspaceRole::Owner || current == Some(WorkspaceRole::Owner))
+            && caller != WorkspaceRole::Owner
+        {
+            return Err(AppError::WorkspaceForbidden(workspace.to_string()));
+        }
+
+        self.grant(workspace, user_id, role).await
+    }
+
+    /// Sets the role of the user without checking the caller, for the admin cli and checked callers.
+    pub(crate) async fn grant(&self, workspace: &str, user_id: &str, role: WorkspaceRole) -> Result<()> {
+        if !self.users.exists_by_id(user_id).await? {
+            return Err(AppError::UserNotFound(user_id.to_string()));
+        }
+
+        let demoted = role != WorkspaceRole::Owner
+            && self.role(workspace, user_id).await? == Some(WorkspaceRole::Owner);
+        let now = Utc::now().naive_utc();
+        let role = role.value();
+        let mut tx = self.db.begin().await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO workspace_members (workspace_id, user_id, role, created_at)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT (workspace_id, user_id) DO UPDATE SET role = excluded.role
+            "#,
+            workspace,
+            user_id,
+            role,
+            now
+        )
+            .execute(&mut *tx)
+            .await?;
+
+        if demoted {
+            Self::check_owned(&mut tx, workspace).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub(crate) async fn remove_member(&self, workspace: &str, user_id: &str, claims: &Claims) -> Result<()> {
+        let caller = self.require_role(workspace, &claims.id, WorkspaceRole::Admin).await?;
+        let owner = self.role(workspace, user_id).await? == Some(WorkspaceRole::Owner);
+        if owner && caller != WorkspaceRole::Owner {
+            return Err(AppError::WorkspaceForbidden(workspace.to_string()));
+        }
+
+        let mut tx = self.db.begin().await?;
+        sqlx::query!(
+            r#"
+            DELETE FROM workspace_members WHERE workspace_id = ? AND user_id = ?
+            "#,
+            workspace,
+            user_id
+        )
+            .execute(&mut *tx)
+            .await?;
+
+        if owner {
+            Self::check_owned(&mut tx, workspace).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Fails a change taking an owner away when no owner is left, including owners leaving
+    /// themselves. Counted after the write of the change, in its transaction, so two owners
+    /// demoting each other at once can't both pass.
+    async fn check_owned(tx: &mut Transaction<'_, Sqlite>, workspace: &str) -> Result<()> {
+        let owners: i64 = sqlx::query_scalar(
+            "SELECT COUNT(1) FROM workspace_members WHERE workspace_id = ? AND role = ?",
+        )
+            .bind(workspace)
+            .bind(WorkspaceRole::Owner.value())
+            .fetch_one(&mut **tx)
+            .await?;
+        if owners == 0 {
+            return Err(AppError::LastOwner(workspace.to_string()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+use poem_openapi::Enum;
+use serde::{Deserialize, Serialize};
+
+pub mod biz;
+pub mod mvc;
+
+/// Role of a user inside a workspace, ordered from least to most privileged.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Enum)]
+#[serde(rename_all = "lowercase")]
+#[oai(rename_all = "lowercase")]
+pub enum WorkspaceRole {
+    Viewer,
+    Editor,
+    Admin,
+    Owner,
+}
+
+impl WorkspaceRole {
+    pub(crate) fn value(&self) -> &'static str {
+        match self {
+            WorkspaceRole::Viewer => "viewer",
+            WorkspaceRole::Editor => "editor",
+            WorkspaceRole::Admin => "admin",
+            WorkspaceRole::Owner => "owner",
+        }
+    }
+
+    pub(crate) fn from_value(value: &str) -> Option<Self> {
+        match value {
+            "viewer" => Some(WorkspaceRole::Viewer),
+            "editor" => Some(WorkspaceRole::Editor),
+            "admin" => Some(WorkspaceRole::Admin),
+            "owner" => Some(WorkspaceRole::Owner),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn allows(&self, required: WorkspaceRole) -> bool {
+        *self >= required
+    }
+}
@@ -0,0 +1,69 @@
+use crate::{
+    auth::apikey::JwtAuth,
+    common::{Response, Result},
+    metrics::metered,
+    workspace::{
+        biz::{Member, Membership, Workspace, WorkspaceService},
+        WorkspaceRole,
+    },
+};
+use ioc::{mvc, Bean, OpenApi};
+use poem_openapi::{param::Path, payload::Json, Object};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub(crate) struct NewWorkspaceRequest {
+    pub(crate) name: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub(crate) struct MemberRequest {
+    pub(crate) role: WorkspaceRole,
+}
+
+#[derive(Bean)]
+pub(crate) struct WorkspaceMvc {
+    #[inject(bean)]
+    workspaces: &'static WorkspaceService,
+}
+
+#[mvc]
+#[OpenApi(prefix_path = "/api/v1")]
+impl WorkspaceMvc {
+    /// Workspaces the caller belongs to
+    #[oai(path = "/workspaces", method = "get", transform = "metered")]
+    async fn list(&self, auth: JwtAuth) -> Result<Response<Vec<Membership>>> {
+        let memberships = self.workspaces.list(&auth.id).await?;
+        Ok(Response::ok(memberships))
+    }
+
+    #[oai(path = "/workspaces", method = "post", transform = "metered")]
+    async fn create(&self, request: Json<NewWorkspaceRequest>, auth: JwtAuth) -> Result<Response<Workspace>> {
+        let workspace = self.workspaces.create(&request.name, &auth).await?;
+        Ok(Response::ok(workspace))
+    }
+
+    #[oai(path = "/workspaces/:id/members", method = "get", transform = "metered")]
+    async fn members(&self, id: Path<String>, auth: JwtAuth) -> Result<Response<Vec<Member>>> {
+        let members = self.workspaces.members(&id, &auth).await?;
+        Ok(Response::ok(members))
+    }
+
+    #[oai(path = "/workspaces/:id/members/:user_id", method = "put", transform = "metered")]
+    async fn set_member(
+        &self,
+        id: Path<String>,
+        user_id: Path<String>,
+        request: Json<MemberRequest>,
+        auth: JwtAuth,
+    ) -> Result<Response<String>> {
+        self.workspaces.set_member(&id, &user_id, request.role, &auth).await?;
+        Ok(Response::ok("ok".to_string()))
+    }
+
+    #[oai(path = "/workspaces/:id/members/:user_id", method = "delete", transform = "metered")]
+    async fn remove_member(&self, id: Path<String>, user_id: Path<String>, auth: JwtAuth) -> Result<Response<String>> {
+        self.workspaces.remove_member(&id, &user_id, &auth).await?;
+        Ok(Response::ok("ok".to_string()))
+    }
+}